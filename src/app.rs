@@ -1,11 +1,25 @@
+use crate::background::{Background, EnvironmentMapPicker};
 use crate::camera::Camera;
-use crate::raytracer::{Raytracer, RayPath};
+use crate::gizmo::{self, GizmoMode};
+use crate::gpu_raytracer::GpuRaytracer;
+use crate::math::Transform;
+use crate::obj::ObjImporter;
+use crate::raytracer::{Accumulator, Backend, Raytracer, RayPath, RenderMode};
+#[cfg(target_arch = "wasm32")]
+use crate::render_worker::WorkerRenderer;
 use crate::renderer_3d::Renderer3D;
-use crate::scene::Scene;
-use crate::ui::{UiState, render_controls};
+use crate::primitives::LightType;
+use crate::scene::{ObjectId, Scene};
+use crate::scene_file::{self, SceneFile, SceneFileLoader};
+use crate::ui::{SceneFileRequest, Selection, UiState, render_controls};
 use eframe::egui;
 use glam::{Vec3, Quat};
 
+/// Progressive rendering stops adding samples once this many have been accumulated.
+const MAX_PROGRESSIVE_SAMPLES: u32 = 4096;
+
+/// How close (in points) a click in the 3D view has to be to a light or the render camera to select it.
+const PICK_RADIUS: f32 = 10.0;
 
 pub struct RaytracerApp {
     scene: Scene,
@@ -17,6 +31,26 @@ pub struct RaytracerApp {
     
     raytraced_texture: Option<egui::TextureHandle>,
     ray_paths: Vec<RayPath>,
+
+    // Progressive Pathtracing
+    accumulator: Accumulator,
+    accumulated_camera: Camera, // Camera the accumulated samples were traced with
+    scene_revision: u64,        // Counts scene edits that were not rendered right away
+    accumulated_revision: u64,  // Scene revision the accumulated samples were traced with
+
+    // Compute-shader backend, None if the adapter can't run it
+    gpu_raytracer: Option<GpuRaytracer>,
+
+    scene_file_loader: SceneFileLoader,
+    obj_importer: ObjImporter,
+    environment_map_picker: EnvironmentMapPicker,
+
+    // On the web, rendering runs in a Web Worker so the UI stays responsive.
+    // None if the worker could not be started, in which case we render on the main thread.
+    #[cfg(target_arch = "wasm32")]
+    render_worker: Option<WorkerRenderer>,
+    #[cfg(target_arch = "wasm32")]
+    display_pixels: Vec<u8>, // RGBA image the worker tiles are drawn into
     
     // 3D View Texture
     view_texture: Option<wgpu::Texture>,
@@ -35,6 +69,10 @@ impl RaytracerApp {
 
         let renderer_3d = Renderer3D::new(device, format);
 
+        let gpu_raytracer = GpuRaytracer::is_supported(&wgpu_render_state.adapter, device).then(|| {
+            GpuRaytracer::new(wgpu_render_state.device.clone(), wgpu_render_state.queue.clone())
+        });
+
         let mut camera = Camera::default();
         camera.transform.position = Vec3::new(0.0, 2.5, 6.0);
         camera.look_at(Vec3::ZERO);
@@ -48,12 +86,14 @@ impl RaytracerApp {
         view_camera.look_at(Vec3::ZERO);
 
         let raytracer = Raytracer::default();
+        let accumulator = Accumulator::new(raytracer.width, raytracer.height);
         let scene = Scene::default();
 
         let mut ui_state = UiState::default();
         let (yaw, pitch, _) = camera.transform.rotation.to_euler(glam::EulerRot::YXZ);
         ui_state.camera_yaw = yaw.to_degrees();
         ui_state.camera_pitch = pitch.to_degrees();
+        ui_state.gpu_available = gpu_raytracer.is_some();
 
         let mut app = Self {
             scene,
@@ -64,6 +104,20 @@ impl RaytracerApp {
             ui_state,
             raytraced_texture: None,
             ray_paths: Vec::new(),
+            accumulator,
+            accumulated_camera: camera,
+            scene_revision: 0,
+            accumulated_revision: 0,
+            gpu_raytracer,
+            scene_file_loader: SceneFileLoader::default(),
+            obj_importer: ObjImporter::default(),
+            environment_map_picker: EnvironmentMapPicker::default(),
+            #[cfg(target_arch = "wasm32")]
+            render_worker: WorkerRenderer::new()
+                .map_err(|err| log::warn!("Render worker unavailable, rendering on the main thread: {:?}", err))
+                .ok(),
+            #[cfg(target_arch = "wasm32")]
+            display_pixels: Vec::new(),
             view_texture: None,
             view_texture_view: None,
             view_texture_id: None,
@@ -76,25 +130,299 @@ impl RaytracerApp {
         app
     }
 
+    fn progressive_active(&self) -> bool {
+        self.ui_state.progressive && self.raytracer.mode == RenderMode::Pathtracing
+    }
+
     fn update_raytrace(&mut self, ctx: egui::Context) {
-        let pixels = self.raytracer.render(&self.scene, &self.camera);
-        
+        self.scene.rebuild_bvh();
+
+        // Something changed, so previously accumulated samples are stale
+        self.accumulator.ensure_size(self.raytracer.width, self.raytracer.height);
+        self.accumulator.reset();
+        self.accumulated_camera = self.camera;
+        self.accumulated_revision = self.scene_revision;
+
+        if !self.start_async_pass(true) {
+            // Without progressive rendering the image is just this first pass
+            self.raytracer.accumulate(&self.scene, &self.camera, &mut self.accumulator);
+            self.show_accumulated_image(&ctx);
+        }
+
+        if self.ui_state.show_rays {
+            self.ray_paths = self.raytracer.trace_paths(&self.scene, &self.camera, self.ui_state.ray_count);
+        } else {
+            self.ray_paths.clear();
+        }
+    }
+
+    /// Adds one more pass of samples to the progressive image.
+    fn continue_accumulation(&mut self, ctx: egui::Context) {
+        if !self.progressive_active() || self.accumulator.sample_count >= MAX_PROGRESSIVE_SAMPLES {
+            return;
+        }
+
+        // Camera and gizmo edits only trigger a render with Auto Update on, but must always
+        // restart accumulation
+        if self.camera != self.accumulated_camera || self.scene_revision != self.accumulated_revision {
+            self.update_raytrace(ctx.clone());
+        } else if !self.start_async_pass(false) {
+            self.raytracer.accumulate(&self.scene, &self.camera, &mut self.accumulator);
+            self.show_accumulated_image(&ctx);
+        }
+        ctx.request_repaint();
+    }
+
+    /// Hands the next pass to the GPU or the render worker, abandoning the running one if
+    /// `restart` is set and otherwise waiting for it to finish. Returns false if the caller
+    /// has to render on this thread.
+    fn start_async_pass(&mut self, restart: bool) -> bool {
+        let use_gpu = self.ui_state.active_backend(&self.raytracer, &self.scene).0 == Backend::Gpu;
+        if use_gpu && self.gpu_raytracer.is_some() {
+            self.cancel_worker_pass();
+            if let Some(gpu) = &mut self.gpu_raytracer {
+                if restart || !gpu.is_busy() {
+                    gpu.start(&self.scene, &self.camera, &self.raytracer, self.accumulator.sample_count);
+                }
+                return true;
+            }
+        }
+
+        // A pass from a previously selected backend must not land in this image
+        if let Some(gpu) = &mut self.gpu_raytracer {
+            gpu.cancel();
+        }
+        self.start_worker_pass(restart)
+    }
+
+    /// Worker half of `start_async_pass`. Returns false if there is no worker.
+    #[cfg(target_arch = "wasm32")]
+    fn start_worker_pass(&mut self, restart: bool) -> bool {
+        let Some(worker) = &mut self.render_worker else {
+            return false;
+        };
+
+        if restart || !worker.is_busy() {
+            self.display_pixels.resize((self.raytracer.width * self.raytracer.height * 4) as usize, 0);
+            worker.start(&self.scene, &self.camera, &self.raytracer, self.accumulator.sample_count, restart);
+        }
+        true
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_worker_pass(&mut self, _restart: bool) -> bool {
+        false
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn cancel_worker_pass(&mut self) {
+        if let Some(worker) = &mut self.render_worker {
+            worker.cancel();
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn cancel_worker_pass(&mut self) {}
+
+    /// Shows the pass the GPU has finished, if it has.
+    fn poll_gpu_raytracer(&mut self, ctx: &egui::Context) {
+        let Some(gpu) = &mut self.gpu_raytracer else {
+            return;
+        };
+        if !gpu.is_busy() {
+            return;
+        }
+
+        // Keep polling even without user input
+        ctx.request_repaint();
+        if gpu.poll(&mut self.accumulator) {
+            self.show_accumulated_image(ctx);
+        }
+    }
+
+    /// Shows the tiles the render worker has finished since the last frame.
+    #[cfg(target_arch = "wasm32")]
+    fn poll_render_worker(&mut self, ctx: &egui::Context) {
+        let Some(worker) = &mut self.render_worker else {
+            return;
+        };
+
+        if worker.has_failed() {
+            log::warn!("Render worker failed, rendering on the main thread instead");
+            self.render_worker = None;
+            self.update_raytrace(ctx.clone());
+            return;
+        }
+
+        if worker.is_busy() {
+            // Keep polling even without user input
+            ctx.request_repaint();
+        }
+        if worker.poll(&mut self.accumulator, self.raytracer.tone_mapping, &mut self.display_pixels) {
+            let pixels = std::mem::take(&mut self.display_pixels);
+            self.upload_raytraced_image(ctx, &pixels);
+            self.display_pixels = pixels;
+        }
+    }
+
+    /// Carries out the load or save picked in the controls and applies a loaded file.
+    fn handle_scene_file(&mut self, ctx: &egui::Context) {
+        match self.ui_state.scene_file_request.take() {
+            Some(SceneFileRequest::Load) => self.scene_file_loader.open(),
+            Some(SceneFileRequest::ImportObj) => self.obj_importer.open(),
+            Some(SceneFileRequest::LoadEnvironmentMap) => self.environment_map_picker.open(),
+            Some(SceneFileRequest::Save(format)) => {
+                let file = SceneFile::new(&self.scene, &self.camera, &self.view_camera, &self.raytracer);
+                match scene_file::save(&file, format) {
+                    Ok(Some(name)) => self.ui_state.scene_file_status = Some(Ok(format!("Saved {}", name))),
+                    Ok(None) => {} // Cancelled
+                    Err(err) => self.ui_state.scene_file_status = Some(Err(err.to_string())),
+                }
+            }
+            None => {}
+        }
+
+        match self.scene_file_loader.poll() {
+            Some(Ok((name, file))) => {
+                self.scene = file.scene;
+                self.camera = file.camera;
+                self.view_camera = file.view_camera;
+                self.raytracer = file.raytracer;
+                if !self.ui_state.gpu_available {
+                    self.raytracer.backend = Backend::Cpu;
+                }
+
+                let (yaw, pitch, _) = self.camera.transform.rotation.to_euler(glam::EulerRot::YXZ);
+                self.ui_state.camera_yaw = yaw.to_degrees();
+                self.ui_state.camera_pitch = pitch.to_degrees();
+                self.ui_state.scene_file_status = Some(Ok(format!("Loaded {}", name)));
+                self.ui_state.selected = None;
+                self.update_raytrace(ctx.clone());
+            }
+            Some(Err(err)) => self.ui_state.scene_file_status = Some(Err(err.to_string())),
+            None => {}
+        }
+
+        match self.obj_importer.poll() {
+            Some(Ok((name, meshes))) => {
+                let triangles: usize = meshes.iter().map(|mesh| mesh.triangles.len()).sum();
+                self.ui_state.scene_file_status = Some(Ok(format!("Imported {} ({} triangles)", name, triangles)));
+                self.scene.meshes.extend(meshes);
+                self.update_raytrace(ctx.clone());
+            }
+            Some(Err(err)) => self.ui_state.scene_file_status = Some(Err(err.to_string())),
+            None => {}
+        }
+
+        match self.environment_map_picker.poll() {
+            Some(Ok(map)) => {
+                let (width, height) = map.size();
+                self.ui_state.scene_file_status = Some(Ok(format!("Loaded {} ({}x{})", map.name, width, height)));
+                self.scene.background = Background::EnvironmentMap(map);
+                self.update_raytrace(ctx.clone());
+            }
+            Some(Err(err)) => self.ui_state.scene_file_status = Some(Err(err.to_string())),
+            None => {}
+        }
+    }
+
+    /// Selects the object under `pos` in `rect`, an image of what `camera` sees.
+    fn pick_object(&mut self, camera: &Camera, rect: egui::Rect, pos: egui::Pos2) {
+        let u = (pos.x - rect.left()) / rect.width();
+        let v = 1.0 - (pos.y - rect.top()) / rect.height(); // Flip Y, like `Raytracer::sample_pixel`
+        self.ui_state.selected = self.scene.pick(&camera.get_ray(u, v)).map(Selection::Object);
+    }
+
+    /// Like `pick_object` for the 3D view, where lights and the render camera can be picked too.
+    fn pick_in_view(&mut self, rect: egui::Rect, pos: egui::Pos2) {
+        let near = |point| gizmo::project(&self.view_camera, rect, point).is_some_and(|p| p.distance(pos) < PICK_RADIUS);
+        if near(self.camera.transform.position) {
+            self.ui_state.selected = Some(Selection::Camera);
+        } else if let Some(i) = self.scene.lights.iter().position(|light| near(light.position)) {
+            self.ui_state.selected = Some(Selection::Light(i));
+        } else {
+            let view_camera = self.view_camera;
+            self.pick_object(&view_camera, rect, pos);
+        }
+    }
+
+    /// Pose of the selection for the gizmo, with the gizmo modes that apply to it.
+    fn selection_transform(&self) -> Option<(Transform, &'static [GizmoMode])> {
+        const ALL: &[GizmoMode] = &[GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+        const MOVE_AND_ROTATE: &[GizmoMode] = &[GizmoMode::Translate, GizmoMode::Rotate];
+
+        match self.ui_state.selected? {
+            Selection::Object(id) => {
+                let transform = match id {
+                    ObjectId::Sphere(i) => self.scene.spheres.get(i)?.transform,
+                    ObjectId::Cube(i) => self.scene.cubes.get(i)?.transform,
+                    ObjectId::Plane(i) => self.scene.planes.get(i)?.transform,
+                    ObjectId::Mesh(i) => self.scene.meshes.get(i)?.transform,
+                };
+                Some((transform, ALL))
+            }
+            Selection::Light(i) => {
+                let light = self.scene.lights.get(i)?;
+                let transform = Transform { position: light.position, ..Default::default() };
+                match light.light_type {
+                    LightType::Point | LightType::Sphere => Some((transform, &[GizmoMode::Translate])),
+                    // Rotating turns the direction, which plays the part of the transform's forward axis
+                    LightType::Directional | LightType::Spot | LightType::Rectangle | LightType::Disk => {
+                        let rotation = glam::Quat::from_rotation_arc(Vec3::NEG_Z, light.direction.normalize());
+                        Some((Transform { rotation, ..transform }, MOVE_AND_ROTATE))
+                    }
+                }
+            }
+            Selection::Camera => Some((self.camera.transform, MOVE_AND_ROTATE)),
+        }
+    }
+
+    /// Writes a transform edited with the gizmo back to the selection.
+    fn apply_selection_transform(&mut self, edited: Transform) {
+        match self.ui_state.selected {
+            Some(Selection::Object(id)) => {
+                if let Some(transform) = self.scene.transform_mut(id) {
+                    *transform = edited;
+                }
+            }
+            Some(Selection::Light(i)) => {
+                if let Some(light) = self.scene.lights.get_mut(i) {
+                    light.position = edited.position;
+                    if matches!(light.light_type, LightType::Directional | LightType::Spot | LightType::Rectangle | LightType::Disk) {
+                        light.direction = edited.forward();
+                    }
+                }
+                // A physical sky follows the sun around
+                self.scene.update_sun();
+            }
+            Some(Selection::Camera) => {
+                self.camera.transform = edited;
+                let (yaw, pitch, _) = edited.rotation.to_euler(glam::EulerRot::YXZ);
+                self.ui_state.camera_yaw = yaw.to_degrees();
+                self.ui_state.camera_pitch = pitch.to_degrees();
+            }
+            None => {}
+        }
+    }
+
+    /// Tone maps the accumulated samples and shows them.
+    fn show_accumulated_image(&mut self, ctx: &egui::Context) {
+        let pixels = self.accumulator.to_rgba(self.raytracer.tone_mapping);
+        self.upload_raytraced_image(ctx, &pixels);
+    }
+
+    fn upload_raytraced_image(&mut self, ctx: &egui::Context, pixels: &[u8]) {
         let image = egui::ColorImage::from_rgba_unmultiplied(
             [self.raytracer.width as usize, self.raytracer.height as usize],
-            &pixels,
+            pixels,
         );
 
+        // Nearest, so the pixels on screen are the ones the reconstruction filter made
         self.raytraced_texture = Some(ctx.load_texture(
             "raytraced_output",
             image,
             egui::TextureOptions::NEAREST,
         ));
-
-        if self.ui_state.show_rays {
-            self.ray_paths = self.raytracer.trace_paths(&self.scene, &self.camera, self.ui_state.ray_count);
-        } else {
-            self.ray_paths.clear();
-        }
     }
 
     fn update_3d_view(&mut self, frame: &mut eframe::Frame, width: u32, height: u32) {
@@ -162,6 +490,10 @@ impl RaytracerApp {
                 &self.camera,
                 &self.ray_paths,
                 &self.view_camera,
+                match self.ui_state.selected {
+                    Some(Selection::Object(id)) => Some(id),
+                    _ => None,
+                },
             );
         }
     }
@@ -173,6 +505,7 @@ impl eframe::App for RaytracerApp {
         crate::apply_custom_style(ctx);
         
         let mut trigger_render = false;
+        let tone_mapping = self.raytracer.tone_mapping;
 
         egui::SidePanel::left("controls_panel").show(ctx, |ui| {
             render_controls(
@@ -186,6 +519,8 @@ impl eframe::App for RaytracerApp {
             
             ui.separator();
             ui.label("3D View Controls:");
+            ui.label("Left-click to select an object, light or the camera");
+            ui.label("Drag the gizmo handles to move, rotate or scale it");
             ui.label("Right-click + Drag to rotate");
             ui.label("Scroll to zoom");
         });
@@ -201,11 +536,31 @@ impl eframe::App for RaytracerApp {
                 
                 let response = if let Some(texture_id) = self.view_texture_id {
                     ui_left.add(egui::Image::new(egui::load::SizedTexture::new(texture_id, view_size))
-                        .sense(egui::Sense::drag()))
+                        .sense(egui::Sense::click_and_drag()))
                 } else {
-                    ui_left.allocate_response(view_size, egui::Sense::drag())
+                    ui_left.allocate_response(view_size, egui::Sense::click_and_drag())
                 };
 
+                if let Some((transform, modes)) = self.selection_transform() {
+                    let gizmo = &mut self.ui_state.gizmo;
+                    if let Some(edited) = gizmo.interact(ui_left, &response, &self.view_camera, &transform, modes) {
+                        self.apply_selection_transform(edited);
+                        if self.ui_state.auto_update {
+                            trigger_render = true;
+                        } else {
+                            // Not rendered yet, but picking should already see the new placement
+                            self.scene.rebuild_bvh();
+                            self.scene_revision += 1;
+                        }
+                    }
+                }
+
+                if response.clicked() && !self.ui_state.gizmo.is_active() {
+                    if let Some(pos) = response.interact_pointer_pos() {
+                        self.pick_in_view(response.rect, pos);
+                    }
+                }
+
                 // Handle 3D view input
                 if response.dragged_by(egui::PointerButton::Secondary) {
                     let delta = response.drag_delta();
@@ -235,12 +590,43 @@ impl eframe::App for RaytracerApp {
                     let image_height = available_height * 0.6; // 60% for image
                     let image_size = egui::vec2(ui.available_width(), image_height);
                     
+                    let (backend, fallback_reason) = self.ui_state.active_backend(&self.raytracer, &self.scene);
                     if let Some(texture) = &self.raytraced_texture {
-                        ui.add(egui::Image::new(texture).fit_to_exact_size(image_size));
+                        let response = ui.add(egui::Image::new(texture).fit_to_exact_size(image_size).sense(egui::Sense::click()));
+                        if let (Some(compare_filter), Backend::Cpu) = (self.raytracer.compare_filter, backend) {
+                            // Mark where the image switches to the compared filter
+                            let rect = response.rect;
+                            let split = rect.left() + rect.width() * (self.raytracer.width / 2) as f32 / self.raytracer.width as f32;
+                            let painter = ui.painter_at(rect);
+                            painter.vline(split, rect.y_range(), egui::Stroke::new(1.0, egui::Color32::WHITE));
+                            let font = egui::FontId::proportional(12.0);
+                            let margin = egui::vec2(4.0, 2.0);
+                            painter.text(egui::pos2(split, rect.top()) - egui::vec2(margin.x, -margin.y), egui::Align2::RIGHT_TOP, self.raytracer.filter.name(), font.clone(), egui::Color32::WHITE);
+                            painter.text(egui::pos2(split, rect.top()) + margin, egui::Align2::LEFT_TOP, compare_filter.name(), font, egui::Color32::WHITE);
+                        }
+                        if response.clicked() {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let camera = self.camera;
+                                self.pick_object(&camera, response.rect, pos);
+                            }
+                        }
                     } else {
                         ui.allocate_space(image_size);
                         ui.label("Rendering...");
                     }
+
+                    // Running sample count
+                    let samples = if self.progressive_active() {
+                        self.accumulator.sample_count
+                    } else {
+                        self.raytracer.samples_per_pixel
+                    };
+                    let backend = match (backend, fallback_reason) {
+                        (Backend::Gpu, _) => "GPU".to_string(),
+                        (Backend::Cpu, None) => "CPU".to_string(),
+                        (Backend::Cpu, Some(reason)) => format!("CPU, {}", reason),
+                    };
+                    ui.label(format!("Samples/Pixel: {} ({})", samples, backend));
                     
                     ui.separator();
                     
@@ -250,8 +636,22 @@ impl eframe::App for RaytracerApp {
             });
         });
 
+        self.handle_scene_file(ctx);
+
+        // Display settings only need the samples redrawn. While the first pass is still
+        // coming in, its next tiles or the finished pass pick them up.
+        if !trigger_render && self.raytracer.tone_mapping != tone_mapping && self.accumulator.sample_count > 0 {
+            self.show_accumulated_image(ctx);
+        }
+
         if trigger_render {
             self.update_raytrace(ctx.clone());
+        } else {
+            self.continue_accumulation(ctx.clone());
         }
+
+        self.poll_gpu_raytracer(ctx);
+        #[cfg(target_arch = "wasm32")]
+        self.poll_render_worker(ctx);
     }
 }
@@ -0,0 +1,354 @@
+//! What rays see when they miss every object: a solid color, the sky gradient, a physical
+//! sky or an equirectangular HDR environment map.
+//!
+//! Environment maps are saved with the scene as the original file, base64 encoded, so a
+//! scene file still reproduces the render on its own.
+
+use crate::hdr_image::{self, HdrImage, HdrImageError};
+use crate::sky::Sky;
+use base64::Engine;
+use glam::{Quat, Vec3};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum Background {
+    Color(Vec3),
+    /// White at the horizon to light blue overhead.
+    #[default]
+    Gradient,
+    /// Follows the sun, see `Scene::update_sun`.
+    Sky(Sky),
+    EnvironmentMap(EnvironmentMap),
+}
+
+/// A direction to the background picked for next event estimation.
+pub struct BackgroundSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    pub pdf: f32, // Per unit solid angle
+}
+
+impl Background {
+    /// Radiance arriving from `direction`.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Self::Color(color) => *color,
+            Self::Gradient => {
+                let t = 0.5 * (direction.normalize().y + 1.0);
+                Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t
+            }
+            Self::Sky(sky) => sky.radiance(direction),
+            Self::EnvironmentMap(map) => map.radiance(direction),
+        }
+    }
+
+    /// Whether the background is sampled like a light. Pathtracing then looks it up from
+    /// diffuse surfaces directly, and must not count it again when their bounces miss.
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Self::EnvironmentMap(_))
+    }
+
+    /// A direction to the background, picked by brightness. None if the background isn't sampled.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<BackgroundSample> {
+        match self {
+            Self::EnvironmentMap(map) => map.sample(rng),
+            Self::Color(_) | Self::Gradient | Self::Sky(_) => None,
+        }
+    }
+
+    /// Density per unit solid angle with which `sample` picks `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Self::EnvironmentMap(map) => map.pdf(direction),
+            Self::Color(_) | Self::Gradient | Self::Sky(_) => 0.0,
+        }
+    }
+}
+
+/// An equirectangular image around the scene: the middle of the image is towards -Z,
+/// the top row straight up.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "EnvironmentMapFile", into = "EnvironmentMapFile")]
+pub struct EnvironmentMap {
+    pub name: String,   // File name, whose extension says the format
+    pub rotation: f32,  // Degrees about the Y axis
+    pub intensity: f32, // Multiplies the radiance in the image
+    file: Arc<Vec<u8>>, // Kept to be saved with the scene
+    image: Arc<HdrImage>,
+    distribution: Arc<PixelDistribution>,
+}
+
+/// How an environment map is saved.
+#[derive(Serialize, Deserialize)]
+struct EnvironmentMapFile {
+    name: String,
+    rotation: f32,
+    intensity: f32,
+    data: String, // The .hdr or .exr file, base64 encoded
+}
+
+impl From<EnvironmentMap> for EnvironmentMapFile {
+    fn from(map: EnvironmentMap) -> Self {
+        Self {
+            data: base64::engine::general_purpose::STANDARD.encode(map.file.as_slice()),
+            name: map.name,
+            rotation: map.rotation,
+            intensity: map.intensity,
+        }
+    }
+}
+
+impl TryFrom<EnvironmentMapFile> for EnvironmentMap {
+    type Error = HdrImageError;
+
+    fn try_from(file: EnvironmentMapFile) -> Result<Self, Self::Error> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(&file.data)
+            .map_err(|err| HdrImageError::Parse(format!("{}: {}", file.name, err)))?;
+        let mut map = Self::new(file.name, bytes)?;
+        map.rotation = file.rotation;
+        map.intensity = file.intensity;
+        Ok(map)
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("name", &self.name)
+            .field("size", &(self.image.width, self.image.height))
+            .field("rotation", &self.rotation)
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
+
+impl EnvironmentMap {
+    /// Decodes the .hdr or .exr file `name` with contents `file`.
+    pub fn new(name: String, file: Vec<u8>) -> Result<Self, HdrImageError> {
+        let image = hdr_image::decode(&name, &file)?;
+        Ok(Self {
+            name,
+            rotation: 0.0,
+            intensity: 1.0,
+            file: Arc::new(file),
+            distribution: Arc::new(PixelDistribution::new(&image)),
+            image: Arc::new(image),
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.image.width, self.image.height)
+    }
+
+    fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.rotation.to_radians())
+    }
+
+    /// Index of the pixel seen in `direction`, and the sine of its angle from straight up.
+    fn pixel(&self, direction: Vec3) -> (usize, f32) {
+        let local = self.rotation().inverse() * direction.normalize();
+        let theta = local.y.clamp(-1.0, 1.0).acos();
+        let phi = local.x.atan2(-local.z);
+        let u = 0.5 + phi / (2.0 * PI);
+        let v = theta / PI;
+        let (width, height) = (self.image.width as usize, self.image.height as usize);
+        let x = ((u * width as f32) as usize).min(width - 1);
+        let y = ((v * height as f32) as usize).min(height - 1);
+        // Exact where the acos above loses precision, next to the poles
+        let sin_theta = (local.x * local.x + local.z * local.z).sqrt();
+        (y * width + x, sin_theta)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        self.image.pixels[self.pixel(direction).0] * self.intensity
+    }
+
+    /// Density per unit solid angle with which `sample` picks `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (index, sin_theta) = self.pixel(direction);
+        match self.distribution.pdf.get(index) {
+            Some(&pixel_pdf) if sin_theta > 0.0 => {
+                pixel_pdf * (self.image.width * self.image.height) as f32 / (2.0 * PI * PI * sin_theta)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// A direction picked with probability proportional to the brightness the map shows there.
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<BackgroundSample> {
+        let (x, y, pixel_pdf) = self.distribution.sample(rng)?;
+        let (width, height) = (self.image.width as f32, self.image.height as f32);
+        let u = (x as f32 + rng.gen::<f32>()) / width;
+        let v = (y as f32 + rng.gen::<f32>()) / height;
+
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI;
+        let sin_theta = theta.sin();
+        if sin_theta <= 0.0 {
+            return None;
+        }
+        let local = Vec3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos());
+
+        // Pixels cover 2 PI^2 sin(theta) / (width * height) of solid angle
+        let pdf = pixel_pdf * width * height / (2.0 * PI * PI * sin_theta);
+        let radiance = self.image.pixels[y * self.image.width as usize + x] * self.intensity;
+        Some(BackgroundSample { direction: self.rotation() * local, radiance, pdf })
+    }
+}
+
+/// Picks pixels of an equirectangular image with probability proportional to their luminance
+/// times the solid angle they cover, which shrinks towards the poles.
+struct PixelDistribution {
+    width: usize,
+    row_cdf: Vec<f32>,     // height + 1 entries from 0 to 1
+    column_cdfs: Vec<f32>, // width + 1 entries per row, each from 0 to 1
+    pdf: Vec<f32>,         // Probability of each pixel, empty for an all black image
+}
+
+impl PixelDistribution {
+    fn new(image: &HdrImage) -> Self {
+        let (width, height) = (image.width as usize, image.height as usize);
+        let weights: Vec<f32> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let sin_theta = (((i / width) as f32 + 0.5) / height as f32 * PI).sin();
+                let luminance = pixel.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+                // Finite pixels can still add up to more than f32 holds
+                if luminance.is_finite() { luminance * sin_theta } else { 0.0 }
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+
+        let mut row_cdf = vec![0.0];
+        let mut column_cdfs = Vec::with_capacity(height * (width + 1));
+        for row in weights.chunks_exact(width) {
+            let row_total: f32 = row.iter().sum();
+            let mut sum = 0.0;
+            column_cdfs.push(0.0);
+            for &weight in row {
+                sum += weight;
+                column_cdfs.push(if row_total > 0.0 { sum / row_total } else { 0.0 });
+            }
+            row_cdf.push(row_cdf.last().unwrap() + row_total / total);
+        }
+
+        let pdf = if total > 0.0 { weights.iter().map(|weight| weight / total).collect() } else { Vec::new() };
+        Self { width, row_cdf, column_cdfs, pdf }
+    }
+
+    /// Column, row and probability of a random pixel.
+    fn sample(&self, rng: &mut impl Rng) -> Option<(usize, usize, f32)> {
+        if self.pdf.is_empty() {
+            return None;
+        }
+        // The interval of the returned entry contains xi, so it is never empty
+        let pick = |cdf: &[f32], xi: f32| cdf.partition_point(|&c| c <= xi).clamp(1, cdf.len() - 1) - 1;
+        let y = pick(&self.row_cdf, rng.gen());
+        let x = pick(&self.column_cdfs[y * (self.width + 1)..(y + 1) * (self.width + 1)], rng.gen());
+        let pdf = self.pdf[y * self.width + x];
+        (pdf > 0.0).then_some((x, y, pdf))
+    }
+}
+
+/// Picks an environment map: a file dialog on native, a browser upload on wasm.
+#[cfg(feature = "gui")]
+#[derive(Default)]
+pub struct EnvironmentMapPicker {
+    inbox: std::rc::Rc<std::cell::RefCell<Option<Result<EnvironmentMap, HdrImageError>>>>,
+}
+
+#[cfg(feature = "gui")]
+impl EnvironmentMapPicker {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(&self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("HDR image", &["hdr", "exr"]).pick_file() else {
+            return; // Cancelled
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let result = std::fs::read(&path)
+            .map_err(|err| HdrImageError::Io(format!("{}: {}", path.display(), err)))
+            .and_then(|bytes| EnvironmentMap::new(name, bytes));
+        *self.inbox.borrow_mut() = Some(result);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn open(&self) {
+        let inbox = self.inbox.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let Some(handle) = rfd::AsyncFileDialog::new().add_filter("HDR image", &["hdr", "exr"]).pick_file().await else {
+                return; // Cancelled
+            };
+            let bytes = handle.read().await;
+            *inbox.borrow_mut() = Some(EnvironmentMap::new(handle.file_name(), bytes));
+        });
+    }
+
+    /// The loaded map, once the user has picked a file.
+    pub fn poll(&self) -> Option<Result<EnvironmentMap, HdrImageError>> {
+        self.inbox.borrow_mut().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A flat RGBE file, `pixels` given as one byte mantissas with a shared exponent of 1.
+    fn hdr_file(width: u32, height: u32, pixels: &[[u8; 3]]) -> Vec<u8> {
+        let mut file = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes();
+        for &[r, g, b] in pixels {
+            file.extend_from_slice(&[r, g, b, 136]);
+        }
+        file
+    }
+
+    #[test]
+    fn samples_match_the_map_and_integrate_it() {
+        // Dim everywhere but for one bright pixel
+        let mut pixels = vec![[1, 1, 1]; 8 * 4];
+        pixels[9] = [200, 200, 200];
+        let mut map = EnvironmentMap::new("sky.hdr".to_string(), hdr_file(8, 4, &pixels)).unwrap();
+        map.rotation = 30.0;
+        let mut rng = StdRng::seed_from_u64(5);
+
+        // Radiance over pdf averages to the integral over the sphere, whatever the pdf
+        let count = 20000;
+        let mut estimate = 0.0;
+        let mut bright = 0;
+        for _ in 0..count {
+            let sample = map.sample(&mut rng).unwrap();
+            assert_eq!(map.radiance(sample.direction), sample.radiance);
+            assert!((map.pdf(sample.direction) - sample.pdf).abs() <= sample.pdf * 1e-3);
+            estimate += sample.radiance.x / sample.pdf / count as f32;
+            bright += (sample.radiance.x > 100.0) as u32;
+        }
+        // Solid angle of a pixel in row y, the same in the mirrored row
+        let cell = |y: f32| 2.0 * PI / 8.0 * ((y / 4.0 * PI).cos() - ((y + 1.0) / 4.0 * PI).cos());
+        let exact = 16.0 * 1.5 * cell(0.0) + (15.0 * 1.5 + 200.5) * cell(1.0);
+        assert!((estimate - exact).abs() < exact * 0.02, "{} vs {}", estimate, exact);
+        assert!(bright > count * 3 / 4, "{}", bright);
+    }
+
+    #[test]
+    fn saves_the_original_file() {
+        let mut map = EnvironmentMap::new("sky.hdr".to_string(), hdr_file(2, 1, &[[1, 2, 3], [4, 5, 6]])).unwrap();
+        map.intensity = 2.5;
+        let json = serde_json::to_string(&Background::EnvironmentMap(map)).unwrap();
+
+        let Background::EnvironmentMap(loaded) = serde_json::from_str(&json).unwrap() else {
+            panic!("not an environment map: {}", json);
+        };
+        assert_eq!(loaded.intensity, 2.5);
+        assert_eq!(loaded.radiance(-Vec3::Z), Vec3::new(4.5, 5.5, 6.5) * 2.5);
+        assert!(serde_json::from_str::<Background>(&json.replace("sky.hdr", "sky.png")).is_err());
+    }
+}
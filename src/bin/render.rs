@@ -0,0 +1,201 @@
+//! Headless renderer: loads a scene file and writes the rendered image to a PNG or PPM file,
+//! or unmapped to a Radiance HDR file.
+//!
+//! Needs no window or GPU and builds without the `gui` feature:
+//! `cargo run --release --no-default-features --features parallel --bin render -- scene.ron -o out.png`
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> std::process::ExitCode {
+    cli::main()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    panic!("render is a command-line tool and does not run in the browser");
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod cli {
+    use interactive_wasm_raytracer::{film::FilterType, hdr_image, image_file, raytracer::RenderMode, sampler::SamplerType, scene_file};
+    use interactive_wasm_raytracer::tone_mapping::ToneMapper;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process::ExitCode;
+
+    const USAGE: &str = "\
+Usage: render <SCENE> -o <OUTPUT> [OPTIONS]
+
+Renders a .json or .ron scene file and writes the image as .png or .ppm, tone mapped,
+or as .hdr in linear HDR colors. Options override the settings stored in the scene file.
+
+Options:
+  -o, --output <PATH>     Image to write (.png, .ppm or .hdr)
+      --width <PIXELS>    Image width
+      --height <PIXELS>   Image height
+      --mode <MODE>       raytracing or pathtracing
+      --bounces <N>       Maximum bounces per path
+      --roulette <N>      Bounces before Russian roulette may end a pathtraced path
+      --spp <N>           Samples per pixel
+      --seed <N>          Random seed, equal seeds give equal images
+      --sampler <NAME>    independent, stratified, sobol or blue-noise
+      --filter <NAME>     box, tent, gaussian, mitchell or lanczos
+      --exposure <STOPS>  Brightens (or with a negative value darkens) the image
+      --tone-mapper <NAME>
+                          clamp, reinhard, aces, agx or filmic
+  -h, --help              Print this help";
+
+    /// Command line arguments. Settings left as None keep the value from the scene file.
+    #[derive(Default)]
+    struct Options {
+        scene: PathBuf,
+        output: PathBuf,
+        width: Option<u32>,
+        height: Option<u32>,
+        mode: Option<RenderMode>,
+        bounces: Option<u32>,
+        roulette_depth: Option<u32>,
+        samples_per_pixel: Option<u32>,
+        seed: Option<u64>,
+        sampler: Option<SamplerType>,
+        filter: Option<FilterType>,
+        exposure: Option<f32>,
+        tone_mapper: Option<ToneMapper>,
+    }
+
+    enum ParseResult {
+        Run(Options),
+        Help,
+    }
+
+    fn parse_args(mut args: impl Iterator<Item = String>) -> Result<ParseResult, String> {
+        fn value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+            let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+            value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, flag))
+        }
+
+        let mut options = Options::default();
+        let mut scene = None;
+        let mut output = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(ParseResult::Help),
+                "-o" | "--output" => output = Some(value::<PathBuf>(&arg, args.next())?),
+                "--width" => options.width = Some(value(&arg, args.next())?),
+                "--height" => options.height = Some(value(&arg, args.next())?),
+                "--bounces" => options.bounces = Some(value(&arg, args.next())?),
+                "--roulette" => options.roulette_depth = Some(value(&arg, args.next())?),
+                "--spp" => options.samples_per_pixel = Some(value(&arg, args.next())?),
+                "--seed" => options.seed = Some(value(&arg, args.next())?),
+                "--mode" => {
+                    let mode: String = value(&arg, args.next())?;
+                    options.mode = Some(match mode.to_ascii_lowercase().as_str() {
+                        "raytracing" => RenderMode::Raytracing,
+                        "pathtracing" => RenderMode::Pathtracing,
+                        _ => return Err(format!("Unknown mode '{}', expected raytracing or pathtracing", mode)),
+                    });
+                }
+                "--sampler" => {
+                    let name: String = value(&arg, args.next())?;
+                    let sampler = SamplerType::ALL.into_iter().find(|sampler| {
+                        sampler.name().replace(' ', "-").eq_ignore_ascii_case(&name)
+                    });
+                    options.sampler = Some(sampler.ok_or_else(|| {
+                        format!("Unknown sampler '{}', expected independent, stratified, sobol or blue-noise", name)
+                    })?);
+                }
+                "--filter" => {
+                    let name: String = value(&arg, args.next())?;
+                    let filter = FilterType::ALL.into_iter().find(|filter| format!("{:?}", filter).eq_ignore_ascii_case(&name));
+                    options.filter = Some(filter.ok_or_else(|| {
+                        format!("Unknown filter '{}', expected box, tent, gaussian, mitchell or lanczos", name)
+                    })?);
+                }
+                "--exposure" => options.exposure = Some(value(&arg, args.next())?),
+                "--tone-mapper" => {
+                    let name: String = value(&arg, args.next())?;
+                    let tone_mapper = ToneMapper::ALL.into_iter().find(|tone_mapper| tone_mapper.name().eq_ignore_ascii_case(&name));
+                    options.tone_mapper = Some(tone_mapper.ok_or_else(|| {
+                        format!("Unknown tone mapper '{}', expected clamp, reinhard, aces, agx or filmic", name)
+                    })?);
+                }
+                flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+                _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument '{}'", arg)),
+            }
+        }
+
+        options.scene = scene.ok_or("Missing scene file")?;
+        options.output = output.ok_or("Missing output image, pass -o <PATH>")?;
+        Ok(ParseResult::Run(options))
+    }
+
+    fn run(options: Options) -> Result<(), String> {
+        // Fail before rendering rather than after
+        if image_file::ImageFormat::from_path(&options.output).is_none() && !is_hdr(&options.output) {
+            return Err(format!("'{}' is not a .png, .ppm or .hdr file", options.output.display()));
+        }
+
+        let mut file = scene_file::load(&options.scene).map_err(|err| err.to_string())?;
+
+        let raytracer = &mut file.raytracer;
+        raytracer.width = options.width.unwrap_or(raytracer.width);
+        raytracer.height = options.height.unwrap_or(raytracer.height);
+        raytracer.mode = options.mode.unwrap_or(raytracer.mode);
+        raytracer.max_bounces = options.bounces.unwrap_or(raytracer.max_bounces);
+        raytracer.roulette_depth = options.roulette_depth.unwrap_or(raytracer.roulette_depth);
+        raytracer.samples_per_pixel = options.samples_per_pixel.unwrap_or(raytracer.samples_per_pixel);
+        raytracer.seed = options.seed.unwrap_or(raytracer.seed);
+        raytracer.sampler = options.sampler.unwrap_or(raytracer.sampler);
+        raytracer.filter = options.filter.unwrap_or(raytracer.filter);
+        raytracer.tone_mapping.exposure = options.exposure.unwrap_or(raytracer.tone_mapping.exposure);
+        raytracer.tone_mapping.tone_mapper = options.tone_mapper.unwrap_or(raytracer.tone_mapping.tone_mapper);
+        if options.width.is_some() || options.height.is_some() {
+            // Keep pixels square at the new resolution
+            file.camera.aspect_ratio = raytracer.width as f32 / raytracer.height as f32;
+        }
+        file.validate().map_err(|err| err.to_string())?;
+
+        file.scene.rebuild_bvh();
+        let image = file.raytracer.render(&file.scene, &file.camera);
+        if is_hdr(&options.output) {
+            return save_hdr(&options.output, &image);
+        }
+        let pixels = file.raytracer.tone_mapping.image_to_rgba(&image.pixels);
+        image_file::save(&options.output, image.width, image.height, &pixels).map_err(|err| err.to_string())
+    }
+
+    fn is_hdr(path: &Path) -> bool {
+        path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"))
+    }
+
+    fn save_hdr(path: &Path, image: &hdr_image::HdrImage) -> Result<(), String> {
+        let io_error = |err: std::io::Error| format!("Could not write image: {}: {}", path.display(), err);
+        let file = std::fs::File::create(path).map_err(io_error)?;
+        let mut writer = std::io::BufWriter::new(file);
+        hdr_image::encode_rgbe(&mut writer, image).map_err(io_error)?;
+        writer.flush().map_err(io_error)
+    }
+
+    pub fn main() -> ExitCode {
+        let options = match parse_args(std::env::args().skip(1)) {
+            Ok(ParseResult::Run(options)) => options,
+            Ok(ParseResult::Help) => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            Err(message) => {
+                eprintln!("error: {}\n\n{}", message, USAGE);
+                return ExitCode::from(2);
+            }
+        };
+
+        match run(options) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Web Worker that renders tiles off the browser's main thread (see `render_worker.rs`).
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    eframe::WebLogger::init(log::LevelFilter::Warn).ok();
+    interactive_wasm_raytracer::render_worker::run_worker();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("render_worker only runs as a Web Worker in the wasm build. Native builds render in-process.");
+}
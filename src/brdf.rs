@@ -0,0 +1,434 @@
+//! GGX (Trowbridge-Reitz) microfacet reflection with Smith shadowing and Schlick's Fresnel,
+//! and the principled material built from it.
+//!
+//! Light sampling and bounces both go through [`Ggx`] and [`PrincipledBsdf`], so they agree
+//! on how much a surface reflects. Directions all point away from the surface.
+
+use crate::math::{random_unit_vector, reflectance, refract};
+use crate::primitives::Material;
+use glam::{Vec2, Vec3};
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// Roughness below which a lobe is treated as a perfect mirror: sampling it is the only way
+/// to find what it reflects, so light sampling skips it.
+pub const SHARP_ROUGHNESS: f32 = 0.03;
+
+/// Smallest alpha, so very smooth surfaces keep a finite highlight. Rougher lobes never get
+/// narrower than at `SHARP_ROUGHNESS`, so all that changes there is that point lights drop out.
+const MIN_ALPHA: f32 = SHARP_ROUGHNESS * SHARP_ROUGHNESS;
+
+/// A tangent frame around `normal`. Tangents run around the vertical axis, which is the
+/// direction anisotropic highlights are stretched in, except where that is undefined.
+fn tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let tangent = Vec3::Y.cross(normal);
+    if tangent.length_squared() < 1e-6 {
+        return normal.any_orthonormal_pair();
+    }
+    let tangent = tangent.normalize();
+    (tangent, normal.cross(tangent))
+}
+
+/// Reflection off a rough conductor.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub f0: Vec3,    // Reflectance at normal incidence
+    pub alpha: Vec2, // Width of the normal distribution along the tangent and bitangent
+}
+
+impl Ggx {
+    pub fn new(f0: Vec3, roughness: f32) -> Self {
+        Self::anisotropic(f0, roughness, 0.0)
+    }
+
+    /// `roughness` is perceptual, alpha is its square. `anisotropy` from 0 to 1 narrows the
+    /// distribution across the tangent, stretching highlights along it.
+    pub fn anisotropic(f0: Vec3, roughness: f32, anisotropy: f32) -> Self {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Self { f0, alpha: Vec2::new(alpha / aspect, alpha * aspect).max(Vec2::splat(MIN_ALPHA)) }
+    }
+
+    /// Density of microfacet normals `h`, given in the tangent frame.
+    fn distribution(&self, h: Vec3) -> f32 {
+        let s = (h.x / self.alpha.x).powi(2) + (h.y / self.alpha.y).powi(2) + h.z * h.z;
+        1.0 / (PI * self.alpha.x * self.alpha.y * s * s)
+    }
+
+    /// Fraction of the microfacets facing `v`, given in the tangent frame, that it sees.
+    fn smith_g1(&self, v: Vec3) -> f32 {
+        let tan2 = ((self.alpha.x * v.x).powi(2) + (self.alpha.y * v.y).powi(2)) / (v.z * v.z);
+        2.0 / (1.0 + (1.0 + tan2).sqrt())
+    }
+
+    fn fresnel(&self, cos_theta: f32) -> Vec3 {
+        fresnel_schlick(self.f0, cos_theta)
+    }
+
+    /// The BRDF times the cosine of the light direction: what reflects towards `view` per unit
+    /// of radiance arriving from `light`.
+    pub fn eval(&self, normal: Vec3, view: Vec3, light: Vec3) -> Vec3 {
+        let (view, light) = (to_local(normal, view), to_local(normal, light));
+        if view.z <= 0.0 || light.z <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let halfway = (view + light).normalize();
+        let g = self.smith_g1(view) * self.smith_g1(light);
+        self.fresnel(view.dot(halfway)) * (self.distribution(halfway) * g / (4.0 * view.z))
+    }
+
+    /// Density per unit solid angle with which `sample` picks `light`.
+    pub fn pdf(&self, normal: Vec3, view: Vec3, light: Vec3) -> f32 {
+        let (view, light) = (to_local(normal, view), to_local(normal, light));
+        if view.z <= 0.0 || light.z <= 0.0 {
+            return 0.0;
+        }
+        let halfway = (view + light).normalize();
+        self.smith_g1(view) * self.distribution(halfway) / (4.0 * view.z)
+    }
+
+    /// Picks a light direction by sampling the microfacet normals `view` can see (Heitz 2018).
+    /// Returns it with `eval / pdf`, or None if it would leave below the surface.
+    pub fn sample(&self, normal: Vec3, view: Vec3, rng: &mut impl Rng) -> Option<(Vec3, Vec3)> {
+        let halfway = self.sample_normal(normal, view, rng)?;
+        let light = (-view).reflect(halfway);
+        let local_light = to_local(normal, light);
+        if local_light.z <= 0.0 {
+            return None;
+        }
+        // eval / pdf: D and G1(view) cancel
+        Some((light, self.fresnel(view.dot(halfway)) * self.smith_g1(local_light)))
+    }
+
+    /// A microfacet normal that `view` sees, in world space.
+    fn sample_normal(&self, normal: Vec3, view: Vec3, rng: &mut impl Rng) -> Option<Vec3> {
+        let local_view = to_local(normal, view);
+        if local_view.z <= 0.0 {
+            return None;
+        }
+
+        // Stretch to a hemisphere configuration, pick a point on the projected disk, unstretch
+        let stretched = Vec3::new(self.alpha.x * local_view.x, self.alpha.y * local_view.y, local_view.z).normalize();
+        let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+        let t1 = if length2 > 0.0 { Vec3::new(-stretched.y, stretched.x, 0.0) / length2.sqrt() } else { Vec3::X };
+        let t2 = stretched.cross(t1);
+        let r = rng.gen::<f32>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f32>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + stretched.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n_h = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * stretched;
+        let h = Vec3::new(self.alpha.x * n_h.x, self.alpha.y * n_h.y, n_h.z.max(0.0)).normalize();
+
+        let (tangent, bitangent) = tangent_frame(normal);
+        Some(tangent * h.x + bitangent * h.y + normal * h.z)
+    }
+}
+
+fn to_local(normal: Vec3, v: Vec3) -> Vec3 {
+    let (tangent, bitangent) = tangent_frame(normal);
+    Vec3::new(v.dot(tangent), v.dot(bitangent), v.dot(normal))
+}
+
+/// Schlick's approximation of the Fresnel reflectance.
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+/// Which part of a [`PrincipledBsdf`] a sample came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Glossy,
+    Mirror, // A lobe below `SHARP_ROUGHNESS`
+    Transmission,
+}
+
+impl Lobe {
+    /// Whether light sampling covers the lobe. Bounces off the others have to pick up
+    /// lights and the background themselves.
+    pub fn is_light_sampled(self) -> bool {
+        matches!(self, Self::Diffuse | Self::Glossy)
+    }
+}
+
+pub struct BsdfSample {
+    pub direction: Vec3,
+    pub weight: Vec3, // BSDF times cosine over the probability of the direction
+    pub lobe: Lobe,
+}
+
+/// A principled material after Burley's Disney BSDF: a dielectric base that diffuses or
+/// transmits light with a sheen on top, a specular layer that turns it into a metal as
+/// `metallic` goes to 1, and a clearcoat over everything.
+pub struct PrincipledBsdf {
+    normal: Vec3,   // Facing the viewer
+    entering: bool, // Whether the viewer is on the outside, for refraction
+    diffuse: Vec3,
+    sheen: Vec3,
+    specular: Ggx,
+    specular_weight: f32,
+    specular_sharp: bool,
+    coat: Ggx,
+    coat_weight: f32,
+    coat_sharp: bool,
+    transmission: Vec3,
+    transmission_ggx: Ggx, // Microfacets refracting the transmitted light
+    transmission_sharp: bool,
+    ior: f32,
+    probabilities: [f32; 4], // Of sampling diffuse, specular, coat and transmission
+}
+
+impl PrincipledBsdf {
+    /// The BSDF of `material` at a surface with outward `normal`, seen from `view`.
+    pub fn new(material: &Material, normal: Vec3, view: Vec3) -> Self {
+        let params = &material.principled;
+        let entering = normal.dot(view) >= 0.0;
+        let dielectric = 1.0 - params.metallic;
+        let f0 = Vec3::splat(0.08 * material.specular).lerp(material.color, params.metallic);
+        // The transmissive part reflects through its own Fresnel term
+        let specular_weight = 1.0 - dielectric * params.transmission;
+
+        let weights = [
+            dielectric * (1.0 - params.transmission),
+            specular_weight,
+            params.clearcoat,
+            dielectric * params.transmission,
+        ];
+        let total: f32 = weights.iter().sum();
+        // Light the specular layer reflects doesn't reach the diffuse base
+        let diffuse_transmittance = 1.0 - fresnel_schlick(Vec3::splat(0.08 * material.specular), normal.dot(view).abs()).x;
+
+        Self {
+            normal: if entering { normal } else { -normal },
+            entering,
+            diffuse: material.color * weights[0] * diffuse_transmittance,
+            sheen: Vec3::splat(params.sheen * weights[0]),
+            specular: Ggx::anisotropic(f0, material.roughness, params.anisotropy),
+            specular_weight,
+            specular_sharp: material.roughness < SHARP_ROUGHNESS,
+            coat: Ggx::new(Vec3::splat(0.04), params.clearcoat_roughness),
+            coat_weight: params.clearcoat,
+            coat_sharp: params.clearcoat_roughness < SHARP_ROUGHNESS,
+            transmission: material.color * weights[3],
+            transmission_ggx: Ggx::new(Vec3::ONE, material.roughness),
+            transmission_sharp: material.roughness < SHARP_ROUGHNESS,
+            ior: material.ior,
+            probabilities: weights.map(|weight| if total > 0.0 { weight / total } else { 0.0 }),
+        }
+    }
+
+    /// What the clearcoat lets through to the layers below.
+    fn coat_transmittance(&self, view: Vec3) -> f32 {
+        1.0 - self.coat_weight * fresnel_schlick(self.coat.f0, self.normal.dot(view)).x
+    }
+
+    /// BSDF times cosine of the lobes that light sampling covers.
+    pub fn eval(&self, view: Vec3, light: Vec3) -> Vec3 {
+        let n_dot_l = self.normal.dot(light);
+        if n_dot_l <= 0.0 || self.normal.dot(view) <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let l_dot_h = light.dot((view + light).normalize());
+        let mut base = (self.diffuse / PI + self.sheen * (1.0 - l_dot_h).powi(5)) * n_dot_l;
+        if !self.specular_sharp {
+            base += self.specular.eval(self.normal, view, light) * self.specular_weight;
+        }
+        let mut value = base * self.coat_transmittance(view);
+        if !self.coat_sharp {
+            value += self.coat.eval(self.normal, view, light) * self.coat_weight;
+        }
+        value
+    }
+
+    /// Density with which `sample` picks `light` through the lobes `eval` covers.
+    pub fn pdf(&self, view: Vec3, light: Vec3) -> f32 {
+        let n_dot_l = self.normal.dot(light);
+        if n_dot_l <= 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, coat, _] = self.probabilities;
+        let mut pdf = diffuse * n_dot_l / PI;
+        if !self.specular_sharp {
+            pdf += specular * self.specular.pdf(self.normal, view, light);
+        }
+        if !self.coat_sharp {
+            pdf += coat * self.coat.pdf(self.normal, view, light);
+        }
+        pdf
+    }
+
+    /// Picks a lobe by its weight and a direction from it. None if the path ends here.
+    pub fn sample(&self, view: Vec3, rng: &mut impl Rng) -> Option<BsdfSample> {
+        let [diffuse, specular, coat, transmission] = self.probabilities;
+        let u = rng.gen::<f32>();
+        let glossy = |direction: Vec3, lobe| {
+            let pdf = self.pdf(view, direction);
+            (pdf > 0.0).then(|| BsdfSample { direction, weight: self.eval(view, direction) / pdf, lobe })
+        };
+        let mirror = |ggx: &Ggx, weight: f32, probability: f32| {
+            let direction = (-view).reflect(self.normal);
+            let weight = ggx.fresnel(self.normal.dot(view)) * weight / probability;
+            Some(BsdfSample { direction, weight, lobe: Lobe::Mirror })
+        };
+
+        if u < diffuse {
+            glossy((self.normal + random_unit_vector(rng)).normalize(), Lobe::Diffuse)
+        } else if u < diffuse + specular {
+            let weight = self.specular_weight * self.coat_transmittance(view);
+            if self.specular_sharp {
+                return mirror(&self.specular, weight, specular);
+            }
+            let (direction, _) = self.specular.sample(self.normal, view, rng)?;
+            glossy(direction, Lobe::Glossy)
+        } else if u < diffuse + specular + coat {
+            if self.coat_sharp {
+                return mirror(&self.coat, self.coat_weight, coat);
+            }
+            let (direction, _) = self.coat.sample(self.normal, view, rng)?;
+            glossy(direction, Lobe::Glossy)
+        } else {
+            self.sample_transmission(view, rng).map(|(direction, weight)| BsdfSample {
+                direction,
+                weight: self.transmission * weight * self.coat_transmittance(view) / transmission,
+                lobe: Lobe::Transmission,
+            })
+        }
+    }
+
+    /// Refracts or reflects off a microfacet by the dielectric Fresnel term. The weight is
+    /// G1 of the new direction, which is all that remains after sampling visible normals.
+    fn sample_transmission(&self, view: Vec3, rng: &mut impl Rng) -> Option<(Vec3, f32)> {
+        let halfway = if self.transmission_sharp {
+            self.normal
+        } else {
+            self.transmission_ggx.sample_normal(self.normal, view, rng)?
+        };
+        let refraction_ratio = if self.entering { 1.0 / self.ior } else { self.ior };
+        let cos_theta = view.dot(halfway).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let cannot_refract = refraction_ratio * sin_theta > 1.0;
+
+        let direction = if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>() {
+            let direction = (-view).reflect(halfway);
+            if self.normal.dot(direction) <= 0.0 {
+                return None;
+            }
+            direction
+        } else {
+            let direction = refract(-view, halfway, refraction_ratio);
+            if self.normal.dot(direction) >= 0.0 {
+                return None;
+            }
+            direction
+        };
+
+        let weight = if self.transmission_sharp {
+            1.0
+        } else {
+            self.transmission_ggx.smith_g1(to_local(self.normal, direction))
+        };
+        Some((direction, weight))
+    }
+
+    /// Share of the light from the mirror direction that the specular layers reflect, all of
+    /// them treated as perfectly smooth. Approximates the layers where rays can't branch.
+    pub fn mirror_reflectance(&self, view: Vec3) -> Vec3 {
+        let cos_theta = self.normal.dot(view);
+        self.specular.fresnel(cos_theta) * self.specular_weight * self.coat_transmittance(view)
+            + self.coat.fresnel(cos_theta) * self.coat_weight
+    }
+
+    /// Share of the light that the base lets through.
+    pub fn transmittance(&self, view: Vec3) -> Vec3 {
+        self.transmission * self.coat_transmittance(view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{MaterialType, PrincipledParams};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A white surface under uniform white light reflects at most what arrives: sampled
+    /// and uniform estimates of its albedo agree and never exceed one.
+    #[test]
+    fn white_furnace() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let normal = Vec3::new(0.3, 0.9, -0.2).normalize();
+        let (tangent, _) = normal.any_orthonormal_pair();
+        let count = 200_000;
+
+        for roughness in [0.3, 0.6, 1.0] {
+            let ggx = Ggx::new(Vec3::ONE, roughness);
+            for cos_view in [1.0, 0.5, 0.1f32] {
+                let view = normal * cos_view + tangent * (1.0 - cos_view * cos_view).sqrt();
+
+                let mut sampled = 0.0;
+                for _ in 0..count {
+                    if let Some((light, weight)) = ggx.sample(normal, view, &mut rng) {
+                        assert!(weight.max_element() <= 1.0 + 1e-5, "{}", weight);
+                        let expected = ggx.eval(normal, view, light) / ggx.pdf(normal, view, light);
+                        assert!((weight - expected).abs().max_element() < 1e-3, "{} vs {}", weight, expected);
+                        sampled += weight.x / count as f32;
+                    }
+                }
+
+                let mut uniform = 0.0;
+                for _ in 0..count {
+                    let mut light = crate::math::random_unit_vector(&mut rng);
+                    if light.dot(normal) < 0.0 {
+                        light = -light;
+                    }
+                    uniform += ggx.eval(normal, view, light).x * 2.0 * PI / count as f32;
+                }
+
+                assert!(sampled <= 1.0, "roughness {} cos {}: {}", roughness, cos_view, sampled);
+                assert!(uniform < 1.01, "roughness {} cos {}: {}", roughness, cos_view, uniform);
+                assert!((sampled - uniform).abs() < 0.02, "roughness {} cos {}: {} vs {}", roughness, cos_view, sampled, uniform);
+            }
+        }
+    }
+
+    /// White principled materials don't reflect and transmit more than arrives, and each of
+    /// the three presets keeps its character.
+    #[test]
+    fn principled_conserves_energy() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let normal = Vec3::Y;
+        let view = Vec3::new(0.6, 0.8, 0.0);
+        let count = 100_000;
+        let white = Material { color: Vec3::ONE, specular: 0.5, roughness: 0.4, ..Material::default() };
+        let variants = [
+            PrincipledParams::default(),
+            PrincipledParams { metallic: 1.0, anisotropy: 0.8, ..Default::default() },
+            PrincipledParams { transmission: 1.0, ..Default::default() },
+            PrincipledParams { metallic: 0.5, clearcoat: 1.0, sheen: 1.0, ..Default::default() },
+        ];
+
+        for principled in variants {
+            let material = Material { mat_type: MaterialType::Principled, principled, ..white };
+            let bsdf = PrincipledBsdf::new(&material, normal, view);
+            let mut total = 0.0;
+            for _ in 0..count {
+                if let Some(sample) = bsdf.sample(view, &mut rng) {
+                    assert!(sample.weight.is_finite() && sample.weight.min_element() >= 0.0, "{}", sample.weight);
+                    total += sample.weight.x / count as f32;
+                }
+            }
+            assert!(total < 1.02, "{:?}: {}", principled, total);
+        }
+
+        let lambertian = Material { mat_type: MaterialType::Lambertian, ..white }.to_principled();
+        let bsdf = PrincipledBsdf::new(&lambertian, normal, view);
+        assert!((bsdf.eval(view, normal) - Vec3::ONE / PI).abs().max_element() < 1e-3);
+        assert!(bsdf.mirror_reflectance(view).max_element() < 1e-3);
+
+        let glass = Material { mat_type: MaterialType::Dielectric, ..white }.to_principled();
+        let bsdf = PrincipledBsdf::new(&glass, normal, view);
+        assert_eq!(bsdf.eval(view, normal), Vec3::ZERO);
+        assert_eq!(bsdf.transmittance(view), Vec3::ONE);
+    }
+}
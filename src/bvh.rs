@@ -0,0 +1,400 @@
+use crate::math::Ray;
+use glam::{Mat4, Vec3};
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    /// Box around this box after `matrix` is applied to it.
+    pub fn transformed(self, matrix: Mat4) -> Self {
+        (0..8).fold(Self::EMPTY, |bounds, corner| {
+            let pick = |axis: usize| if corner & (1 << axis) == 0 { self.min[axis] } else { self.max[axis] };
+            bounds.grow(matrix.transform_point3(Vec3::new(pick(0), pick(1), pick(2))))
+        })
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Enlarges the box slightly so rounding in the slab test can never reject
+    /// a ray that the primitive inside would accept.
+    pub fn padded(self) -> Self {
+        let epsilon = (self.max.abs().max(self.min.abs()).max_element() + 1.0) * 1e-4;
+        Self {
+            min: self.min - Vec3::splat(epsilon),
+            max: self.max + Vec3::splat(epsilon),
+        }
+    }
+
+    /// Slab test. Returns the entry distance if the ray overlaps the box within [t_min, t_max].
+    pub fn hit(&self, ray: &Ray, inv_direction: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+        let t1 = (self.min - ray.origin) * inv_direction;
+        let t2 = (self.max - ray.origin) * inv_direction;
+
+        // 0 * inf = NaN when a ray parallel to a slab starts exactly on it. That axis must not
+        // constrain the interval, and f32::min/max would otherwise pick the infinite operand.
+        let t_lo = t1.min(t2);
+        let t_hi = t1.max(t2);
+        let t_lo = Vec3::select(t1.is_nan_mask() | t2.is_nan_mask(), Vec3::NEG_INFINITY, t_lo);
+        let t_hi = Vec3::select(t1.is_nan_mask() | t2.is_nan_mask(), Vec3::INFINITY, t_hi);
+
+        let t_near = t_lo.max_element().max(t_min);
+        let t_far = t_hi.min_element().min(t_max);
+
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+/// Anything that can be stored in a [`Bvh`].
+pub trait Bounded {
+    fn bounds(&self) -> Aabb;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    bounds: Aabb,
+    first: usize, // Leaf: first entry in `indices`. Interior: left child (right child is first + 1)
+    count: usize, // Number of primitives in a leaf, 0 for interior nodes
+}
+
+/// Bounding volume hierarchy over a list of primitive bounds.
+///
+/// The tree only stores indices, so the caller keeps ownership of the primitives
+/// and does the actual intersection tests in the closure passed to [`Bvh::intersect`].
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+const MAX_LEAF_SIZE: usize = 2;
+
+/// Traversal stack size. Median splits halve the primitives at every level, so no tree gets
+/// deeper than the bits in a primitive count, and the stack never holds more than one entry
+/// per level plus the node being split.
+const STACK_SIZE: usize = usize::BITS as usize + 1;
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            indices: (0..bounds.len()).collect(),
+        };
+
+        if !bounds.is_empty() {
+            bvh.nodes.push(BvhNode {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: bounds.len(),
+            });
+            bvh.subdivide(0, bounds);
+        }
+
+        bvh
+    }
+
+    /// Number of primitives the tree was built over.
+    pub fn primitive_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn subdivide(&mut self, node_index: usize, bounds: &[Aabb]) {
+        let first = self.nodes[node_index].first;
+        let count = self.nodes[node_index].count;
+        let primitives = first..first + count;
+
+        let mut node_bounds = Aabb::EMPTY;
+        let mut centroid_bounds = Aabb::EMPTY;
+        for &i in &self.indices[primitives.clone()] {
+            node_bounds = node_bounds.union(bounds[i].padded());
+            centroid_bounds = centroid_bounds.grow(bounds[i].centroid());
+        }
+        self.nodes[node_index].bounds = node_bounds;
+
+        if count <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        // Median split along the axis where the centroids are spread the most
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        self.indices[primitives].sort_by(|&a, &b| {
+            bounds[a].centroid()[axis].total_cmp(&bounds[b].centroid()[axis])
+        });
+        let left_count = count / 2;
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first,
+            count: left_count,
+        });
+        self.nodes.push(BvhNode {
+            bounds: Aabb::EMPTY,
+            first: first + left_count,
+            count: count - left_count,
+        });
+        self.nodes[node_index].first = left;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, bounds);
+        self.subdivide(left + 1, bounds);
+    }
+
+    /// Recomputes the node bounds for moved primitives while keeping the tree topology.
+    /// `bounds` must have the same length as the list the tree was built from.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        debug_assert_eq!(bounds.len(), self.primitive_count());
+
+        // Children are always stored after their parent, so a reverse sweep visits them first
+        for node_index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[node_index];
+            self.nodes[node_index].bounds = if node.count > 0 {
+                self.indices[node.first..node.first + node.count]
+                    .iter()
+                    .fold(Aabb::EMPTY, |acc, &i| acc.union(bounds[i].padded()))
+            } else {
+                self.nodes[node.first].bounds.union(self.nodes[node.first + 1].bounds)
+            };
+        }
+    }
+
+    /// Walks every leaf the ray may touch. `hit_primitive(index, closest_t)` must test
+    /// primitive `index` against [t_min, closest_t] and return the new closest distance
+    /// if it accepted a hit.
+    pub fn intersect<F>(&self, ray: &Ray, t_min: f32, t_max: f32, mut hit_primitive: F)
+    where
+        F: FnMut(usize, f32) -> Option<f32>,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let inv_direction = ray.direction.recip();
+        let mut closest_t = t_max;
+        // On the stack rather than the heap, this runs for every ray including shadow rays
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &self.nodes[stack[stack_len]];
+            if node.bounds.hit(ray, inv_direction, t_min, closest_t).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    if let Some(t) = hit_primitive(i, closest_t) {
+                        closest_t = t;
+                    }
+                }
+            } else {
+                // Visit the nearer child first so the far one is more likely to be culled
+                let left = node.first;
+                let right = node.first + 1;
+                let t_left = self.nodes[left].bounds.hit(ray, inv_direction, t_min, closest_t);
+                let t_right = self.nodes[right].bounds.hit(ray, inv_direction, t_min, closest_t);
+                let children: &[usize] = match (t_left, t_right) {
+                    (Some(l), Some(r)) if l <= r => &[right, left],
+                    (Some(_), Some(_)) => &[left, right],
+                    (Some(_), None) => &[left],
+                    (None, Some(_)) => &[right],
+                    (None, None) => &[],
+                };
+                stack[stack_len..stack_len + children.len()].copy_from_slice(children);
+                stack_len += children.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{Ray, Transform};
+    use crate::primitives::{Cube, HitRecord, Material, MaterialType, Plane, Sphere};
+    use crate::scene::Scene;
+    use glam::{Quat, Vec3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_vec(rng: &mut StdRng, range: f32) -> Vec3 {
+        Vec3::new(
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+        )
+    }
+
+    fn random_material(rng: &mut StdRng) -> Material {
+        Material {
+            color: Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+            mat_type: MaterialType::Lambertian,
+            ..Default::default()
+        }
+    }
+
+    /// Identity most of the time, otherwise a random rotation, non-uniform scale and offset.
+    fn random_transform(rng: &mut StdRng) -> Transform {
+        if rng.gen_bool(0.6) {
+            return Transform::default();
+        }
+        Transform {
+            position: random_vec(rng, 3.0),
+            rotation: Quat::from_axis_angle(random_vec(rng, 1.0).normalize(), rng.gen_range(0.0..6.3)),
+            scale: Vec3::new(rng.gen_range(0.3..2.0), rng.gen_range(0.3..2.0), rng.gen_range(0.3..2.0)),
+        }
+    }
+
+    fn random_scene(rng: &mut StdRng, objects: usize) -> Scene {
+        let mut scene = Scene::empty();
+        for _ in 0..objects {
+            if rng.gen_bool(0.5) {
+                scene.spheres.push(Sphere {
+                    center: random_vec(rng, 10.0),
+                    radius: rng.gen_range(0.1..2.0),
+                    material: random_material(rng),
+                    transform: random_transform(rng),
+                });
+            } else {
+                let min = random_vec(rng, 10.0);
+                // Some cubes snap to integer coordinates so axis-aligned rays graze their faces
+                let min = if rng.gen_bool(0.2) { min.round() } else { min };
+                scene.cubes.push(Cube {
+                    min,
+                    max: min + Vec3::new(rng.gen_range(0.1..3.0), rng.gen_range(0.1..3.0), rng.gen_range(0.1..3.0)),
+                    material: random_material(rng),
+                    transform: random_transform(rng),
+                });
+            }
+        }
+        scene.planes.push(Plane {
+            point: Vec3::new(0.0, -8.0, 0.0),
+            normal: Vec3::Y,
+            material: random_material(rng),
+            transform: Transform::default(),
+        });
+        scene.rebuild_bvh();
+        scene
+    }
+
+    fn random_ray(rng: &mut StdRng) -> Ray {
+        let origin = random_vec(rng, 15.0);
+        let direction = match rng.gen_range(0..4) {
+            // Axis-aligned directions exercise the zero-component slab cases
+            0 => [Vec3::X, Vec3::Y, Vec3::Z, -Vec3::X, -Vec3::Y, -Vec3::Z][rng.gen_range(0..6)],
+            _ => random_vec(rng, 1.0),
+        };
+        Ray::new(origin, direction)
+    }
+
+    fn assert_same_hit(bvh: Option<HitRecord>, linear: Option<HitRecord>) {
+        match (bvh, linear) {
+            (None, None) => {}
+            (Some(a), Some(b)) => {
+                assert_eq!(a.t.to_bits(), b.t.to_bits());
+                assert_eq!(a.point, b.point);
+                assert_eq!(a.normal, b.normal);
+                assert_eq!(a.material, b.material);
+            }
+            (a, b) => panic!("bvh hit {:?} but linear scan hit {:?}", a.map(|h| h.t), b.map(|h| h.t)),
+        }
+    }
+
+    #[test]
+    fn bvh_matches_linear_scan_on_random_scenes() {
+        let mut rng = StdRng::seed_from_u64(0xB0B);
+        for objects in [0, 1, 2, 3, 7, 32, 200] {
+            let scene = random_scene(&mut rng, objects);
+            for _ in 0..2000 {
+                let ray = random_ray(&mut rng);
+                assert_same_hit(
+                    scene.intersect(&ray, 0.001, f32::INFINITY),
+                    scene.intersect_linear(&ray, 0.001, f32::INFINITY),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn refit_tracks_moved_objects() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut scene = random_scene(&mut rng, 50);
+        for sphere in &mut scene.spheres {
+            sphere.center += random_vec(&mut rng, 4.0);
+        }
+        for cube in &mut scene.cubes {
+            let offset = random_vec(&mut rng, 4.0);
+            cube.min += offset;
+            cube.max += offset;
+        }
+        scene.rebuild_bvh();
+
+        for _ in 0..2000 {
+            let ray = random_ray(&mut rng);
+            assert_same_hit(
+                scene.intersect(&ray, 0.001, 20.0),
+                scene.intersect_linear(&ray, 0.001, 20.0),
+            );
+        }
+    }
+
+    #[test]
+    fn coincident_objects_resolve_like_linear_scan() {
+        // Identical spheres give identical t values, so only the tie-break decides the hit
+        let mut scene = Scene::empty();
+        for i in 0..6 {
+            scene.spheres.push(Sphere {
+                center: Vec3::ZERO,
+                radius: 1.0,
+                material: Material { color: Vec3::splat(i as f32 / 6.0), ..Default::default() },
+                transform: Transform::default(),
+            });
+        }
+        scene.rebuild_bvh();
+
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z);
+        assert_same_hit(
+            scene.intersect(&ray, 0.001, f32::INFINITY),
+            scene.intersect_linear(&ray, 0.001, f32::INFINITY),
+        );
+    }
+}
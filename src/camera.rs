@@ -1,7 +1,8 @@
 use crate::math::{Ray, Transform};
 use glam::{Mat4, Vec3, Quat};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Camera {
     pub transform: Transform,
     pub fov: f32,
@@ -0,0 +1,181 @@
+//! Reconstruction filters and the film that samples are splatted onto.
+//!
+//! A sample adds its color to every pixel whose center lies within the filter's radius of
+//! where it landed, weighted by the filter at that distance. Each pixel is the weighted
+//! average of the samples around it. The box filter only reaches the pixel a sample lands in,
+//! which makes the pixel the plain average of its own samples.
+
+use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum FilterType {
+    /// Averages the samples inside each pixel. Sharp, but edges alias.
+    #[default]
+    Box,
+    /// Falls off linearly over one pixel.
+    Tent,
+    /// Smooth and slightly blurry.
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3, a compromise between blur and ringing.
+    Mitchell,
+    /// Windowed sinc over two pixels, the sharpest, with a faint ringing at edges.
+    Lanczos,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [Self::Box, Self::Tent, Self::Gaussian, Self::Mitchell, Self::Lanczos];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Box => "Box",
+            Self::Tent => "Tent",
+            Self::Gaussian => "Gaussian",
+            Self::Mitchell => "Mitchell-Netravali",
+            Self::Lanczos => "Lanczos",
+        }
+    }
+
+    /// Distance in pixels from a pixel center beyond which samples don't count.
+    pub fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Tent => 1.0,
+            Self::Gaussian => 1.5,
+            Self::Mitchell | Self::Lanczos => 2.0,
+        }
+    }
+
+    /// Weight of a sample `offset` pixels from a pixel center. Mitchell-Netravali and Lanczos
+    /// go negative a little way out, which sharpens edges.
+    pub fn weight(self, offset: Vec2) -> f32 {
+        self.evaluate(offset.x) * self.evaluate(offset.y)
+    }
+
+    fn evaluate(self, x: f32) -> f32 {
+        // Half-open, so a sample on the border of two box filtered pixels counts for one
+        if !(-self.radius()..self.radius()).contains(&x) {
+            return 0.0;
+        }
+        let x = x.abs();
+        match self {
+            Self::Box => 1.0,
+            Self::Tent => 1.0 - x,
+            Self::Gaussian => {
+                // Shifted down to reach zero at the radius instead of being cut off
+                const SIGMA: f32 = 0.5;
+                let gaussian = |x: f32| (-x * x / (2.0 * SIGMA * SIGMA)).exp();
+                gaussian(x) - gaussian(self.radius())
+            }
+            Self::Mitchell => {
+                const B: f32 = 1.0 / 3.0;
+                const C: f32 = 1.0 / 3.0;
+                let polynomial = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+                };
+                polynomial / 6.0
+            }
+            Self::Lanczos => sinc(x) * sinc(x / self.radius()),
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Filter-weighted sample sums for a rectangle of the image.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Film {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec4>, // Row-major. Weighted sum of sample colors in xyz, sum of the weights in w
+}
+
+impl Film {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            pixels: vec![Vec4::ZERO; (width * height) as usize],
+        }
+    }
+
+    /// Adds a sample that landed at `position`, in pixels from the top left corner of the
+    /// image, to the pixels of this film within `radius` of it. `filter` picks the filter
+    /// for each column of the image.
+    pub fn add_sample(&mut self, position: Vec2, color: Vec3, radius: f32, filter: impl Fn(u32) -> FilterType) {
+        // Pixel centers sit at half-integer positions
+        let first = (position - radius - 0.5).ceil().max(Vec2::new(self.x as f32, self.y as f32));
+        let last = (position + radius - 0.5).floor().min(Vec2::new((self.x + self.width) as f32, (self.y + self.height) as f32) - 1.0);
+        if first.x > last.x || first.y > last.y {
+            return;
+        }
+
+        for y in first.y as u32..=last.y as u32 {
+            for x in first.x as u32..=last.x as u32 {
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let weight = filter(x).weight(position - center);
+                if weight != 0.0 {
+                    let index = ((y - self.y) * self.width + x - self.x) as usize;
+                    self.pixels[index] += (color * weight).extend(weight);
+                }
+            }
+        }
+    }
+
+    /// Indices into the full image for each film pixel, in the order of `pixels`.
+    pub fn pixel_indices(&self, image_width: u32) -> impl Iterator<Item = usize> {
+        let Film { x, y, width, height, .. } = *self;
+        (y..y + height).flat_map(move |py| (x..x + width).map(move |px| (py * image_width + px) as usize))
+    }
+}
+
+/// The color of a pixel from its weighted sum. Black where no sample counted.
+pub fn resolve(sum: Vec4) -> Vec3 {
+    if sum.w > 0.0 {
+        sum.xyz() / sum.w
+    } else {
+        Vec3::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sample in the middle of a pixel counts fully there, and every filter gives the same
+    /// color back for a flat image however it weighs the samples.
+    #[test]
+    fn filters_reconstruct_flat_color() {
+        for filter in FilterType::ALL {
+            assert!(filter.weight(Vec2::ZERO) > 0.0);
+            assert_eq!(filter.weight(Vec2::new(filter.radius(), 0.0)), 0.0);
+
+            let mut film = Film::new(0, 0, 8, 8);
+            for i in 0..256 {
+                let position = Vec2::new((i % 16) as f32 + 0.3, (i / 16) as f32 + 0.7) * 0.5;
+                film.add_sample(position, Vec3::new(0.2, 0.4, 0.8), filter.radius(), |_| filter);
+            }
+            for &sum in &film.pixels {
+                assert!((resolve(sum) - Vec3::new(0.2, 0.4, 0.8)).abs().max_element() < 1e-4, "{:?}", filter);
+            }
+        }
+
+        // The box filter keeps samples in their own pixel, even on its edge
+        let mut film = Film::new(0, 0, 2, 1);
+        film.add_sample(Vec2::new(1.0, 0.5), Vec3::ONE, FilterType::Box.radius(), |_| FilterType::Box);
+        assert_eq!(film.pixels, [Vec4::ZERO, Vec4::ONE]);
+    }
+}
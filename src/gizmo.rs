@@ -0,0 +1,334 @@
+//! Move, rotate and scale handles for the 3D view.
+//!
+//! The gizmo is painted with egui on top of the wireframe image, so it is never hidden by the
+//! geometry it manipulates. It edits a [`Transform`]; the app maps that to and from whatever
+//! is selected (an object, a light or the render camera).
+
+use crate::camera::Camera;
+use crate::math::{Ray, Transform};
+use egui::{Color32, Pos2, Rect, Stroke};
+use glam::{Quat, Vec3};
+
+/// Handles are this fraction of their distance to the view camera long, so they keep their size on screen.
+const HANDLE_SCALE: f32 = 0.2;
+
+/// How close (in points) the pointer has to be to a handle to grab it.
+const GRAB_DISTANCE: f32 = 8.0;
+
+/// Smallest scale a drag can shrink an axis to; zero would make the transform singular.
+const MIN_SCALE: f32 = 0.01;
+
+/// Segments of the rotation rings.
+const RING_SEGMENTS: usize = 48;
+
+const AXIS_COLORS: [Color32; 3] = [Color32::from_rgb(230, 60, 60), Color32::from_rgb(60, 200, 60), Color32::from_rgb(70, 110, 240)];
+const ACTIVE_COLOR: Color32 = Color32::from_rgb(255, 220, 0);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+/// Increments edits snap to while snapping is enabled.
+pub struct Snapping {
+    pub enabled: bool,
+    pub translate: f32, // World units; the dragged coordinate snaps to multiples of this
+    pub rotate: f32,    // Degrees
+    pub scale: f32,
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            translate: 0.25,
+            rotate: 15.0,
+            scale: 0.1,
+        }
+    }
+}
+
+/// A handle being dragged, with the transform and pointer position it started from.
+struct Drag {
+    axis: usize,
+    start: Transform,
+    grab: Vec3, // Translate/Scale: (distance along the axis, 0, 0). Rotate: pivot-to-pointer direction.
+}
+
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    pub snapping: Snapping,
+    drag: Option<Drag>,
+    hovered_axis: Option<usize>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            snapping: Snapping::default(),
+            drag: None,
+            hovered_axis: None,
+        }
+    }
+}
+
+/// Projects world positions onto the 3D view image.
+struct Projection<'a> {
+    camera: &'a Camera,
+    rect: Rect,
+}
+
+impl Projection<'_> {
+    fn to_screen(&self, point: Vec3) -> Option<Pos2> {
+        project(self.camera, self.rect, point)
+    }
+
+    /// Ray through a screen position, the inverse of `to_screen`.
+    fn ray(&self, pos: Pos2) -> Ray {
+        let u = (pos.x - self.rect.left()) / self.rect.width();
+        let v = 1.0 - (pos.y - self.rect.top()) / self.rect.height();
+        self.camera.get_ray(u, v)
+    }
+}
+
+impl Gizmo {
+    /// Whether the pointer is on a handle or dragging one, so clicks should not select.
+    pub fn is_active(&self) -> bool {
+        self.drag.is_some() || self.hovered_axis.is_some()
+    }
+
+    /// Mode actually shown for a selection that only supports `modes`.
+    fn effective_mode(&self, modes: &[GizmoMode]) -> GizmoMode {
+        if modes.contains(&self.mode) {
+            self.mode
+        } else {
+            GizmoMode::Translate
+        }
+    }
+
+    /// Paints the gizmo around `transform` on the 3D view `response` (seen through `view_camera`)
+    /// and handles dragging its handles. `modes` are the edits the selection supports.
+    /// Returns the edited transform while a handle is being dragged.
+    pub fn interact(
+        &mut self,
+        ui: &egui::Ui,
+        response: &egui::Response,
+        view_camera: &Camera,
+        transform: &Transform,
+        modes: &[GizmoMode],
+    ) -> Option<Transform> {
+        let projection = Projection { camera: view_camera, rect: response.rect };
+        let mode = self.effective_mode(modes);
+        let pivot = transform.position;
+        let length = (pivot - view_camera.transform.position).length() * HANDLE_SCALE;
+        let axes = self.axes(mode, transform);
+
+        if response.drag_stopped() {
+            self.drag = None;
+        }
+
+        // Grab the handle the drag started on. The pointer has moved a little by now, so hit test the press position.
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            let press = ui.input(|input| input.pointer.press_origin());
+            self.drag = press.and_then(|press| {
+                let axis = self.handle_at(&projection, mode, pivot, axes, length, press)?;
+                let grab = self.grab(&projection.ray(press), mode, pivot, axes[axis])?;
+                Some(Drag { axis, start: *transform, grab })
+            });
+        }
+
+        self.hovered_axis = match response.hover_pos() {
+            Some(pos) if self.drag.is_none() => self.handle_at(&projection, mode, pivot, axes, length, pos),
+            _ => None,
+        };
+
+        let edited = match (&self.drag, response.interact_pointer_pos()) {
+            (Some(drag), Some(pos)) if response.dragged_by(egui::PointerButton::Primary) => {
+                self.drag_to(drag, &projection.ray(pos), mode, length)
+            }
+            _ => None,
+        };
+
+        let active_axis = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered_axis);
+        // Draw around the edited transform so the handles follow the drag
+        let shown = edited.unwrap_or(*transform);
+        self.paint(ui.painter_at(response.rect), &projection, mode, &shown, length, active_axis);
+
+        edited
+    }
+
+    /// Handle directions: world axes for moving and rotating, the object's own axes for scaling
+    /// since scale applies in object space.
+    fn axes(&self, mode: GizmoMode, transform: &Transform) -> [Vec3; 3] {
+        match mode {
+            GizmoMode::Scale => [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| transform.rotation * axis),
+            GizmoMode::Translate | GizmoMode::Rotate => [Vec3::X, Vec3::Y, Vec3::Z],
+        }
+    }
+
+    /// Screen polyline of the handle for `axis`.
+    fn handle_points(projection: &Projection, mode: GizmoMode, pivot: Vec3, axis: Vec3, length: f32) -> Vec<Pos2> {
+        let points: Vec<Vec3> = match mode {
+            GizmoMode::Translate | GizmoMode::Scale => vec![pivot, pivot + axis * length],
+            GizmoMode::Rotate => {
+                let tangent = axis.any_orthonormal_vector();
+                let bitangent = axis.cross(tangent);
+                (0..=RING_SEGMENTS)
+                    .map(|i| {
+                        let angle = i as f32 / RING_SEGMENTS as f32 * std::f32::consts::TAU;
+                        pivot + (tangent * angle.cos() + bitangent * angle.sin()) * length
+                    })
+                    .collect()
+            }
+        };
+        points.into_iter().filter_map(|point| projection.to_screen(point)).collect()
+    }
+
+    /// The axis whose handle is under `pos`, if any.
+    fn handle_at(&self, projection: &Projection, mode: GizmoMode, pivot: Vec3, axes: [Vec3; 3], length: f32, pos: Pos2) -> Option<usize> {
+        (0..3)
+            .map(|i| {
+                let points = Self::handle_points(projection, mode, pivot, axes[i], length);
+                let distance = points
+                    .windows(2)
+                    .map(|segment| distance_to_segment(pos, segment[0], segment[1]))
+                    .fold(f32::INFINITY, f32::min);
+                (i, distance)
+            })
+            .filter(|&(_, distance)| distance < GRAB_DISTANCE)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    /// Where `ray` grabs the handle along `axis`, see [`Drag::grab`].
+    fn grab(&self, ray: &Ray, mode: GizmoMode, pivot: Vec3, axis: Vec3) -> Option<Vec3> {
+        match mode {
+            GizmoMode::Translate | GizmoMode::Scale => Some(Vec3::new(closest_on_axis(ray, pivot, axis)?, 0.0, 0.0)),
+            GizmoMode::Rotate => Some((hit_plane(ray, pivot, axis)? - pivot).normalize_or_zero()),
+        }
+    }
+
+    fn drag_to(&self, drag: &Drag, ray: &Ray, mode: GizmoMode, length: f32) -> Option<Transform> {
+        let start = drag.start;
+        let axis = self.axes(mode, &start)[drag.axis];
+        let snapping = &self.snapping;
+        let mut transform = start;
+
+        match mode {
+            GizmoMode::Translate => {
+                let moved = closest_on_axis(ray, start.position, axis)? - drag.grab.x;
+                transform.position[drag.axis] += moved;
+                if snapping.enabled {
+                    transform.position[drag.axis] = snap(transform.position[drag.axis], snapping.translate);
+                }
+            }
+            GizmoMode::Rotate => {
+                let to_pointer = (hit_plane(ray, start.position, axis)? - start.position).normalize_or_zero();
+                let mut angle = axis.dot(drag.grab.cross(to_pointer)).atan2(drag.grab.dot(to_pointer));
+                if snapping.enabled {
+                    angle = snap(angle.to_degrees(), snapping.rotate).to_radians();
+                }
+                transform.rotation = (Quat::from_axis_angle(axis, angle) * start.rotation).normalize();
+            }
+            GizmoMode::Scale => {
+                // Dragging one handle length outwards doubles the scale
+                let moved = closest_on_axis(ray, start.position, axis)? - drag.grab.x;
+                let mut scale = start.scale[drag.axis] * (1.0 + moved / length);
+                if snapping.enabled {
+                    scale = snap(scale, snapping.scale);
+                }
+                transform.scale[drag.axis] = scale.max(MIN_SCALE);
+            }
+        }
+        Some(transform)
+    }
+
+    fn paint(&self, painter: egui::Painter, projection: &Projection, mode: GizmoMode, transform: &Transform, length: f32, active_axis: Option<usize>) {
+        let axes = self.axes(mode, transform);
+        for (i, axis) in axes.into_iter().enumerate() {
+            let color = if active_axis == Some(i) { ACTIVE_COLOR } else { AXIS_COLORS[i] };
+            let points = Self::handle_points(projection, mode, transform.position, axis, length);
+            painter.add(egui::Shape::line(points.clone(), Stroke::new(2.5, color)));
+
+            // Tips tell the modes apart: dots for moving, squares for scaling
+            match (mode, points.last()) {
+                (GizmoMode::Translate, Some(&tip)) => {
+                    painter.circle_filled(tip, 5.0, color);
+                }
+                (GizmoMode::Scale, Some(&tip)) => {
+                    painter.rect_filled(Rect::from_center_size(tip, egui::vec2(9.0, 9.0)), 0.0, color);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Where `point` appears in `rect`, an image of what `camera` sees. None if it is behind the camera.
+pub fn project(camera: &Camera, rect: Rect, point: Vec3) -> Option<Pos2> {
+    let view_proj = camera.projection_matrix() * camera.view_matrix();
+    let clip = view_proj * point.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    Some(Pos2::new(
+        rect.left() + (ndc.x + 1.0) / 2.0 * rect.width(),
+        rect.top() + (1.0 - ndc.y) / 2.0 * rect.height(),
+    ))
+}
+
+fn snap(value: f32, increment: f32) -> f32 {
+    if increment > 0.0 {
+        (value / increment).round() * increment
+    } else {
+        value
+    }
+}
+
+fn distance_to_segment(pos: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let t = ((pos - a).dot(ab) / ab.length_sq().max(f32::EPSILON)).clamp(0.0, 1.0);
+    pos.distance(a + ab * t)
+}
+
+/// Distance along the line through `origin` in direction `axis` of its closest point to `ray`.
+/// None if the ray runs parallel to the axis.
+fn closest_on_axis(ray: &Ray, origin: Vec3, axis: Vec3) -> Option<f32> {
+    let w = origin - ray.origin;
+    let b = axis.dot(ray.direction);
+    let denominator = 1.0 - b * b;
+    if denominator < 1e-6 {
+        return None;
+    }
+    Some((b * ray.direction.dot(w) - axis.dot(w)) / denominator)
+}
+
+/// Where `ray` crosses the plane through `point` with `normal`. None if it runs along the plane.
+fn hit_plane(ray: &Ray, point: Vec3, normal: Vec3) -> Option<Vec3> {
+    let denominator = normal.dot(ray.direction);
+    if denominator.abs() < 1e-4 {
+        return None;
+    }
+    Some(ray.at((point - ray.origin).dot(normal) / denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_point_on_axis_follows_the_pointer_ray() {
+        // Looking down at the X axis from above: the ray through x = 2 is closest at 2
+        let ray = Ray::new(Vec3::new(2.0, 5.0, 0.0), -Vec3::Y);
+        assert!((closest_on_axis(&ray, Vec3::ZERO, Vec3::X).unwrap() - 2.0).abs() < 1e-5);
+        assert!(closest_on_axis(&ray, Vec3::ZERO, Vec3::Y).is_none());
+
+        assert_eq!(snap(0.37, 0.25), 0.25);
+        assert_eq!(snap(-22.0, 15.0), -15.0);
+        assert_eq!(snap(0.37, 0.0), 0.37);
+    }
+}
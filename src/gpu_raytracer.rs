@@ -0,0 +1,576 @@
+//! Compute-shader backend for the raytraced image.
+//!
+//! `gpu_raytracer.wgsl` ports `Raytracer::trace_ray` and `Raytracer::trace_pathtrace`. The scene
+//! is uploaded to storage buffers for every pass and the shader writes per-pixel sample sums,
+//! which feed the same [`Accumulator`] as box filtered samples. The CPU backend's samplers
+//! and reconstruction filters are not ported.
+//! The GPU walks the objects linearly instead of using the BVH, which is fine for scenes of
+//! this size. Triangle meshes, area lights, emission, environment maps and principled materials
+//! are not ported, see [`GpuRaytracer::unsupported`].
+
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::math::Transform;
+use crate::primitives::{LightType, LightUnits, Material, MaterialType};
+use crate::raytracer::{Accumulator, Raytracer, RenderMode};
+use crate::scene::Scene;
+use crate::sky::Sky;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use std::sync::{Arc, Mutex};
+use wgpu::util::DeviceExt;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Storage buffers the shader binds: materials, spheres, cubes, planes, lights and the output.
+const STORAGE_BUFFER_COUNT: u32 = 6;
+
+// Layouts below mirror the WGSL structs, including their vec3 alignment padding
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuParams {
+    camera_origin: [f32; 4],
+    camera_lower_left: [f32; 4],
+    camera_horizontal: [f32; 4],
+    camera_vertical: [f32; 4],
+    background: [f32; 4],
+    sky_sun_direction: [f32; 4],
+    sky_zenith: [f32; 4],
+    sky_perez: [[f32; 4]; 5],
+    width: u32,
+    height: u32,
+    max_bounces: u32,
+    samples_per_pixel: u32,
+    mode: u32,
+    pass_index: u32,
+    sphere_count: u32,
+    cube_count: u32,
+    plane_count: u32,
+    light_count: u32,
+    seed: u32,
+    light_units: u32,
+    roulette_depth: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuMaterial {
+    color: [f32; 3],
+    specular: f32,
+    shininess: f32,
+    reflectivity: f32,
+    roughness: f32,
+    ior: f32,
+    mat_type: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuSphere {
+    world_to_object: [[f32; 4]; 4],
+    center: [f32; 3],
+    radius: f32,
+    material: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuCube {
+    world_to_object: [[f32; 4]; 4],
+    min: [f32; 3],
+    material: u32,
+    max: [f32; 3],
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuPlane {
+    world_to_object: [[f32; 4]; 4],
+    point: [f32; 3],
+    material: u32,
+    normal: [f32; 3],
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuLight {
+    position: [f32; 3],
+    light_type: u32,
+    direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [u32; 3],
+}
+
+impl GpuParams {
+    fn new(camera: &Camera, raytracer: &Raytracer, scene: &Scene, pass: u32) -> Self {
+        // Same viewport as `Camera::get_ray`, with the direction to the lower left corner precomputed
+        let viewport_height = 2.0 * (camera.fov.to_radians() / 2.0).tan();
+        let viewport_width = camera.aspect_ratio * viewport_height;
+        let horizontal = camera.transform.right() * viewport_width;
+        let vertical = camera.transform.up() * viewport_height;
+        let lower_left = camera.transform.forward() - horizontal / 2.0 - vertical / 2.0;
+
+        let sky = match scene.background {
+            Background::Sky(sky) => *sky.model(),
+            _ => *Sky::default().model(), // Unused
+        };
+
+        Self {
+            camera_origin: camera.transform.position.extend(0.0).to_array(),
+            camera_lower_left: lower_left.extend(0.0).to_array(),
+            camera_horizontal: horizontal.extend(0.0).to_array(),
+            camera_vertical: vertical.extend(0.0).to_array(),
+            background: match scene.background {
+                Background::Color(color) => color.extend(1.0).to_array(),
+                Background::Sky(_) => [0.0, 0.0, 0.0, 2.0],
+                Background::Gradient | Background::EnvironmentMap(_) => [0.0; 4],
+            },
+            sky_sun_direction: sky.sun_direction.extend(0.0).to_array(),
+            sky_zenith: sky.zenith.extend(0.0).to_array(),
+            sky_perez: sky.perez.map(|coefficients| coefficients.extend(0.0).to_array()),
+            width: raytracer.width,
+            height: raytracer.height,
+            max_bounces: raytracer.max_bounces,
+            samples_per_pixel: raytracer.samples_per_pixel,
+            mode: match raytracer.mode {
+                RenderMode::Raytracing => 0,
+                RenderMode::Pathtracing => 1,
+            },
+            pass_index: pass,
+            sphere_count: scene.spheres.len() as u32,
+            cube_count: scene.cubes.len() as u32,
+            plane_count: scene.planes.len() as u32,
+            light_count: scene.lights.len() as u32,
+            seed: (raytracer.seed ^ (raytracer.seed >> 32)) as u32,
+            light_units: match scene.light_units {
+                LightUnits::Legacy => 0,
+                LightUnits::Radiometric => 1,
+            },
+            roulette_depth: raytracer.roulette_depth,
+            _padding: [0; 3],
+        }
+    }
+}
+
+impl From<&Material> for GpuMaterial {
+    fn from(material: &Material) -> Self {
+        Self {
+            color: material.color.to_array(),
+            specular: material.specular,
+            shininess: material.shininess,
+            reflectivity: material.reflectivity,
+            roughness: material.roughness,
+            ior: material.ior,
+            mat_type: match material.mat_type {
+                MaterialType::Lambertian => 0,
+                MaterialType::Metal => 1,
+                MaterialType::Dielectric => 2,
+                // Scenes with these are rendered on the CPU
+                MaterialType::Principled => 0,
+            },
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// The shader moves rays into object space with this, and normals out with its transpose.
+fn world_to_object(transform: &Transform) -> [[f32; 4]; 4] {
+    transform.to_mat4().inverse().to_cols_array_2d()
+}
+
+/// Result of `Buffer::map_async`, filled in by its callback.
+type MapResult = Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>;
+
+/// A submitted pass whose output is being copied back to the CPU.
+struct PendingPass {
+    staging: wgpu::Buffer,
+    mapped: MapResult,
+    samples: u32,
+    pass: u32,
+}
+
+pub struct GpuRaytracer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pending: Option<PendingPass>,
+}
+
+impl GpuRaytracer {
+    /// Compute shaders are missing on WebGL, and the shader needs more storage buffers than
+    /// the most conservative limits allow.
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && device.limits().max_storage_buffers_per_shader_stage >= STORAGE_BUFFER_COUNT
+    }
+
+    /// What in `scene` the shader can't render, None if it handles everything. Meshes need
+    /// the BVH to be fast, and the rest only exists on the CPU, so such scenes are rendered there.
+    pub fn unsupported(scene: &Scene) -> Option<&'static str> {
+        if !scene.meshes.is_empty() {
+            Some("meshes")
+        } else if scene.lights.iter().any(|light| light.light_type.is_area()) {
+            Some("area lights")
+        } else if scene.materials().any(|material| material.emission != Vec3::ZERO) {
+            Some("glowing materials")
+        } else if scene.materials().any(|material| material.mat_type == MaterialType::Principled) {
+            Some("principled materials")
+        } else if matches!(scene.background, Background::EnvironmentMap(_)) {
+            Some("environment maps")
+        } else {
+            None
+        }
+    }
+
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Raytracer Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_raytracer.wgsl").into()),
+        });
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GPU Raytracer Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, true),
+                storage_entry(4, true),
+                storage_entry(5, true),
+                storage_entry(6, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Raytracer Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Raytracer Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Self {
+            device,
+            queue,
+            pipeline,
+            bind_group_layout,
+            pending: None,
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Drops the pass being read back, if any. Work already submitted still runs.
+    pub fn cancel(&mut self) {
+        self.pending = None;
+    }
+
+    /// Submits one pass of `samples_per_pixel` samples. A pass that is still being read back
+    /// is abandoned. `pass` 0 starts a new image; later passes add to the accumulator.
+    pub fn start(&mut self, scene: &Scene, camera: &Camera, raytracer: &Raytracer, pass: u32) {
+        let device = &self.device;
+
+        let mut materials = Vec::new();
+        let mut add_material = |material: &Material| {
+            materials.push(GpuMaterial::from(material));
+            materials.len() as u32 - 1
+        };
+
+        let spheres: Vec<GpuSphere> = scene
+            .spheres
+            .iter()
+            .map(|sphere| GpuSphere {
+                world_to_object: world_to_object(&sphere.transform),
+                center: sphere.center.to_array(),
+                radius: sphere.radius,
+                material: add_material(&sphere.material),
+                _padding: [0; 3],
+            })
+            .collect();
+        let cubes: Vec<GpuCube> = scene
+            .cubes
+            .iter()
+            .map(|cube| GpuCube {
+                world_to_object: world_to_object(&cube.transform),
+                min: cube.min.to_array(),
+                material: add_material(&cube.material),
+                max: cube.max.to_array(),
+                _padding: 0,
+            })
+            .collect();
+        let planes: Vec<GpuPlane> = scene
+            .planes
+            .iter()
+            .map(|plane| GpuPlane {
+                world_to_object: world_to_object(&plane.transform),
+                point: plane.point.to_array(),
+                material: add_material(&plane.material),
+                normal: plane.normal.to_array(),
+                _padding: 0,
+            })
+            .collect();
+        let lights: Vec<GpuLight> = scene
+            .lights
+            .iter()
+            .map(|light| GpuLight {
+                position: light.position.to_array(),
+                light_type: match light.light_type {
+                    LightType::Point => 0,
+                    LightType::Directional => 1,
+                    LightType::Spot => 2,
+                    LightType::Rectangle | LightType::Disk | LightType::Sphere => {
+                        unreachable!("area lights are rendered on the CPU, see unsupported")
+                    }
+                },
+                direction: light.direction.to_array(),
+                intensity: light.intensity,
+                color: light.color.to_array(),
+                cos_inner: light.inner_angle.to_radians().cos(),
+                cos_outer: light.outer_angle.to_radians().cos(),
+                _padding: [0; 3],
+            })
+            .collect();
+
+        let params = GpuParams::new(camera, raytracer, scene, pass);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Raytracer Params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let materials_buffer = storage_buffer(device, "GPU Raytracer Materials", &materials);
+        let spheres_buffer = storage_buffer(device, "GPU Raytracer Spheres", &spheres);
+        let cubes_buffer = storage_buffer(device, "GPU Raytracer Cubes", &cubes);
+        let planes_buffer = storage_buffer(device, "GPU Raytracer Planes", &planes);
+        let lights_buffer = storage_buffer(device, "GPU Raytracer Lights", &lights);
+
+        let output_size = (raytracer.width * raytracer.height) as u64 * std::mem::size_of::<[f32; 4]>() as u64;
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Raytracer Output"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Raytracer Staging"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GPU Raytracer Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: materials_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: spheres_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: cubes_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: planes_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: lights_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 6, resource: output_buffer.as_entire_binding() },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GPU Raytracer Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GPU Raytracer Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                raytracer.width.div_ceil(WORKGROUP_SIZE),
+                raytracer.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging, 0, output_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        // Readback has to be asynchronous, the browser can't block on the GPU
+        let mapped = MapResult::default();
+        let callback_result = mapped.clone();
+        staging.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            *callback_result.lock().unwrap() = Some(result);
+        });
+
+        self.pending = Some(PendingPass {
+            staging,
+            mapped,
+            samples: raytracer.samples_per_pixel,
+            pass,
+        });
+    }
+
+    /// Adds the running pass to `accumulator` once it has been read back.
+    /// Returns true if the accumulator changed.
+    pub fn poll(&mut self, accumulator: &mut Accumulator) -> bool {
+        self.device.poll(wgpu::Maintain::Poll);
+
+        let Some(pending) = &self.pending else {
+            return false;
+        };
+        let Some(result) = pending.mapped.lock().unwrap().take() else {
+            return false;
+        };
+        let pending = self.pending.take().expect("checked above");
+
+        if let Err(err) = result {
+            log::error!("Failed to read back GPU render: {}", err);
+            return false;
+        }
+
+        let sums = read_sums(&pending.staging);
+        // Drop a pass that no longer matches the image, e.g. after a reset or resize
+        if sums.len() != accumulator.buffer.len() || accumulator.sample_count != pending.pass {
+            return false;
+        }
+        // Box filtered: every sample counts once, for its own pixel
+        for (sum, sample) in accumulator.buffer.iter_mut().zip(sums) {
+            *sum += sample.extend(pending.samples as f32);
+        }
+        accumulator.sample_count += pending.samples;
+        true
+    }
+
+    /// Renders one pass and waits for it, returning per-pixel sample sums like
+    /// `Raytracer::render_tile`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_blocking(&mut self, scene: &Scene, camera: &Camera, raytracer: &Raytracer, pass: u32) -> Vec<Vec3> {
+        self.start(scene, camera, raytracer, pass);
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let pending = self.pending.take().expect("pass was just started");
+        let result = pending.mapped.lock().unwrap().take();
+        match result {
+            Some(Ok(())) => read_sums(&pending.staging),
+            Some(Err(err)) => panic!("Failed to read back GPU render: {}", err),
+            None => panic!("GPU render did not finish"),
+        }
+    }
+}
+
+/// Read-only storage buffer holding `items`. Bindings can't be empty, so an empty
+/// slice still gets room for one (unused) element.
+fn storage_buffer<T: Pod>(device: &wgpu::Device, label: &str, items: &[T]) -> wgpu::Buffer {
+    let placeholder = [T::zeroed()];
+    let contents = if items.is_empty() { &placeholder[..] } else { items };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(contents),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
+fn read_sums(staging: &wgpu::Buffer) -> Vec<Vec3> {
+    let sums = {
+        let data = staging.slice(..).get_mapped_range();
+        let texels: &[[f32; 4]] = bytemuck::cast_slice(&data);
+        texels.iter().map(|&[r, g, b, _]| Vec3::new(r, g, b)).collect()
+    };
+    staging.unmap();
+    sums
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn gpu() -> Option<GpuRaytracer> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()?;
+        if !GpuRaytracer::is_supported(&adapter, &device) {
+            return None;
+        }
+        Some(GpuRaytracer::new(Arc::new(device), Arc::new(queue)))
+    }
+
+    /// Root mean square difference of two images of sample sums, after clamping to display range.
+    fn rmse(a: &[Vec3], b: &[Vec3], samples: u32) -> f32 {
+        let display = |sum: Vec3| (sum / samples as f32).clamp(Vec3::ZERO, Vec3::ONE);
+        let total: f32 = a.iter().zip(b).map(|(&a, &b)| (display(a) - display(b)).length_squared()).sum();
+        (total / (a.len() * 3) as f32).sqrt()
+    }
+
+    #[test]
+    fn matches_cpu_reference() {
+        // Skipped on machines without an adapter, unless REQUIRE_GPU is set so that a machine
+        // meant to test the backend can't pass without running it
+        let Some(mut gpu) = gpu() else {
+            assert!(std::env::var_os("REQUIRE_GPU").is_none(), "No GPU adapter with compute support");
+            eprintln!("No GPU adapter with compute support, skipping GPU backend test");
+            return;
+        };
+
+        let mut scene = Scene::default();
+        scene.rebuild_bvh();
+        let mut camera = Camera::default();
+        camera.look_at(Vec3::ZERO);
+
+        for (mode, samples, tolerance) in [(RenderMode::Raytracing, 64, 0.03), (RenderMode::Pathtracing, 64, 0.05)] {
+            let raytracer = Raytracer {
+                width: 64,
+                height: 48,
+                samples_per_pixel: samples,
+                mode,
+                ..Default::default()
+            };
+
+            // Both backends jitter and sample differently, so compare converged images
+            let mut cpu = Accumulator::new(raytracer.width, raytracer.height);
+            raytracer.accumulate(&scene, &camera, &mut cpu);
+            let gpu_sums = gpu.render_blocking(&scene, &camera, &raytracer, 0);
+
+            let cpu_sums: Vec<Vec3> = cpu.buffer.iter().map(|sum| sum.truncate()).collect();
+            let error = rmse(&cpu_sums, &gpu_sums, samples);
+            assert!(error < tolerance, "{:?}: RMSE {} exceeds {}", mode, error, tolerance);
+        }
+    }
+}
@@ -0,0 +1,550 @@
+// Compute-shader port of `Raytracer::trace_ray` and `Raytracer::trace_pathtrace`.
+// Each invocation traces `samples_per_pixel` samples through one pixel and writes their sum.
+
+struct Params {
+    // xyz only, w keeps the Rust side free of vec3 alignment padding
+    camera_origin: vec4<f32>,
+    camera_lower_left: vec4<f32>, // Direction to the lower left viewport corner
+    camera_horizontal: vec4<f32>,
+    camera_vertical: vec4<f32>,
+    background: vec4<f32>, // w: 0 = the sky gradient, 1 = a solid color in xyz, 2 = the physical sky
+    // `SkyModel`, channels Y, x and y in xyz
+    sky_sun_direction: vec4<f32>,
+    sky_zenith: vec4<f32>,
+    sky_perez: array<vec4<f32>, 5>,
+    width: u32,
+    height: u32,
+    max_bounces: u32,
+    samples_per_pixel: u32,
+    mode: u32, // 0 = Raytracing, 1 = Pathtracing
+    pass_index: u32,
+    sphere_count: u32,
+    cube_count: u32,
+    plane_count: u32,
+    light_count: u32,
+    seed: u32, // `Raytracer::seed` folded to 32 bits
+    light_units: u32, // 0 = Legacy, 1 = Radiometric
+    roulette_depth: u32,
+};
+
+struct Material {
+    color: vec3<f32>,
+    specular: f32,
+    shininess: f32,
+    reflectivity: f32,
+    roughness: f32,
+    ior: f32,
+    mat_type: u32, // 0 = Lambertian, 1 = Metal, 2 = Dielectric
+};
+
+// Every object is intersected in object space, like the `Intersectable` impls on the CPU
+
+struct Sphere {
+    world_to_object: mat4x4<f32>,
+    center: vec3<f32>,
+    radius: f32,
+    material: u32,
+};
+
+struct Cube {
+    world_to_object: mat4x4<f32>,
+    min: vec3<f32>,
+    material: u32,
+    max: vec3<f32>,
+};
+
+struct Plane {
+    world_to_object: mat4x4<f32>,
+    point: vec3<f32>,
+    material: u32,
+    normal: vec3<f32>,
+};
+
+struct Light {
+    position: vec3<f32>,
+    light_type: u32, // 0 = Point, 1 = Directional, 2 = Spot
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    cos_inner: f32, // Spot cone, full brightness inside cos_inner and dark outside cos_outer
+    cos_outer: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> materials: array<Material>;
+@group(0) @binding(2) var<storage, read> spheres: array<Sphere>;
+@group(0) @binding(3) var<storage, read> cubes: array<Cube>;
+@group(0) @binding(4) var<storage, read> planes: array<Plane>;
+@group(0) @binding(5) var<storage, read> lights: array<Light>;
+@group(0) @binding(6) var<storage, read_write> output: array<vec4<f32>>;
+
+const LAMBERTIAN: u32 = 0u;
+const METAL: u32 = 1u;
+const DIELECTRIC: u32 = 2u;
+const DIRECTIONAL: u32 = 1u;
+const SPOT: u32 = 2u;
+const RADIOMETRIC: u32 = 1u;
+const PI: f32 = 3.14159265;
+const T_MIN: f32 = 0.001;
+const INFINITY: f32 = 3.4e38;
+// Same as brdf.rs: smoother metals are mirrors, and alpha never gets narrower than at this roughness
+const SHARP_ROUGHNESS: f32 = 0.03;
+
+// PCG hash based generator, one state per invocation
+var<private> rng_state: u32;
+
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random_f32() -> f32 {
+    rng_state = pcg_hash(rng_state);
+    return f32(rng_state >> 8u) / 16777216.0;
+}
+
+fn random_unit_vector() -> vec3<f32> {
+    let z = random_f32() * 2.0 - 1.0;
+    let phi = random_f32() * 6.28318530718;
+    let r = sqrt(max(0.0, 1.0 - z * z));
+    return vec3<f32>(r * cos(phi), r * sin(phi), z);
+}
+
+struct Hit {
+    t: f32,
+    point: vec3<f32>,
+    normal: vec3<f32>,
+    material: u32,
+};
+
+// Ray in object space. The direction is not renormalized, so t is the same in both spaces.
+struct LocalRay {
+    origin: vec3<f32>,
+    direction: vec3<f32>,
+};
+
+fn to_object_space(world_to_object: mat4x4<f32>, origin: vec3<f32>, direction: vec3<f32>) -> LocalRay {
+    return LocalRay((world_to_object * vec4<f32>(origin, 1.0)).xyz, (world_to_object * vec4<f32>(direction, 0.0)).xyz);
+}
+
+// Normals transform with the inverse transpose of the object-to-world matrix
+fn normal_to_world(world_to_object: mat4x4<f32>, normal: vec3<f32>) -> vec3<f32> {
+    return normalize((transpose(world_to_object) * vec4<f32>(normal, 0.0)).xyz);
+}
+
+fn intersect_sphere(sphere: Sphere, origin: vec3<f32>, direction: vec3<f32>, t_max: f32) -> f32 {
+    let oc = origin - sphere.center;
+    let a = dot(direction, direction);
+    let half_b = dot(oc, direction);
+    let c = dot(oc, oc) - sphere.radius * sphere.radius;
+    let discriminant = half_b * half_b - a * c;
+    if (discriminant < 0.0) {
+        return -1.0;
+    }
+    let sqrtd = sqrt(discriminant);
+    var root = (-half_b - sqrtd) / a;
+    if (root < T_MIN || t_max < root) {
+        root = (-half_b + sqrtd) / a;
+        if (root < T_MIN || t_max < root) {
+            return -1.0;
+        }
+    }
+    return root;
+}
+
+// Returns (t, axis, sign) with t < 0 on a miss
+fn intersect_cube(cube: Cube, origin: vec3<f32>, direction: vec3<f32>, t_max: f32) -> vec3<f32> {
+    var t_near = T_MIN;
+    var t_far = t_max;
+    var axis = -1.0;
+    var sign = 0.0;
+    for (var i = 0; i < 3; i++) {
+        if (abs(direction[i]) < 1e-6) {
+            if (origin[i] < cube.min[i] || origin[i] > cube.max[i]) {
+                return vec3<f32>(-1.0, 0.0, 0.0);
+            }
+        } else {
+            let t1 = (cube.min[i] - origin[i]) / direction[i];
+            let t2 = (cube.max[i] - origin[i]) / direction[i];
+            let t_min_i = min(t1, t2);
+            let t_max_i = max(t1, t2);
+            if (t_min_i > t_near) {
+                t_near = t_min_i;
+                axis = f32(i);
+                sign = select(-1.0, 1.0, t1 > t2);
+            }
+            if (t_max_i < t_far) {
+                t_far = t_max_i;
+            }
+            if (t_near > t_far) {
+                return vec3<f32>(-1.0, 0.0, 0.0);
+            }
+        }
+    }
+    return vec3<f32>(t_near, axis, sign);
+}
+
+fn intersect_scene(origin: vec3<f32>, direction: vec3<f32>, t_max: f32, hit: ptr<function, Hit>) -> bool {
+    var closest = t_max;
+    var found = false;
+
+    for (var i = 0u; i < params.sphere_count; i++) {
+        let sphere = spheres[i];
+        let local = to_object_space(sphere.world_to_object, origin, direction);
+        let t = intersect_sphere(sphere, local.origin, local.direction, closest);
+        if (t >= 0.0) {
+            closest = t;
+            found = true;
+            (*hit).t = t;
+            (*hit).point = origin + direction * t;
+            let local_normal = (local.origin + local.direction * t - sphere.center) / sphere.radius;
+            (*hit).normal = normal_to_world(sphere.world_to_object, local_normal);
+            (*hit).material = sphere.material;
+        }
+    }
+
+    for (var i = 0u; i < params.cube_count; i++) {
+        let cube = cubes[i];
+        let local = to_object_space(cube.world_to_object, origin, direction);
+        let result = intersect_cube(cube, local.origin, local.direction, closest);
+        if (result.x >= 0.0) {
+            closest = result.x;
+            found = true;
+            var normal = vec3<f32>(0.0);
+            if (result.y >= 0.0) {
+                normal[u32(result.y)] = result.z;
+                normal = normal_to_world(cube.world_to_object, normal);
+            }
+            (*hit).t = result.x;
+            (*hit).point = origin + direction * result.x;
+            (*hit).normal = normal;
+            (*hit).material = cube.material;
+        }
+    }
+
+    for (var i = 0u; i < params.plane_count; i++) {
+        let plane = planes[i];
+        let local = to_object_space(plane.world_to_object, origin, direction);
+        let denom = dot(plane.normal, local.direction);
+        if (abs(denom) > 1e-6) {
+            let t = dot(plane.point - local.origin, plane.normal) / denom;
+            if (t >= T_MIN && t <= closest) {
+                closest = t;
+                found = true;
+                (*hit).t = t;
+                (*hit).point = origin + direction * t;
+                (*hit).normal = normal_to_world(plane.world_to_object, plane.normal);
+                (*hit).material = plane.material;
+            }
+        }
+    }
+
+    return found;
+}
+
+fn occluded(origin: vec3<f32>, direction: vec3<f32>, distance: f32) -> bool {
+    var hit: Hit;
+    return intersect_scene(origin, direction, distance, &hit);
+}
+
+// Same as `SkyModel::radiance`
+fn physical_sky(direction: vec3<f32>) -> vec3<f32> {
+    let cos_theta = max(direction.y, 0.01);
+    let cos_gamma = dot(direction, params.sky_sun_direction.xyz);
+    let gamma = acos(clamp(cos_gamma, -1.0, 1.0));
+    let a = params.sky_perez[0].xyz;
+    let b = params.sky_perez[1].xyz;
+    let c = params.sky_perez[2].xyz;
+    let d = params.sky_perez[3].xyz;
+    let e = params.sky_perez[4].xyz;
+    let distribution = (vec3<f32>(1.0) + a * exp(b / cos_theta)) * (vec3<f32>(1.0) + c * exp(d * gamma) + e * cos_gamma * cos_gamma);
+    let yxy = params.sky_zenith.xyz * distribution;
+    if (yxy.z <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let xyz = vec3<f32>(yxy.y / yxy.z * yxy.x, yxy.x, (1.0 - yxy.y - yxy.z) / yxy.z * yxy.x);
+    let rgb = vec3<f32>(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    );
+    return max(rgb, vec3<f32>(0.0));
+}
+
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+    if (params.background.w == 1.0) {
+        return params.background.xyz;
+    }
+    if (params.background.w == 2.0) {
+        return physical_sky(normalize(direction));
+    }
+    let t = 0.5 * (normalize(direction).y + 1.0);
+    return vec3<f32>(1.0) * (1.0 - t) + vec3<f32>(0.5, 0.7, 1.0) * t;
+}
+
+// Light direction and distance as seen from `point`
+fn light_vector(light: Light, point: vec3<f32>) -> vec4<f32> {
+    if (light.light_type == DIRECTIONAL) {
+        return vec4<f32>(-normalize(light.direction), INFINITY);
+    }
+    let d = light.position - point;
+    return vec4<f32>(normalize(d), length(d));
+}
+
+// Color times intensity, dimmed towards the edge of a spot light's cone. Radiometric
+// intensities are brought to the scale of legacy ones, like `LightUnits::scale`.
+fn light_radiance(light: Light, to_light: vec4<f32>) -> vec3<f32> {
+    var scale = 1.0;
+    if (params.light_units == RADIOMETRIC) {
+        if (light.light_type == DIRECTIONAL) {
+            scale = 1.0 / PI;
+        } else {
+            scale = 1.0 / (4.0 * PI * PI * to_light.w * to_light.w);
+        }
+    }
+    if (light.light_type == SPOT) {
+        let cos_angle = dot(-to_light.xyz, normalize(light.direction));
+        // smoothstep, but defined for a hard edge where both angles are the same
+        let t = clamp((cos_angle - light.cos_outer) / max(light.cos_inner - light.cos_outer, 1e-6), 0.0, 1.0);
+        scale *= t * t * (3.0 - 2.0 * t);
+    }
+    return light.color * light.intensity * scale;
+}
+
+fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
+    var r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+    r0 = r0 * r0;
+    return r0 + (1.0 - r0) * pow(1.0 - cosine, 5.0);
+}
+
+// Reflects or refracts through a dielectric boundary, picking by Fresnel reflectance
+fn scatter_dielectric(direction: vec3<f32>, hit_normal: vec3<f32>, ior: f32) -> vec3<f32> {
+    let unit_direction = normalize(direction);
+    var normal = hit_normal;
+    var refraction_ratio = 1.0 / ior;
+    if (dot(unit_direction, hit_normal) >= 0.0) {
+        normal = -hit_normal;
+        refraction_ratio = ior;
+    }
+    let cos_theta = min(dot(-unit_direction, normal), 1.0);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+    if (cannot_refract || reflectance(cos_theta, refraction_ratio) > random_f32()) {
+        return reflect(unit_direction, normal);
+    }
+    return refract(unit_direction, normal, refraction_ratio);
+}
+
+// Same as `Ggx` in brdf.rs
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    return 2.0 * n_dot_x / (n_dot_x + sqrt(alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x));
+}
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+fn ggx_eval(normal: vec3<f32>, view: vec3<f32>, light: vec3<f32>, f0: vec3<f32>, alpha: f32) -> vec3<f32> {
+    let n_dot_v = dot(normal, view);
+    let n_dot_l = dot(normal, light);
+    if (n_dot_v <= 0.0 || n_dot_l <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let halfway = normalize(view + light);
+    let g = smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha);
+    return fresnel_schlick(f0, dot(view, halfway)) * (ggx_distribution(dot(normal, halfway), alpha) * g / (4.0 * n_dot_v));
+}
+
+struct GgxSample {
+    light: vec3<f32>,
+    weight: vec3<f32>, // Zero if the light direction would be below the surface
+};
+
+fn ggx_sample(normal: vec3<f32>, view: vec3<f32>, f0: vec3<f32>, alpha: f32) -> GgxSample {
+    var result: GgxSample;
+    result.weight = vec3<f32>(0.0);
+
+    // Same basis as glam's `any_orthonormal_pair`
+    let sign = select(-1.0, 1.0, normal.z >= 0.0);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = vec3<f32>(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+    let bitangent = vec3<f32>(b, sign + normal.y * normal.y * a, -normal.y);
+    let local_view = vec3<f32>(dot(view, tangent), dot(view, bitangent), dot(view, normal));
+    if (local_view.z <= 0.0) {
+        return result;
+    }
+
+    let stretched = normalize(vec3<f32>(alpha * local_view.x, alpha * local_view.y, local_view.z));
+    let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+    var t1 = vec3<f32>(1.0, 0.0, 0.0);
+    if (length2 > 0.0) {
+        t1 = vec3<f32>(-stretched.y, stretched.x, 0.0) / sqrt(length2);
+    }
+    let t2 = cross(stretched, t1);
+    let r = sqrt(random_f32());
+    let phi = 2.0 * PI * random_f32();
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + stretched.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+    let n_h = p1 * t1 + p2 * t2 + sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0)) * stretched;
+    let local_halfway = normalize(vec3<f32>(alpha * n_h.x, alpha * n_h.y, max(n_h.z, 0.0)));
+
+    let halfway = tangent * local_halfway.x + bitangent * local_halfway.y + normal * local_halfway.z;
+    result.light = reflect(-view, halfway);
+    let n_dot_l = dot(normal, result.light);
+    if (n_dot_l > 0.0) {
+        result.weight = fresnel_schlick(f0, dot(view, halfway)) * smith_g1(n_dot_l, alpha);
+    }
+    return result;
+}
+
+fn trace_ray(primary_origin: vec3<f32>, primary_direction: vec3<f32>) -> vec3<f32> {
+    var origin = primary_origin;
+    var direction = primary_direction;
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+
+    for (var depth = 0u; depth < params.max_bounces; depth++) {
+        var hit: Hit;
+        if (!intersect_scene(origin, direction, INFINITY, &hit)) {
+            color += throughput * sky(direction);
+            break;
+        }
+        let material = materials[hit.material];
+
+        // Glass only passes on what it refracts or reflects
+        if (material.mat_type == DIELECTRIC) {
+            origin = hit.point;
+            direction = normalize(scatter_dielectric(direction, hit.normal, material.ior));
+            continue;
+        }
+
+        let view_dir = -direction;
+        var local = material.color * 0.1;
+        for (var i = 0u; i < params.light_count; i++) {
+            let light = lights[i];
+            let to_light = light_vector(light, hit.point);
+            let radiance = light_radiance(light, to_light);
+            if (any(radiance > vec3<f32>(0.0)) && !occluded(hit.point, to_light.xyz, to_light.w)) {
+                let diff = max(dot(hit.normal, to_light.xyz), 0.0);
+                local += material.color * radiance * diff;
+                let reflect_dir = reflect(-to_light.xyz, hit.normal);
+                let spec = pow(max(dot(view_dir, reflect_dir), 0.0), material.shininess);
+                local += radiance * material.specular * spec;
+            }
+        }
+        color += throughput * local;
+
+        if (material.reflectivity <= 0.0) {
+            break;
+        }
+        throughput *= material.reflectivity;
+        origin = hit.point;
+        direction = reflect(direction, hit.normal);
+    }
+
+    return color;
+}
+
+fn trace_pathtrace(primary_origin: vec3<f32>, primary_direction: vec3<f32>) -> vec3<f32> {
+    var origin = primary_origin;
+    var direction = normalize(primary_direction);
+    var color = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+
+    for (var depth = 0u; depth < params.max_bounces; depth++) {
+        var hit: Hit;
+        if (!intersect_scene(origin, direction, INFINITY, &hit)) {
+            color += throughput * sky(direction);
+            break;
+        }
+        let material = materials[hit.material];
+
+        // Next event estimation for everything except perfect specular surfaces
+        let is_specular = material.mat_type == DIELECTRIC
+            || (material.mat_type == METAL && material.roughness < SHARP_ROUGHNESS);
+        let view = -direction;
+        let facing_normal = select(hit.normal, -hit.normal, dot(hit.normal, view) < 0.0);
+        let alpha = max(material.roughness * material.roughness, SHARP_ROUGHNESS * SHARP_ROUGHNESS);
+        if (!is_specular) {
+            for (var i = 0u; i < params.light_count; i++) {
+                let light = lights[i];
+                let to_light = light_vector(light, hit.point);
+                let radiance = light_radiance(light, to_light);
+                if (any(radiance > vec3<f32>(0.0)) && !occluded(hit.point, to_light.xyz, to_light.w)) {
+                    let cos_theta = max(dot(hit.normal, to_light.xyz), 0.0);
+                    if (material.mat_type == LAMBERTIAN) {
+                        color += throughput * material.color * radiance * cos_theta;
+                    } else {
+                        color += throughput * ggx_eval(facing_normal, view, to_light.xyz, material.color, alpha) * radiance * PI;
+                    }
+                }
+            }
+        }
+
+        var scatter_direction: vec3<f32>;
+        if (material.mat_type == LAMBERTIAN) {
+            scatter_direction = normalize(hit.normal + random_unit_vector());
+            throughput *= material.color;
+        } else if (material.mat_type == METAL && is_specular) {
+            scatter_direction = reflect(direction, facing_normal);
+            throughput *= fresnel_schlick(material.color, dot(facing_normal, view));
+        } else if (material.mat_type == METAL) {
+            let sample = ggx_sample(facing_normal, view, material.color, alpha);
+            if (all(sample.weight == vec3<f32>(0.0))) {
+                break; // Absorbed
+            }
+            scatter_direction = sample.light;
+            throughput *= sample.weight;
+        } else {
+            scatter_direction = scatter_dielectric(direction, hit.normal, material.ior);
+        }
+
+        origin = hit.point;
+        direction = normalize(scatter_direction);
+
+        // Russian roulette, as on the CPU
+        if (depth + 1u >= params.roulette_depth) {
+            let survival = min(max(throughput.x, max(throughput.y, throughput.z)), 1.0);
+            if (random_f32() >= survival) {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    return color;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let pixel = id.y * params.width + id.x;
+    rng_state = pcg_hash(pixel ^ pcg_hash((params.pass_index + 0x9e3779b9u) ^ params.seed));
+
+    var sum = vec3<f32>(0.0);
+    for (var s = 0u; s < params.samples_per_pixel; s++) {
+        let u = (f32(id.x) + random_f32()) / f32(params.width);
+        let v = 1.0 - (f32(id.y) + random_f32()) / f32(params.height); // Flip Y
+        let direction = params.camera_lower_left.xyz + params.camera_horizontal.xyz * u + params.camera_vertical.xyz * v;
+
+        if (params.mode == 0u) {
+            sum += trace_ray(params.camera_origin.xyz, normalize(direction));
+        } else {
+            sum += trace_pathtrace(params.camera_origin.xyz, normalize(direction));
+        }
+    }
+
+    output[pixel] = vec4<f32>(sum, 1.0);
+}
@@ -0,0 +1,583 @@
+//! Reads high dynamic range images for environment maps: Radiance `.hdr` (RGBE) and OpenEXR.
+//! Rendered images can be written back out as Radiance `.hdr`.
+//!
+//! Only what environment maps usually come as is supported. EXR files have to be single-part
+//! scanline images with half or float channels, uncompressed or RLE, ZIPS or ZIP compressed.
+
+use glam::Vec3;
+use std::fmt;
+use std::io::Write;
+
+/// Linear RGB pixels, row-major from the top left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+}
+
+#[derive(Debug)]
+pub enum HdrImageError {
+    UnknownFormat(String), // File name without a .hdr or .exr extension
+    Parse(String),         // Not a valid or not a supported image
+    Io(String),            // Reading the file failed
+}
+
+impl fmt::Display for HdrImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(name) => write!(f, "'{}' is not a .hdr or .exr file", name),
+            Self::Parse(message) => write!(f, "Could not read HDR image: {}", message),
+            Self::Io(message) => write!(f, "Could not read HDR image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for HdrImageError {}
+
+fn parse_error<T>(message: impl Into<String>) -> Result<T, HdrImageError> {
+    Err(HdrImageError::Parse(message.into()))
+}
+
+/// Most pixels an image may have, as many as a 16K environment map. Header sizes are checked
+/// against it before anything is allocated for them.
+const MAX_PIXELS: u64 = 16384 * 8192;
+
+/// Number of pixels of a `width` by `height` image, if it isn't too large.
+fn pixel_count(width: u64, height: u64) -> Result<usize, HdrImageError> {
+    match width.checked_mul(height) {
+        Some(count) if count <= MAX_PIXELS => Ok(count as usize),
+        _ => parse_error(format!("{}x{} is larger than the supported {} pixels", width, height, MAX_PIXELS)),
+    }
+}
+
+/// Decodes `bytes` as the format the extension of `file_name` names. Infinite, NaN and negative
+/// values become 0, as a single one would poison every pixel that sees it while accumulating.
+pub fn decode(file_name: &str, bytes: &[u8]) -> Result<HdrImage, HdrImageError> {
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    let mut image = match extension.as_deref() {
+        Some("hdr") => decode_rgbe(bytes),
+        Some("exr") => decode_exr(bytes),
+        _ => return Err(HdrImageError::UnknownFormat(file_name.to_string())),
+    }
+    .map_err(|err| match err {
+        HdrImageError::Parse(message) => HdrImageError::Parse(format!("{}: {}", file_name, message)),
+        err => err,
+    })?;
+    if image.pixels.is_empty() {
+        return parse_error(format!("{}: image is empty", file_name));
+    }
+    for pixel in &mut image.pixels {
+        *pixel = pixel.map(|value| if value.is_finite() { value.max(0.0) } else { 0.0 });
+    }
+    Ok(image)
+}
+
+/// Radiance RGBE, flat or with the run-length encoded scanlines most files use.
+pub fn decode_rgbe(bytes: &[u8]) -> Result<HdrImage, HdrImageError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let magic = reader.line()?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return parse_error("missing #?RADIANCE header");
+    }
+    loop {
+        let line = reader.line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return parse_error(format!("unsupported pixel format {}", format));
+            }
+        }
+    }
+
+    // Usually "-Y <height> +X <width>", rows from the top
+    let resolution = reader.line()?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match fields.as_slice() {
+        [y, height, "+X", width] if *y == "-Y" || *y == "+Y" => (*y == "+Y", height.parse::<u32>(), width.parse::<u32>()),
+        _ => return parse_error(format!("unsupported resolution line '{}'", resolution)),
+    };
+    let (Ok(height), Ok(width)) = (height, width) else {
+        return parse_error(format!("invalid resolution line '{}'", resolution));
+    };
+
+    let mut rgbe = vec![[0u8; 4]; pixel_count(width as u64, height as u64)?];
+    for row in rgbe.chunks_exact_mut(width.max(1) as usize) {
+        read_rgbe_scanline(&mut reader, row)?;
+    }
+    if flip {
+        rgbe = rgbe.chunks_exact(width.max(1) as usize).rev().flatten().copied().collect();
+    }
+
+    let pixels = rgbe
+        .iter()
+        .map(|&[r, g, b, e]| {
+            if e == 0 {
+                return Vec3::ZERO;
+            }
+            let scale = 2f32.powi(e as i32 - 136);
+            Vec3::new(r as f32 + 0.5, g as f32 + 0.5, b as f32 + 0.5) * scale
+        })
+        .collect();
+    Ok(HdrImage { width, height, pixels })
+}
+
+fn read_rgbe_scanline(reader: &mut Reader, row: &mut [[u8; 4]]) -> Result<(), HdrImageError> {
+    let width = row.len();
+    let start = reader.take(4)?;
+    let run_length_encoded = (8..0x8000).contains(&width)
+        && start[0] == 2
+        && start[1] == 2
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+    if !run_length_encoded {
+        row[0].copy_from_slice(start);
+        for pixel in &mut row[1..] {
+            pixel.copy_from_slice(reader.take(4)?);
+        }
+        return Ok(());
+    }
+
+    // Each channel of the row is stored on its own as runs and literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = reader.take(1)?[0] as usize;
+            let (count, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+            if count == 0 || x + count > width {
+                return parse_error("corrupt run-length encoded scanline");
+            }
+            if run {
+                let value = reader.take(1)?[0];
+                for pixel in &mut row[x..x + count] {
+                    pixel[channel] = value;
+                }
+            } else {
+                for (pixel, &value) in row[x..x + count].iter_mut().zip(reader.take(count)?) {
+                    pixel[channel] = value;
+                }
+            }
+            x += count;
+        }
+    }
+    Ok(())
+}
+
+/// Radiance RGBE. Rows of 8 to 32767 pixels are run-length encoded, as readers expect, but
+/// only with literal spans; other widths are stored flat.
+pub fn encode_rgbe(mut writer: impl Write, image: &HdrImage) -> std::io::Result<()> {
+    write!(writer, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height, image.width)?;
+    let width = image.width as usize;
+    for row in image.pixels.chunks_exact(width.max(1)) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|&color| to_rgbe(color)).collect();
+        if !(8..0x8000).contains(&width) {
+            writer.write_all(rgbe.as_flattened())?;
+            continue;
+        }
+        writer.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for channel in 0..4 {
+            let values: Vec<u8> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+            for span in values.chunks(128) {
+                writer.write_all(&[span.len() as u8])?;
+                writer.write_all(span)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Shared exponent of the brightest channel, with 8 bit mantissas for all three.
+fn to_rgbe(color: Vec3) -> [u8; 4] {
+    let brightest = color.max_element();
+    if brightest.is_nan() || brightest <= 1e-32 {
+        return [0; 4];
+    }
+    let exponent = brightest.log2().floor().clamp(-128.0, 126.0) as i32 + 1;
+    let mantissa = (color.max(Vec3::ZERO) * 256.0 / 2f32.powi(exponent)).min(Vec3::splat(255.0));
+    [mantissa.x as u8, mantissa.y as u8, mantissa.z as u8, (exponent + 128) as u8]
+}
+
+/// EXR channel sample types.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ExrSample {
+    Half,
+    Float,
+}
+
+impl ExrSample {
+    fn size(self) -> usize {
+        match self {
+            Self::Half => 2,
+            Self::Float => 4,
+        }
+    }
+}
+
+/// OpenEXR, see the format description at openexr.com. Channels R, G and B are read, or Y
+/// for grayscale images; the rest are skipped.
+pub fn decode_exr(bytes: &[u8]) -> Result<HdrImage, HdrImageError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return parse_error("missing OpenEXR magic number");
+    }
+    let version = reader.u32()?;
+    if version & 0xff != 2 {
+        return parse_error(format!("unsupported version {}", version & 0xff));
+    }
+    if version & 0x1a00 != 0 {
+        return parse_error("tiled, deep and multi-part images are not supported");
+    }
+
+    let mut channels: Vec<(String, ExrSample)> = Vec::new();
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _type_name = reader.string()?;
+        let size = reader.u32()? as usize;
+        let mut value = Reader { bytes: reader.take(size)?, position: 0 };
+        match name.as_str() {
+            "channels" => loop {
+                let channel = value.string()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let sample = match value.u32()? {
+                    1 => ExrSample::Half,
+                    2 => ExrSample::Float,
+                    other => return parse_error(format!("unsupported sample type {} of channel {}", other, channel)),
+                };
+                value.take(4)?; // pLinear and reserved
+                if value.u32()? != 1 || value.u32()? != 1 {
+                    return parse_error("subsampled channels are not supported");
+                }
+                channels.push((channel, sample));
+            },
+            "compression" => compression = Some(value.take(1)?[0]),
+            "dataWindow" => {
+                let [x_min, y_min, x_max, y_max] = [value.i32()?, value.i32()?, value.i32()?, value.i32()?];
+                // In i64, as the corners of a corrupt window can be more than i32::MAX apart
+                let size = |min: i32, max: i32| max as i64 - min as i64 + 1;
+                data_window = Some((size(x_min, x_max), size(y_min, y_max), y_min));
+            }
+            _ => {}
+        }
+    }
+
+    let Some((width, height, y_min)) = data_window.filter(|&(width, height, _)| width > 0 && height > 0) else {
+        return parse_error("missing or empty data window");
+    };
+    pixel_count(width as u64, height as u64)?;
+    let (width, height) = (width as usize, height as usize);
+    let lines_per_block = match compression {
+        Some(0..=2) => 1,  // None, RLE, ZIPS
+        Some(3) => 16,     // ZIP
+        Some(other) => return parse_error(format!("unsupported compression {}", other)),
+        None => return parse_error("missing compression attribute"),
+    };
+    let compression = compression.unwrap_or_default();
+
+    // Where each channel's samples start within one scanline, they are stored in name order
+    let channel_offset = |wanted: &str| {
+        let mut offset = 0;
+        for (name, sample) in &channels {
+            if name == wanted {
+                return Some((offset, *sample));
+            }
+            offset += sample.size() * width;
+        }
+        None
+    };
+    let line_size = channels.iter().try_fold(0usize, |size, (_, sample)| size.checked_add(sample.size() * width));
+    let Some(line_size) = line_size.filter(|size| size.checked_mul(lines_per_block).is_some()) else {
+        return parse_error("too many channels");
+    };
+    let rgb = match (channel_offset("R"), channel_offset("G"), channel_offset("B"), channel_offset("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return parse_error("no R, G and B or Y channels"),
+    };
+
+    let block_count = height.div_ceil(lines_per_block);
+    let offsets: Vec<u64> = (0..block_count).map(|_| reader.u64()).collect::<Result<_, _>>()?;
+
+    let mut pixels = vec![Vec3::ZERO; width * height];
+    for offset in offsets {
+        let mut block = Reader { bytes, position: usize::try_from(offset).unwrap_or(usize::MAX) };
+        let first_line = usize::try_from(block.i32()? as i64 - y_min as i64).ok().filter(|&line| line < height);
+        let Some(first_line) = first_line else {
+            return parse_error("block outside the data window");
+        };
+        let size = block.u32()? as usize;
+        let packed = block.take(size)?;
+        let lines = lines_per_block.min(height.saturating_sub(first_line));
+        let expected = lines * line_size;
+
+        let data = if size == expected {
+            packed.to_vec() // Stored as is when compression wouldn't help
+        } else {
+            let mut data = match compression {
+                1 => decompress_rle(packed, expected)?,
+                2 | 3 => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(packed, expected)
+                    .map_err(|err| HdrImageError::Parse(format!("corrupt ZIP block: {:?}", err)))?,
+                _ => return parse_error("block has the wrong size"),
+            };
+            if data.len() != expected {
+                return parse_error("block has the wrong size");
+            }
+            undo_predictor(&mut data);
+            data
+        };
+
+        for line in 0..lines {
+            let Some(row) = pixels.get_mut((first_line + line) * width..(first_line + line + 1) * width) else {
+                return parse_error("block outside the data window");
+            };
+            let line_data = &data[line * line_size..(line + 1) * line_size];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let [r, g, b] = rgb.map(|(offset, sample)| read_sample(line_data, offset + x * sample.size(), sample));
+                *pixel = Vec3::new(r, g, b);
+            }
+        }
+    }
+
+    Ok(HdrImage { width: width as u32, height: height as u32, pixels })
+}
+
+fn read_sample(data: &[u8], offset: usize, sample: ExrSample) -> f32 {
+    match sample {
+        ExrSample::Half => half_to_f32(u16::from_le_bytes([data[offset], data[offset + 1]])),
+        ExrSample::Float => f32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]),
+    }
+}
+
+/// EXR run-length encoding: a negative count is followed by that many literal bytes,
+/// a positive one by a byte repeated count + 1 times.
+fn decompress_rle(packed: &[u8], expected: usize) -> Result<Vec<u8>, HdrImageError> {
+    let mut data = Vec::with_capacity(expected);
+    let mut reader = Reader { bytes: packed, position: 0 };
+    while reader.position < packed.len() {
+        let count = reader.take(1)?[0] as i8;
+        if count < 0 {
+            data.extend_from_slice(reader.take(-(count as i32) as usize)?);
+        } else {
+            let value = reader.take(1)?[0];
+            data.extend(std::iter::repeat_n(value, count as usize + 1));
+        }
+        if data.len() > expected {
+            return parse_error("block has the wrong size");
+        }
+    }
+    Ok(data)
+}
+
+/// RLE and ZIP blocks store byte differences, with the even and odd bytes split into halves.
+fn undo_predictor(data: &mut [u8]) {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+    let half = data.len().div_ceil(2);
+    let (even, odd) = data.split_at(half);
+    let interleaved: Vec<u8> = (0..data.len()).map(|i| if i % 2 == 0 { even[i / 2] } else { odd[i / 2] }).collect();
+    data.copy_from_slice(&interleaved);
+}
+
+/// IEEE 754 half precision to single precision.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24), // Subnormal
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+/// Little-endian reads from a byte slice that fail instead of panicking at the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], HdrImageError> {
+        let end = self.position.checked_add(count).filter(|&end| end <= self.bytes.len());
+        let Some(end) = end else {
+            return parse_error("unexpected end of file");
+        };
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, HdrImageError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, HdrImageError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, HdrImageError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Bytes up to the next NUL, which is skipped.
+    fn string(&mut self) -> Result<String, HdrImageError> {
+        let length = self.bytes[self.position.min(self.bytes.len())..].iter().position(|&b| b == 0);
+        let Some(length) = length else {
+            return parse_error("unterminated string");
+        };
+        let text = String::from_utf8_lossy(self.take(length)?).into_owned();
+        self.take(1)?;
+        Ok(text)
+    }
+
+    /// Text up to the next newline, which is skipped.
+    fn line(&mut self) -> Result<String, HdrImageError> {
+        let length = self.bytes[self.position.min(self.bytes.len())..].iter().position(|&b| b == b'\n');
+        let Some(length) = length else {
+            return parse_error("unexpected end of header");
+        };
+        let text = String::from_utf8_lossy(self.take(length)?).trim_end().to_string();
+        self.take(1)?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_run_length_encoded_rgbe() {
+        // One 8 pixel row: red runs along, green and blue are literal, exponent 129 is a scale of 2
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        file.extend_from_slice(&[128 + 8, 64]);
+        file.extend_from_slice(&[8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        file.extend_from_slice(&[128 + 8, 0]);
+        file.extend_from_slice(&[128 + 8, 129]);
+
+        let image = decode("sky.hdr", &file).unwrap();
+        assert_eq!((image.width, image.height), (8, 1));
+        assert_eq!(image.pixels[3], Vec3::new(64.5, 3.5, 0.5) / 128.0);
+        assert!(matches!(decode("sky.png", &file), Err(HdrImageError::UnknownFormat(_))));
+    }
+
+    #[test]
+    fn rgbe_round_trips() {
+        for width in [3, 10] {
+            let pixels: Vec<Vec3> = (0..width * 2).map(|i| Vec3::new(i as f32 * 0.3, 1.0, 1000.0 / (i + 1) as f32)).collect();
+            let image = HdrImage { width, height: 2, pixels };
+            let mut file = Vec::new();
+            encode_rgbe(&mut file, &image).unwrap();
+
+            let decoded = decode_rgbe(&file).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, 2));
+            for (a, b) in decoded.pixels.iter().zip(&image.pixels) {
+                // Mantissas are relative to the brightest channel
+                assert!((*a - *b).abs().max_element() <= b.max_element() / 128.0, "{} {}", a, b);
+            }
+        }
+    }
+
+    /// A scanline EXR with half B, float G and half R channels, one block per line.
+    fn exr(width: i32, lines: &[Vec<u8>], compression: u8) -> Vec<u8> {
+        let mut file = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        let mut attribute = |name: &str, type_name: &str, value: &[u8]| {
+            file.extend_from_slice(name.as_bytes());
+            file.push(0);
+            file.extend_from_slice(type_name.as_bytes());
+            file.push(0);
+            file.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            file.extend_from_slice(value);
+        };
+        let mut channels = Vec::new();
+        for (name, sample_type) in [("B", 1u32), ("G", 2), ("R", 1)] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&sample_type.to_le_bytes());
+            channels.extend_from_slice(&[0; 4]);
+            channels.extend_from_slice(&1u32.to_le_bytes());
+            channels.extend_from_slice(&1u32.to_le_bytes());
+        }
+        channels.push(0);
+        attribute("channels", "chlist", &channels);
+        attribute("compression", "compression", &[compression]);
+        let window: Vec<u8> = [0, 10, width - 1, 10 + lines.len() as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+        attribute("dataWindow", "box2i", &window);
+        file.push(0);
+
+        let mut offset = file.len() + 8 * lines.len();
+        let mut blocks = Vec::new();
+        for (y, line) in lines.iter().enumerate() {
+            file.extend_from_slice(&(offset as u64).to_le_bytes());
+            blocks.extend_from_slice(&(10 + y as i32).to_le_bytes());
+            blocks.extend_from_slice(&(line.len() as u32).to_le_bytes());
+            blocks.extend_from_slice(line);
+            offset += 8 + line.len();
+        }
+        file.extend_from_slice(&blocks);
+        file
+    }
+
+    #[test]
+    fn decodes_exr_lines() {
+        // Two pixels: (1, 2, 0.5) and (-2, 0, 65504)
+        let mut line = Vec::new();
+        line.extend_from_slice(&[0x00, 0x38, 0xff, 0x7b]); // B halves 0.5 and 65504
+        line.extend_from_slice(&2.0f32.to_le_bytes());
+        line.extend_from_slice(&0.0f32.to_le_bytes());
+        line.extend_from_slice(&[0x00, 0x3c, 0x00, 0xc0]); // R halves 1 and -2
+        let expected = [Vec3::new(1.0, 2.0, 0.5), Vec3::new(-2.0, 0.0, 65504.0)];
+
+        let image = decode_exr(&exr(2, &[line.clone()], 0)).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, expected);
+
+        // ZIPS stores the bytes split into even and odd halves, as differences, deflated
+        let mut predicted: Vec<u8> = line.iter().step_by(2).chain(line.iter().skip(1).step_by(2)).copied().collect();
+        for i in (1..predicted.len()).rev() {
+            predicted[i] = predicted[i].wrapping_sub(predicted[i - 1]).wrapping_add(128);
+        }
+        let zipped = miniz_oxide::deflate::compress_to_vec_zlib(&predicted, 6);
+        assert_eq!(decode_exr(&exr(2, &[zipped], 2)).unwrap().pixels, expected);
+    }
+
+    #[test]
+    fn replaces_values_that_cant_be_rendered() {
+        // Two pixels: (-1, inf, inf) and (1, 2, NaN)
+        let mut line = Vec::new();
+        line.extend_from_slice(&[0x00, 0x7c, 0x00, 0x7e]); // B halves inf and NaN
+        line.extend_from_slice(&f32::INFINITY.to_le_bytes());
+        line.extend_from_slice(&2.0f32.to_le_bytes());
+        line.extend_from_slice(&[0x00, 0xbc, 0x00, 0x3c]); // R halves -1 and 1
+        let file = exr(2, &[line], 0);
+
+        assert!(decode_exr(&file).unwrap().pixels[1].z.is_nan());
+        assert_eq!(decode("sky.exr", &file).unwrap().pixels, [Vec3::ZERO, Vec3::new(1.0, 2.0, 0.0)]);
+    }
+
+    /// Sizes and positions in the header are checked before they are allocated or indexed with.
+    #[test]
+    fn rejects_corrupt_headers() {
+        let huge = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4000000000 +X 4000000000\n";
+        assert!(matches!(decode_rgbe(huge), Err(HdrImageError::Parse(_))));
+
+        let lines = [vec![0; 2 * 8]];
+        assert!(decode_exr(&exr(2, &lines, 0)).is_ok());
+        assert!(matches!(decode_exr(&exr(i32::MAX, &lines, 0)), Err(HdrImageError::Parse(_))));
+        let mut file = exr(2, &lines, 0);
+        let block = file.len() - lines[0].len() - 8;
+        file[block..block + 4].copy_from_slice(&i32::MIN.to_le_bytes());
+        assert!(matches!(decode_exr(&file), Err(HdrImageError::Parse(_))));
+    }
+}
@@ -0,0 +1,85 @@
+//! Writes rendered RGBA8 images to disk as PNG or binary PPM, picked by extension.
+
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Format matching the extension of `path`, None if it is neither .png nor .ppm.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "ppm" => Some(Self::Ppm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageFileError {
+    UnknownFormat(String), // Path without a .png or .ppm extension
+    Io(String),            // Creating or writing the file failed
+}
+
+impl fmt::Display for ImageFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(path) => write!(f, "'{}' is not a .png or .ppm file", path),
+            Self::Io(message) => write!(f, "Could not write image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImageFileError {}
+
+/// Writes `rgba` (row-major, 4 bytes per pixel) to `path`.
+pub fn save(path: &Path, width: u32, height: u32, rgba: &[u8]) -> Result<(), ImageFileError> {
+    let format = ImageFormat::from_path(path)
+        .ok_or_else(|| ImageFileError::UnknownFormat(path.display().to_string()))?;
+    let io_error = |err: &dyn fmt::Display| ImageFileError::Io(format!("{}: {}", path.display(), err));
+
+    let file = std::fs::File::create(path).map_err(|err| io_error(&err))?;
+    let mut writer = std::io::BufWriter::new(file);
+    match format {
+        ImageFormat::Png => encode_png(&mut writer, width, height, rgba).map_err(|err| io_error(&err))?,
+        ImageFormat::Ppm => encode_ppm(&mut writer, width, height, rgba).map_err(|err| io_error(&err))?,
+    }
+    writer.flush().map_err(|err| io_error(&err))
+}
+
+pub fn encode_png(writer: impl Write, width: u32, height: u32, rgba: &[u8]) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)
+}
+
+/// Binary (P6) PPM. PPM has no alpha channel, so alpha is dropped.
+pub fn encode_ppm(mut writer: impl Write, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    let rgb: Vec<u8> = rgba.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+    writer.write_all(&rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_ppm_without_alpha() {
+        let rgba = [255, 0, 0, 255, 0, 128, 255, 255];
+        let mut out = Vec::new();
+        encode_ppm(&mut out, 2, 1, &rgba).unwrap();
+
+        assert_eq!(out, b"P6\n2 1\n255\n\xff\x00\x00\x00\x80\xff");
+        assert_eq!(ImageFormat::from_path(Path::new("out.PNG")), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("out.jpg")), None);
+    }
+}
@@ -1,5 +1,6 @@
-use glam::{Mat4, Quat, Vec3, Vec4Swizzles};
+use glam::{Mat4, Quat, Vec3};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Ray {
@@ -20,7 +21,7 @@ impl Ray {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Transform {
     pub position: Vec3,
     pub rotation: Quat,
@@ -38,7 +39,7 @@ impl Default for Transform {
 }
 
 impl Transform {
-    pub fn to_mat4(&self) -> Mat4 {
+    pub fn to_mat4(self) -> Mat4 {
         Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.position)
     }
 
@@ -53,28 +54,31 @@ impl Transform {
     pub fn up(&self) -> Vec3 {
         self.rotation * Vec3::Y
     }
-}
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let p = Vec3::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-        );
-        if p.length_squared() < 1.0 {
-            return p;
+    /// Moves a world-space ray into the local space of this transform. The direction is not
+    /// renormalized, so a distance `t` along the ray means the same point in both spaces.
+    pub fn ray_to_local(&self, ray: &Ray) -> Ray {
+        let inverse_rotation = self.rotation.inverse();
+        Ray {
+            origin: inverse_rotation * (ray.origin - self.position) / self.scale,
+            direction: inverse_rotation * ray.direction / self.scale,
         }
     }
-}
 
-pub fn random_unit_vector() -> Vec3 {
-    random_in_unit_sphere().normalize()
+    /// Maps a local surface normal to world space. Normals are divided by the scale rather
+    /// than multiplied, so they stay perpendicular to a non-uniformly scaled surface.
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        (self.rotation * (normal / self.scale)).normalize_or_zero()
+    }
 }
 
-pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
-    v - 2.0 * v.dot(n) * n
+/// Uniform over the unit sphere, mapped from two random numbers rather than found by
+/// rejection, so well spread samples give well spread directions.
+pub fn random_unit_vector(rng: &mut impl Rng) -> Vec3 {
+    let z = 1.0 - 2.0 * rng.gen::<f32>();
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(radius * phi.cos(), radius * phi.sin(), z)
 }
 
 pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
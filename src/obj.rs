@@ -0,0 +1,234 @@
+//! Wavefront OBJ import.
+//!
+//! Every object in the file becomes one [`Mesh`]. Diffuse, specular, shininess, index of
+//! refraction and dissolve from the `.mtl` file are mapped onto [`Material`]; objects without
+//! a material (or whose `.mtl` is missing) get the default one.
+
+use crate::primitives::{Material, MaterialType, Mesh};
+use glam::Vec3;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Parse(String),      // Not a valid OBJ file
+    NoGeometry(String), // Parsed, but without any triangles
+    Io(String),         // Reading the file failed
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(message) => write!(f, "Could not parse OBJ file: {}", message),
+            Self::NoGeometry(name) => write!(f, "'{}' contains no triangles", name),
+            Self::Io(message) => write!(f, "Could not read OBJ file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Parses an OBJ file. `read_mtl` is asked for the contents of each `mtllib` the file
+/// references and returns None if it is not available.
+pub fn parse(name: &str, obj: &str, read_mtl: impl Fn(&Path) -> Option<String>) -> Result<Vec<Mesh>, ObjError> {
+    let load_mtl = |path: &Path| match read_mtl(path) {
+        Some(text) => tobj::load_mtl_buf(&mut text.as_bytes()),
+        None => Err(tobj::LoadError::OpenFileFailed),
+    };
+    let (models, materials) = tobj::load_obj_buf(&mut obj.as_bytes(), &tobj::GPU_LOAD_OPTIONS, load_mtl)
+        .map_err(|err| ObjError::Parse(format!("{}: {}", name, err)))?;
+
+    let materials = materials.unwrap_or_else(|err| {
+        log::warn!("Materials of {} unavailable, using defaults: {}", name, err);
+        Vec::new()
+    });
+
+    let meshes: Vec<Mesh> = models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| {
+            let mesh = model.mesh;
+            let material = mesh
+                .material_id
+                .and_then(|id| materials.get(id))
+                .map(convert_material)
+                .unwrap_or_default();
+
+            let vertices = mesh.positions.chunks_exact(3).map(Vec3::from_slice).collect();
+            let normals = mesh.normals.chunks_exact(3).map(Vec3::from_slice).collect();
+            let triangles = mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+            let name = if model.name.is_empty() { name.to_string() } else { model.name };
+            Mesh::new(name, vertices, normals, triangles, material)
+        })
+        .collect();
+
+    if meshes.is_empty() {
+        return Err(ObjError::NoGeometry(name.to_string()));
+    }
+    Ok(meshes)
+}
+
+/// Loads an OBJ file and the `.mtl` files it references, which are looked up next to it.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(path: &Path) -> Result<Vec<Mesh>, ObjError> {
+    let text = std::fs::read_to_string(path).map_err(|err| ObjError::Io(format!("{}: {}", path.display(), err)))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    parse(&name, &text, |mtl| std::fs::read_to_string(directory.join(mtl)).ok())
+}
+
+fn convert_material(mtl: &tobj::Material) -> Material {
+    let average = |rgb: [f32; 3]| (rgb[0] + rgb[1] + rgb[2]) / 3.0;
+    let defaults = Material::default();
+
+    let color = mtl.diffuse.map_or(defaults.color, Vec3::from_array);
+    let specular = mtl.specular.map_or(defaults.specular, average);
+    let shininess = mtl.shininess.unwrap_or(defaults.shininess);
+    let ior = mtl.optical_density.filter(|&ior| ior > 0.0).unwrap_or(defaults.ior);
+
+    // illum 3 and up add raytraced reflection, dissolve below 1 means see-through
+    let transparent = mtl.dissolve.is_some_and(|d| d < 1.0);
+    let reflective = mtl.illumination_model.is_some_and(|illum| illum >= 3);
+    let mat_type = if transparent {
+        MaterialType::Dielectric
+    } else if reflective {
+        MaterialType::Metal
+    } else {
+        MaterialType::Lambertian
+    };
+
+    Material {
+        color: if transparent { Vec3::ONE } else { color },
+        specular,
+        shininess,
+        reflectivity: if reflective { specular.clamp(0.0, 1.0) } else { 0.0 },
+        // Usual mapping of a Phong exponent to a microfacet roughness
+        roughness: (2.0 / (shininess + 2.0)).sqrt(),
+        ior,
+        mat_type,
+        emission: mtl.emissive.map_or(Vec3::ZERO, Vec3::from_array),
+        principled: defaults.principled,
+    }
+}
+
+/// Picks OBJ files to import: a file dialog on native, a browser upload on wasm.
+/// On the web the `.mtl` files have to be selected together with the `.obj`.
+#[cfg(feature = "gui")]
+#[derive(Default)]
+pub struct ObjImporter {
+    inbox: std::rc::Rc<std::cell::RefCell<Option<ImportResult>>>,
+}
+
+/// Result of an import started with [`ObjImporter::open`]: the file name and its meshes.
+#[cfg(feature = "gui")]
+pub type ImportResult = Result<(String, Vec<Mesh>), ObjError>;
+
+#[cfg(feature = "gui")]
+impl ObjImporter {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(&self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("Wavefront OBJ", &["obj"]).pick_file() else {
+            return; // Cancelled
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        *self.inbox.borrow_mut() = Some(load(&path).map(|meshes| (file_name, meshes)));
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn open(&self) {
+        let inbox = self.inbox.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let Some(handles) = rfd::AsyncFileDialog::new().add_filter("Wavefront OBJ", &["obj", "mtl"]).pick_files().await else {
+                return; // Cancelled
+            };
+
+            let mut files = Vec::new();
+            for handle in handles {
+                let text = String::from_utf8_lossy(&handle.read().await).into_owned();
+                files.push((handle.file_name(), text));
+            }
+
+            let result = match files.iter().find(|(name, _)| name.to_ascii_lowercase().ends_with(".obj")) {
+                Some((file_name, text)) => {
+                    let name = file_name.rsplit_once('.').map_or(file_name.as_str(), |(stem, _)| stem);
+                    let read_mtl = |mtl: &Path| {
+                        let wanted = mtl.file_name()?.to_string_lossy();
+                        files.iter().find(|(name, _)| *name == wanted).map(|(_, text)| text.clone())
+                    };
+                    parse(name, text, read_mtl).map(|meshes| (file_name.clone(), meshes))
+                }
+                None => Err(ObjError::Io("No .obj file selected".to_string())),
+            };
+            *inbox.borrow_mut() = Some(result);
+        });
+    }
+
+    /// The imported meshes, once the user has picked a file.
+    pub fn poll(&self) -> Option<ImportResult> {
+        self.inbox.borrow_mut().take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Ray;
+    use crate::primitives::Intersectable;
+
+    // Unit square in the XY plane, split in two triangles, with normals tilted apart
+    const QUAD: &str = "
+mtllib quad.mtl
+o Quad
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn -1 0 1
+vn 1 0 1
+vn 1 0 1
+vn -1 0 1
+usemtl Shiny
+f 1//1 2//2 3//3
+f 1//1 3//3 4//4
+";
+
+    const MTL: &str = "
+newmtl Shiny
+Kd 0.8 0.2 0.1
+Ks 0.5 0.5 0.5
+Ns 98
+Ke 0.5 0.0 0.0
+illum 3
+";
+
+    #[test]
+    fn imports_geometry_and_materials() {
+        let meshes = parse("quad", QUAD, |path| (path == Path::new("quad.mtl")).then(|| MTL.to_string())).unwrap();
+        assert_eq!(meshes.len(), 1);
+
+        let mesh = &meshes[0];
+        assert_eq!(mesh.name, "Quad");
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(mesh.material.color, Vec3::new(0.8, 0.2, 0.1));
+        assert_eq!(mesh.material.mat_type, MaterialType::Metal);
+        assert_eq!(mesh.material.reflectivity, 0.5);
+        assert!((mesh.material.roughness - 0.1414).abs() < 1e-3);
+        assert_eq!(mesh.material.emission, Vec3::new(0.5, 0.0, 0.0));
+
+        // The shading normal blends the vertex normals, leaning left on the left edge
+        let ray = Ray::new(Vec3::new(0.001, 0.5, 1.0), -Vec3::Z);
+        let hit = mesh.intersect(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-5);
+        assert!((hit.normal - Vec3::new(-1.0, 0.0, 1.0).normalize()).length() < 1e-2);
+
+        let miss = Ray::new(Vec3::new(1.5, 0.5, 1.0), -Vec3::Z);
+        assert!(mesh.intersect(&miss, 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn missing_mtl_falls_back_to_default_material() {
+        let meshes = parse("quad", QUAD, |_| None).unwrap();
+        assert_eq!(meshes[0].material, Material::default());
+        assert!(matches!(parse("empty", "o Nothing\nv 0 0 0\n", |_| None), Err(ObjError::NoGeometry(_))));
+    }
+}
@@ -1,22 +1,74 @@
-use crate::math::Ray;
-use glam::Vec3;
+use crate::bvh::{Aabb, Bounded, Bvh};
+use crate::math::{random_unit_vector, Ray, Transform};
+use glam::{Vec2, Vec3};
+use rand::Rng;
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// How a surface scatters light. The first three are presets that `Material::to_principled`
+/// reproduces with the parameters of a principled material.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum MaterialType {
     Lambertian,
     Metal,
     Dielectric,
+    /// Layers blended by `Material::principled`, see `brdf::PrincipledBsdf`.
+    Principled,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Material {
     pub color: Vec3,
-    pub specular: f32,
+    pub specular: f32, // For principled materials 0.5 is a reflectance of 4%, as for most dielectrics
     pub shininess: f32,
     pub reflectivity: f32, // Kept for legacy/hybrid support
     pub roughness: f32,
     pub ior: f32,
     pub mat_type: MaterialType,
+    #[serde(default)]
+    pub emission: Vec3, // Radiance given off by the surface itself
+    #[serde(default)]
+    pub principled: PrincipledParams,
+}
+
+/// Parameters of a principled material beyond the base color, roughness, specular and IOR
+/// of [`Material`]. All range from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrincipledParams {
+    pub metallic: f32,
+    pub clearcoat: f32, // Strength of a glossy varnish over everything else
+    pub clearcoat_roughness: f32,
+    pub sheen: f32, // Soft grazing highlight as on cloth
+    pub transmission: f32, // Share of the dielectric base that lets light through instead of diffusing it
+    pub anisotropy: f32, // Stretches highlights around the vertical axis, as on brushed metal
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        Self {
+            metallic: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            transmission: 0.0,
+            anisotropy: 0.0,
+        }
+    }
+}
+
+impl Material {
+    /// A principled material that looks like this one. Preset types become the parameters
+    /// that reproduce them in pathtracing, principled materials are returned as they are.
+    pub fn to_principled(self) -> Self {
+        let (specular, roughness, principled) = match self.mat_type {
+            MaterialType::Lambertian => (0.0, 1.0, PrincipledParams::default()),
+            MaterialType::Metal => (self.specular, self.roughness, PrincipledParams { metallic: 1.0, ..Default::default() }),
+            MaterialType::Dielectric => (self.specular, 0.0, PrincipledParams { transmission: 1.0, ..Default::default() }),
+            MaterialType::Principled => return self,
+        };
+        Self { specular, roughness, mat_type: MaterialType::Principled, principled, ..self }
+    }
 }
 
 impl Default for Material {
@@ -29,6 +81,8 @@ impl Default for Material {
             roughness: 0.0,
             ior: 1.5,
             mat_type: MaterialType::Lambertian,
+            emission: Vec3::ZERO,
+            principled: PrincipledParams::default(),
         }
     }
 }
@@ -44,14 +98,43 @@ pub trait Intersectable {
     fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
 }
 
+/// Runs `intersect_local` on the ray moved into the object space of `transform` and moves the
+/// hit back out. The shapes below are all defined in object space and intersected this way.
+fn intersect_transformed(
+    transform: &Transform,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    intersect_local: impl FnOnce(&Ray, f32, f32) -> Option<HitRecord>,
+) -> Option<HitRecord> {
+    let local_ray = transform.ray_to_local(ray);
+    let hit = intersect_local(&local_ray, t_min, t_max)?;
+    Some(HitRecord {
+        point: ray.at(hit.t),
+        normal: transform.normal_to_world(hit.normal),
+        ..hit
+    })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Transform, // Non-uniform scale turns the sphere into an ellipsoid
 }
 
 impl Intersectable for Sphere {
     fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        intersect_transformed(&self.transform, ray, t_min, t_max, |ray, t_min, t_max| {
+            self.intersect_local(ray, t_min, t_max)
+        })
+    }
+}
+
+impl Sphere {
+    fn intersect_local(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(ray.direction);
@@ -84,14 +167,32 @@ impl Intersectable for Sphere {
     }
 }
 
+impl Bounded for Sphere {
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.center - Vec3::splat(self.radius), self.center + Vec3::splat(self.radius))
+            .transformed(self.transform.to_mat4())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Plane {
     pub point: Vec3,
     pub normal: Vec3,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Transform,
 }
 
 impl Intersectable for Plane {
     fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        intersect_transformed(&self.transform, ray, t_min, t_max, |ray, t_min, t_max| {
+            self.intersect_local(ray, t_min, t_max)
+        })
+    }
+}
+
+impl Plane {
+    fn intersect_local(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let denom = self.normal.dot(ray.direction);
         if denom.abs() > 1e-6 {
             let t = (self.point - ray.origin).dot(self.normal) / denom;
@@ -108,14 +209,25 @@ impl Intersectable for Plane {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cube {
     pub min: Vec3,
     pub max: Vec3,
     pub material: Material,
+    #[serde(default)]
+    pub transform: Transform, // Rotating this is what makes the box not axis-aligned
 }
 
 impl Intersectable for Cube {
     fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        intersect_transformed(&self.transform, ray, t_min, t_max, |ray, t_min, t_max| {
+            self.intersect_local(ray, t_min, t_max)
+        })
+    }
+}
+
+impl Cube {
+    fn intersect_local(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let mut t_near = t_min;
         let mut t_far = t_max;
         let mut normal = Vec3::ZERO;
@@ -164,17 +276,494 @@ impl Intersectable for Cube {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+impl Bounded for Cube {
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.min, self.max).transformed(self.transform.to_mat4())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+}
+
+impl Triangle {
+    /// Möller-Trumbore. Returns the distance and the barycentric weights of `b` and `c`.
+    pub fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+        let edge1 = self.b - self.a;
+        let edge2 = self.c - self.a;
+        let p = ray.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < 1e-8 {
+            return None; // Parallel to the triangle
+        }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin - self.a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        Some((t, u, v))
+    }
+
+    /// Unit normal following the counter-clockwise winding of a, b, c.
+    pub fn normal(&self) -> Vec3 {
+        (self.b - self.a).cross(self.c - self.a).normalize_or_zero()
+    }
+}
+
+impl Bounded for Triangle {
+    fn bounds(&self) -> Aabb {
+        Aabb::EMPTY.grow(self.a).grow(self.b).grow(self.c)
+    }
+}
+
+/// Triangle mesh, shaded smoothly if it has per-vertex normals and flat otherwise.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mesh {
+    pub name: String,
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,       // One per vertex, or empty for flat shading
+    pub triangles: Vec<[u32; 3]>, // Counter-clockwise indices into `vertices` and `normals`
+    pub material: Material,
+    #[serde(default)]
+    pub transform: Transform,
+    // Over the triangles in object space. Call `rebuild_bvh` after editing the geometry;
+    // `Scene::rebuild_bvh` builds it after deserializing.
+    #[serde(skip)]
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(name: String, vertices: Vec<Vec3>, normals: Vec<Vec3>, triangles: Vec<[u32; 3]>, material: Material) -> Self {
+        let mut mesh = Self {
+            name,
+            vertices,
+            normals,
+            triangles,
+            material,
+            transform: Transform::default(),
+            bvh: Bvh::default(),
+        };
+        mesh.rebuild_bvh();
+        mesh
+    }
+
+    pub fn triangle(&self, index: usize) -> Triangle {
+        let [a, b, c] = self.triangles[index];
+        Triangle {
+            a: self.vertices[a as usize],
+            b: self.vertices[b as usize],
+            c: self.vertices[c as usize],
+        }
+    }
+
+    /// Brings the BVH up to date with the triangles, like `Scene::rebuild_bvh`.
+    pub fn rebuild_bvh(&mut self) {
+        let bounds: Vec<Aabb> = (0..self.triangles.len()).map(|i| self.triangle(i).bounds()).collect();
+        if bounds.len() == self.bvh.primitive_count() {
+            self.bvh.refit(&bounds);
+        } else {
+            self.bvh = Bvh::build(&bounds);
+        }
+    }
+
+    /// Whether the BVH covers the triangles, which it doesn't yet after deserializing.
+    /// Transforms apply outside of it, so moving the mesh never makes it stale.
+    pub fn has_bvh(&self) -> bool {
+        self.bvh.primitive_count() == self.triangles.len()
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        intersect_transformed(&self.transform, ray, t_min, t_max, |ray, t_min, t_max| {
+            self.intersect_local(ray, t_min, t_max)
+        })
+    }
+}
+
+impl Mesh {
+    fn intersect_local(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut closest = None;
+        self.bvh.intersect(ray, t_min, t_max, |index, closest_t| {
+            let (t, u, v) = self.triangle(index).intersect(ray, t_min, closest_t)?;
+            closest = Some((index, t, u, v));
+            Some(t)
+        });
+
+        let (index, t, u, v) = closest?;
+        let geometric_normal = self.triangle(index).normal();
+        let normal = if self.normals.is_empty() {
+            geometric_normal
+        } else {
+            // Interpolate the vertex normals for smooth shading
+            let [a, b, c] = self.triangles[index].map(|i| self.normals[i as usize]);
+            (a * (1.0 - u - v) + b * u + c * v).normalize_or(geometric_normal)
+        };
+
+        Some(HitRecord {
+            t,
+            point: ray.at(t),
+            normal,
+            material: self.material,
+        })
+    }
+}
+
+impl Bounded for Mesh {
+    fn bounds(&self) -> Aabb {
+        let matrix = self.transform.to_mat4();
+        self.vertices.iter().fold(Aabb::EMPTY, |bounds, &v| bounds.grow(matrix.transform_point3(v)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LightType {
     Point,
     Directional,
+    Spot,
+    Rectangle,
+    Disk,
+    Sphere,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl LightType {
+    /// Area lights have a surface: it shows up in the image and casts soft shadows.
+    pub fn is_area(self) -> bool {
+        matches!(self, Self::Rectangle | Self::Disk | Self::Sphere)
+    }
+}
+
+/// How the intensities of point, spot and directional lights are read.
+/// Area lights always give the radiance of their surface.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LightUnits {
+    /// Unitless intensities that don't fall off with distance, as the renderer started out.
+    /// Kept around to compare against.
+    Legacy,
+    /// Point and spot lights give their power in watts and fall off with the inverse square
+    /// of the distance. Directional lights give their irradiance in W/m².
+    #[default]
+    Radiometric,
+}
+
+impl LightUnits {
+    /// Factor from a legacy intensity to one in these units that lights a point at `distance`
+    /// the same. A diffuse surface reflects 1/PI of the irradiance, and a point light of power P
+    /// gives an irradiance of P / (4 PI distance^2).
+    pub fn scale(self, light_type: LightType, distance: f32) -> f32 {
+        match (self, light_type) {
+            (Self::Legacy, _) => 1.0,
+            (Self::Radiometric, LightType::Point | LightType::Spot) => 4.0 * PI * PI * distance * distance,
+            (Self::Radiometric, LightType::Directional) => PI,
+            (Self::Radiometric, LightType::Rectangle | LightType::Disk | LightType::Sphere) => 1.0,
+        }
+    }
+}
+
+/// Spot, rectangle and disk lights sit at `position` and shine only towards `direction`.
+/// For area lights `color * intensity` is the radiance of their surface.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Light {
     pub light_type: LightType,
     pub position: Vec3,
     pub direction: Vec3,
     pub color: Vec3,
     pub intensity: f32,
+    #[serde(default)]
+    pub size: Vec2, // Width and height of a rectangle, the radius of a disk or sphere in x
+    #[serde(default)]
+    pub inner_angle: f32, // Spot cone in degrees from `direction`: full brightness inside,
+    #[serde(default)]
+    pub outer_angle: f32, // fading out towards the outer angle and dark beyond it
+}
+
+/// One way to a light, as seen from a point being shaded.
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    /// What a point light at the sample would need to shine with to light a diffuse surface the same.
+    pub intensity: Vec3,
+    /// Density of `direction` per unit solid angle. Infinite for point, spot and directional
+    /// lights, which nothing but sampling can find.
+    pub pdf: f32,
+}
+
+impl Light {
+    /// Direction a spot, rectangle or disk light faces, and two directions across it: along
+    /// the sides of a rectangle.
+    pub fn area_frame(&self) -> (Vec3, Vec3, Vec3) {
+        let normal = self.direction.normalize();
+        let tangent = if normal.y.abs() < 0.999 {
+            Vec3::Y.cross(normal).normalize()
+        } else {
+            Vec3::X
+        };
+        (normal, tangent, normal.cross(tangent))
+    }
+
+    /// Picks a point on the light to shade `point` with, `None` if that point faces away.
+    /// Point and directional lights always give the same sample.
+    pub fn sample(&self, point: Vec3, units: LightUnits, rng: &mut impl Rng) -> Option<LightSample> {
+        let radiance = self.color * self.intensity;
+        let (surface_point, surface_normal) = match self.light_type {
+            LightType::Directional => {
+                return Some(LightSample {
+                    direction: -self.direction.normalize(),
+                    distance: f32::INFINITY,
+                    intensity: radiance / units.scale(self.light_type, f32::INFINITY),
+                    pdf: f32::INFINITY,
+                });
+            }
+            LightType::Point | LightType::Spot => {
+                let to_light = self.position - point;
+                let direction = to_light.normalize();
+                let distance = to_light.length();
+                let falloff = if self.light_type == LightType::Spot { self.spot_falloff(-direction) } else { 1.0 };
+                if falloff <= 0.0 {
+                    return None;
+                }
+                return Some(LightSample {
+                    direction,
+                    distance,
+                    intensity: radiance * falloff / units.scale(self.light_type, distance),
+                    pdf: f32::INFINITY,
+                });
+            }
+            LightType::Rectangle => {
+                let (normal, tangent, bitangent) = self.area_frame();
+                let offset = tangent * (rng.gen::<f32>() - 0.5) * self.size.x
+                    + bitangent * (rng.gen::<f32>() - 0.5) * self.size.y;
+                (self.position + offset, normal)
+            }
+            LightType::Disk => {
+                let (normal, tangent, bitangent) = self.area_frame();
+                let radius = self.size.x * rng.gen::<f32>().sqrt();
+                let angle = rng.gen::<f32>() * std::f32::consts::TAU;
+                let offset = (tangent * angle.cos() + bitangent * angle.sin()) * radius;
+                (self.position + offset, normal)
+            }
+            LightType::Sphere => {
+                // Only the half facing the point can light it
+                let mut normal = random_unit_vector(rng);
+                if normal.dot(point - self.position) < 0.0 {
+                    normal = -normal;
+                }
+                (self.position + normal * self.size.x, normal)
+            }
+        };
+
+        let to_light = surface_point - point;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let cos_light = surface_normal.dot(-direction);
+        if cos_light <= 0.0 || distance <= 0.0 {
+            return None;
+        }
+        // A Lambertian surface reflects radiance * cos / (PI * pdf) of it
+        let pdf = distance * distance / (cos_light * self.sampled_area());
+        Some(LightSample { direction, distance, intensity: radiance / (PI * pdf), pdf })
+    }
+
+    /// Area that `sample` picks points from uniformly. A sphere only offers the half facing
+    /// the point being shaded.
+    fn sampled_area(&self) -> f32 {
+        match self.light_type {
+            LightType::Point | LightType::Directional | LightType::Spot => 0.0,
+            LightType::Rectangle => self.size.x * self.size.y,
+            LightType::Disk => PI * self.size.x * self.size.x,
+            LightType::Sphere => std::f32::consts::TAU * self.size.x * self.size.x,
+        }
+    }
+
+    /// Density per unit solid angle with which `sample` at `ray.origin` picks the point `t`
+    /// along `ray`, which `intersect` found on this light.
+    pub fn pdf(&self, ray: &Ray, t: f32) -> f32 {
+        let surface_point = ray.at(t);
+        let normal = match self.light_type {
+            LightType::Point | LightType::Directional | LightType::Spot => return 0.0,
+            LightType::Rectangle | LightType::Disk => self.direction.normalize(),
+            LightType::Sphere => (surface_point - self.position).normalize(),
+        };
+        let to_light = surface_point - ray.origin;
+        let distance_squared = to_light.length_squared();
+        let cos_light = normal.dot(-to_light) / distance_squared.sqrt();
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cos_light * self.sampled_area())
+    }
+
+    /// How much of a spot light's intensity goes out along the unit vector `direction`:
+    /// 1 inside the inner cone, 0 outside the outer one and a smoothstep in between.
+    pub fn spot_falloff(&self, direction: Vec3) -> f32 {
+        let cos_angle = direction.dot(self.direction.normalize());
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+        // Same as the shader, which has to avoid dividing by zero for a hard edge
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(1e-6)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Distance along `ray` to the lit side of an area light. Other lights can't be hit.
+    pub fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let t = match self.light_type {
+            LightType::Point | LightType::Directional | LightType::Spot => return None,
+            LightType::Rectangle | LightType::Disk => {
+                let (normal, tangent, bitangent) = self.area_frame();
+                let denom = normal.dot(ray.direction);
+                if denom >= 0.0 {
+                    return None; // Parallel, or looking at the dark back
+                }
+                let t = (self.position - ray.origin).dot(normal) / denom;
+                let offset = ray.at(t) - self.position;
+                let inside = if self.light_type == LightType::Rectangle {
+                    offset.dot(tangent).abs() <= 0.5 * self.size.x && offset.dot(bitangent).abs() <= 0.5 * self.size.y
+                } else {
+                    offset.length_squared() <= self.size.x * self.size.x
+                };
+                if !inside {
+                    return None;
+                }
+                t
+            }
+            LightType::Sphere => {
+                let oc = ray.origin - self.position;
+                let a = ray.direction.length_squared();
+                let half_b = oc.dot(ray.direction);
+                let c = oc.length_squared() - self.size.x * self.size.x;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let near = (-half_b - discriminant.sqrt()) / a;
+                if near < t_min {
+                    (-half_b + discriminant.sqrt()) / a
+                } else {
+                    near
+                }
+            }
+        };
+        (t_min..=t_max).contains(&t).then_some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Quat;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn rotated_cube_is_hit_on_its_edge() {
+        let cube = Cube {
+            min: Vec3::splat(-0.5),
+            max: Vec3::splat(0.5),
+            material: Material::default(),
+            transform: Transform {
+                rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_4),
+                ..Default::default()
+            },
+        };
+
+        // Turned 45 degrees, the edge between two faces points at the ray
+        let hit = cube.intersect(&Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::Z), 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - (5.0 - 0.5 * std::f32::consts::SQRT_2)).abs() < 1e-5);
+        assert!((hit.point.z - 0.5 * std::f32::consts::SQRT_2).abs() < 1e-5);
+        assert!((hit.normal.length() - 1.0).abs() < 1e-5);
+
+        // Seen along the diagonal the box is wider than its sides
+        assert!(cube.intersect(&Ray::new(Vec3::new(0.65, 0.0, 5.0), -Vec3::Z), 0.001, f32::INFINITY).is_some());
+        assert!(cube.intersect(&Ray::new(Vec3::new(0.75, 0.0, 5.0), -Vec3::Z), 0.001, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn scaled_sphere_is_an_ellipsoid() {
+        let ellipsoid = Sphere {
+            center: Vec3::ZERO,
+            radius: 1.0,
+            material: Material::default(),
+            transform: Transform {
+                scale: Vec3::new(2.0, 1.0, 1.0),
+                ..Default::default()
+            },
+        };
+
+        let hit = ellipsoid.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), -Vec3::X), 0.001, f32::INFINITY).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!((hit.normal - Vec3::X).length() < 1e-5);
+
+        // On x^2/4 + y^2 = 1 the normal is proportional to (x/4, y), not to the position
+        let hit = ellipsoid.intersect(&Ray::new(Vec3::new(1.0, 5.0, 0.0), -Vec3::Y), 0.001, f32::INFINITY).unwrap();
+        let y = 0.75f32.sqrt();
+        assert!((hit.point - Vec3::new(1.0, y, 0.0)).length() < 1e-5);
+        assert!((hit.normal - Vec3::new(0.25, y, 0.0).normalize()).length() < 1e-5);
+    }
+
+    #[test]
+    fn rectangle_light_shines_one_way() {
+        let light = Light {
+            light_type: LightType::Rectangle,
+            position: Vec3::new(0.0, 10.0, 0.0),
+            direction: -Vec3::Y,
+            color: Vec3::ONE,
+            intensity: 2.0,
+            size: Vec2::new(0.2, 0.1),
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // From far away it is about as bright as radiance * area / PI at the inverse square
+        let sample = light.sample(Vec3::ZERO, LightUnits::Radiometric, &mut rng).unwrap();
+        let expected = 2.0 * 0.02 / (std::f32::consts::PI * 100.0);
+        assert!((sample.intensity.x - expected).abs() < expected * 1e-3);
+        assert!((sample.direction - Vec3::Y).length() < 0.02);
+        assert!(light.sample(Vec3::new(0.0, 20.0, 0.0), LightUnits::Radiometric, &mut rng).is_none());
+
+        let up = Ray::new(Vec3::new(0.05, 0.0, 0.0), Vec3::Y);
+        assert_eq!(light.intersect(&up, 0.001, f32::INFINITY), Some(10.0));
+        let down = Ray::new(Vec3::new(0.05, 20.0, 0.0), -Vec3::Y);
+        assert_eq!(light.intersect(&down, 0.001, f32::INFINITY), None);
+        let beside = Ray::new(Vec3::new(0.0, 0.0, 0.06), Vec3::Y);
+        assert_eq!(light.intersect(&beside, 0.001, f32::INFINITY), None);
+    }
+
+    #[test]
+    fn spot_light_fades_out_between_its_cones() {
+        let spot = Light {
+            light_type: LightType::Spot,
+            position: Vec3::new(0.0, 1.0, 0.0),
+            direction: -Vec3::Y,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            size: Vec2::ZERO,
+            inner_angle: 20.0,
+            outer_angle: 40.0,
+        };
+        let mut rng = StdRng::seed_from_u64(2);
+        let at_angle = |degrees: f32| Vec3::new(degrees.to_radians().tan(), 0.0, 0.0);
+
+        assert_eq!(spot.sample(at_angle(10.0), LightUnits::Legacy, &mut rng).unwrap().intensity, Vec3::ONE);
+        let half = spot.sample(at_angle(30.0), LightUnits::Legacy, &mut rng).unwrap().intensity.x;
+        assert!(half > 0.3 && half < 0.7, "{}", half);
+        assert!(spot.sample(at_angle(50.0), LightUnits::Legacy, &mut rng).is_none());
+        assert!(spot.sample(Vec3::new(0.0, 2.0, 0.0), LightUnits::Legacy, &mut rng).is_none());
+    }
 }
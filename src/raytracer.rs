@@ -1,22 +1,60 @@
+use crate::brdf::{fresnel_schlick, Ggx, Lobe, PrincipledBsdf, SHARP_ROUGHNESS};
 use crate::camera::Camera;
-use crate::math::{Ray, random_unit_vector, reflect, refract, reflectance};
+use crate::film::{self, Film, FilterType};
+use crate::hdr_image::HdrImage;
+use crate::math::{Ray, random_unit_vector, refract, reflectance};
 use crate::primitives::MaterialType;
+use crate::sampler::{SamplerRng, SamplerType};
 use crate::scene::Scene;
-use glam::Vec3;
+use crate::tone_mapping::ToneMapping;
+use glam::{Vec2, Vec3, Vec4};
 use rand::Rng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum RenderMode {
     Raytracing,
     Pathtracing,
 }
 
+/// Where the image is rendered. The GPU backend runs `gpu_raytracer.wgsl` and needs
+/// compute shader support, see `GpuRaytracer::is_supported`.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Backend {
+    #[default]
+    Cpu,
+    Gpu,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Raytracer {
     pub width: u32,
     pub height: u32,
     pub max_bounces: u32,
     pub samples_per_pixel: u32,
     pub mode: RenderMode,
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default)]
+    pub seed: u64, // Picks the random streams, so the same seed gives the same image
+    #[serde(default)]
+    pub sampler: SamplerType, // How the random numbers of the CPU backend spread over each pixel
+    #[serde(default)]
+    pub filter: FilterType, // How the CPU backend weighs the samples around each pixel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare_filter: Option<FilterType>, // Filters the right half of the image instead, to compare
+    #[serde(default = "default_roulette_depth")]
+    pub roulette_depth: u32, // Pathtracing bounces before Russian roulette may end a path
+    #[serde(default)]
+    pub tone_mapping: ToneMapping, // How the HDR image is shown, changing it needs no new samples
+}
+
+fn default_roulette_depth() -> u32 {
+    3
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -32,6 +70,7 @@ pub struct RayPath {
     pub points: Vec<Vec3>,
     pub segment_types: Vec<RaySegmentType>, // Type of each segment (length = points.len() - 1)
     pub hit: bool,
+    pub light_rays: Vec<(Vec3, Vec3)>, // Shadow rays from shaded hits to the lights that reach them
 }
 
 impl Default for Raytracer {
@@ -42,248 +81,478 @@ impl Default for Raytracer {
             max_bounces: 3,
             samples_per_pixel: 1,
             mode: RenderMode::Raytracing,
+            backend: Backend::Cpu,
+            seed: 0,
+            sampler: SamplerType::default(),
+            filter: FilterType::default(),
+            compare_filter: None,
+            roulette_depth: default_roulette_depth(),
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+/// Running sum of path-traced samples kept across frames so the image keeps
+/// converging while nothing changes.
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub buffer: Vec<Vec4>, // Filter-weighted HDR sum of all samples per pixel, see `Film`
+    pub sample_count: u32, // Samples per pixel accumulated so far
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            buffer: vec![Vec4::ZERO; (width * height) as usize],
+            sample_count: 0,
         }
     }
+
+    /// Matches the buffer to a new resolution, dropping all samples if it changed.
+    pub fn ensure_size(&mut self, width: u32, height: u32) {
+        if self.width != width || self.height != height {
+            *self = Self::new(width, height);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|c| *c = Vec4::ZERO);
+        self.sample_count = 0;
+    }
+
+    /// Adds the samples of `film` to the pixels it covers.
+    pub fn add(&mut self, film: &Film) {
+        for (index, sum) in film.pixel_indices(self.width).zip(&film.pixels) {
+            self.buffer[index] += *sum;
+        }
+    }
+
+    /// Averages the accumulated samples into an RGBA8 buffer for display.
+    pub fn to_rgba(&self, tone_mapping: ToneMapping) -> Vec<u8> {
+        self.buffer.iter().flat_map(|&sum| tone_mapping.to_rgba(film::resolve(sum))).collect()
+    }
+}
+
+/// Refracts `direction` through a smooth surface with outward `normal`, or reflects it with
+/// the probability given by the Fresnel term.
+fn scatter_dielectric(direction: Vec3, normal: Vec3, ior: f32, rng: &mut impl Rng) -> Vec3 {
+    let unit_direction = direction.normalize();
+    let (normal, refraction_ratio) = if unit_direction.dot(normal) < 0.0 {
+        (normal, 1.0 / ior)
+    } else {
+        (-normal, ior)
+    };
+
+    let cos_theta = (-unit_direction).dot(normal).min(1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+
+    if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen::<f32>() {
+        unit_direction.reflect(normal)
+    } else {
+        refract(unit_direction, normal, refraction_ratio)
+    }
+}
+
+/// Weight of a sample picked with density `pdf` when another strategy could have picked the
+/// same one with density `other`. Veach's power heuristic with an exponent of 2.
+fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    if pdf.is_infinite() {
+        return 1.0; // A point light, which the other strategy can't find
+    }
+    if pdf <= 0.0 {
+        return 0.0;
+    }
+    let ratio = other / pdf;
+    1.0 / (1.0 + ratio * ratio)
+}
+
+/// Edge length in pixels of the square tiles the image is split into.
+const TILE_SIZE: u32 = 16;
+
+/// Shadow rays per area light at each raytraced hit. Without bounces to average over,
+/// a single one would leave the penumbra grainy.
+const AREA_LIGHT_SAMPLES: u32 = 4;
+
+/// Rectangular block of pixels, the unit of work for parallel and worker rendering.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
 impl Raytracer {
-    pub fn render(&self, scene: &Scene, camera: &Camera) -> Vec<u8> {
-        let mut buffer = vec![0; (self.width * self.height * 4) as usize];
-        let mut rng = rand::thread_rng();
+    /// Renders the image in linear HDR colors. `tone_mapping` turns them into display colors.
+    pub fn render(&self, scene: &Scene, camera: &Camera) -> HdrImage {
+        let pixels = self.render_samples(scene, camera, 0).pixels.into_iter().map(film::resolve).collect();
+        HdrImage { width: self.width, height: self.height, pixels }
+    }
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let mut color = Vec3::ZERO;
-                
-                // Multi-sampling with random jittering
-                for _ in 0..self.samples_per_pixel {
-                    let random_u: f32 = rng.gen();
-                    let random_v: f32 = rng.gen();
-                    
-                    let u = (x as f32 + random_u) / self.width as f32;
-                    let v = 1.0 - (y as f32 + random_v) / self.height as f32; // Flip Y
-                    
-                    let ray = camera.get_ray(u, v);
-                    
-                    match self.mode {
-                        RenderMode::Raytracing => {
-                            color += self.trace_ray(ray, scene, self.max_bounces);
-                        }
-                        RenderMode::Pathtracing => {
-                            color += self.trace_pathtrace(ray, scene, self.max_bounces);
-                        }
-                    }
-                }
-                
-                // Average the samples
-                color /= self.samples_per_pixel as f32;
-
-                // Clamp and convert to RGBA
-                let r = (color.x.clamp(0.0, 1.0) * 255.0) as u8;
-                let g = (color.y.clamp(0.0, 1.0) * 255.0) as u8;
-                let b = (color.z.clamp(0.0, 1.0) * 255.0) as u8;
-
-                let index = ((y * self.width + x) * 4) as usize;
-                buffer[index] = r;
-                buffer[index + 1] = g;
-                buffer[index + 2] = b;
-                buffer[index + 3] = 255;
+    /// Adds `samples_per_pixel` new samples to every pixel of `accumulator`.
+    /// The accumulator is resized (and thereby reset) if the resolution changed.
+    pub fn accumulate(&self, scene: &Scene, camera: &Camera, accumulator: &mut Accumulator) {
+        accumulator.ensure_size(self.width, self.height);
+
+        // The running sample count doubles as pass index so every pass gets fresh random numbers
+        accumulator.add(&self.render_samples(scene, camera, accumulator.sample_count));
+
+        accumulator.sample_count += self.samples_per_pixel;
+    }
+
+    pub fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(TILE_SIZE as usize) {
+            for x in (0..self.width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(self.width - x),
+                    height: TILE_SIZE.min(self.height - y),
+                });
             }
         }
+        tiles
+    }
 
-        buffer
+    /// `samples_per_pixel` samples of every pixel on a film of the whole image.
+    /// Tiles are rendered in parallel on native builds with the `parallel` feature.
+    fn render_samples(&self, scene: &Scene, camera: &Camera, pass: u32) -> Film {
+        let tiles = self.tiles();
+        let render_tile = |tile: &Tile| self.render_tile(scene, camera, tile, pass);
+
+        #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+        let tile_films: Vec<Film> = tiles.par_iter().map(render_tile).collect();
+        #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+        let tile_films: Vec<Film> = tiles.iter().map(render_tile).collect();
+
+        // Added in tile order, so the sums don't depend on which tile finished first
+        let mut image = Film::new(0, 0, self.width, self.height);
+        for tile_film in &tile_films {
+            for (index, sum) in tile_film.pixel_indices(self.width).zip(&tile_film.pixels) {
+                image.pixels[index] += *sum;
+            }
+        }
+        image
     }
 
-    pub fn trace_ray(&self, ray: Ray, scene: &Scene, depth: u32) -> Vec3 {
+    /// Samples of the pixels of one tile. The film reaches past the tile as far as the filter
+    /// spreads its samples.
+    /// Every pixel sample draws its own random numbers from `seed`, its pixel and its index,
+    /// which starts at `pass`, so the same pass always gives the same pixels.
+    pub fn render_tile(&self, scene: &Scene, camera: &Camera, tile: &Tile, pass: u32) -> Film {
+        // How far the filter reaches into neighboring pixels
+        let margin = (self.filter_radius() - 0.5).ceil() as u32;
+        let x = tile.x.saturating_sub(margin);
+        let y = tile.y.saturating_sub(margin);
+        let width = (tile.x + tile.width + margin).min(self.width) - x;
+        let height = (tile.y + tile.height + margin).min(self.height) - y;
+        let mut film = Film::new(x, y, width, height);
+
+        let mut sampler = self.sampler.build(self.seed, self.samples_per_pixel);
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                self.sample_pixel(x, y, scene, camera, pass, &mut sampler, &mut film);
+            }
+        }
+        film
+    }
+
+    fn filter_radius(&self) -> f32 {
+        self.compare_filter.map_or(0.0, FilterType::radius).max(self.filter.radius())
+    }
+
+    /// The filter of image column `x`.
+    fn filter_at(&self, x: u32) -> FilterType {
+        match self.compare_filter {
+            Some(filter) if x >= self.width / 2 => filter,
+            _ => self.filter,
+        }
+    }
+
+    /// Adds `samples_per_pixel` jittered samples through pixel (x, y) to `film`,
+    /// continuing from sample number `first_sample` of the pixel.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(&self, x: u32, y: u32, scene: &Scene, camera: &Camera, first_sample: u32, rng: &mut SamplerRng, film: &mut Film) {
+        let radius = self.filter_radius();
+
+        // Multi-sampling with random jittering
+        for sample in 0..self.samples_per_pixel {
+            rng.start_pixel_sample(x, y, first_sample + sample);
+            let position = Vec2::new(x as f32 + rng.gen::<f32>(), y as f32 + rng.gen::<f32>());
+
+            let u = position.x / self.width as f32;
+            let v = 1.0 - position.y / self.height as f32; // Flip Y
+
+            let ray = camera.get_ray(u, v);
+
+            let color = match self.mode {
+                RenderMode::Raytracing => self.trace_ray(ray, scene, self.max_bounces, rng),
+                RenderMode::Pathtracing => self.trace_pathtrace(ray, scene, self.max_bounces, rng),
+            };
+            film.add_sample(position, color, radius, |x| self.filter_at(x));
+        }
+    }
+
+    pub fn trace_ray(&self, ray: Ray, scene: &Scene, depth: u32, rng: &mut impl Rng) -> Vec3 {
         if depth == 0 {
             return Vec3::ZERO;
         }
 
-        if let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) {
-            let mut color = Vec3::ZERO;
+        let hit = scene.intersect(&ray, 0.001, f32::INFINITY);
+        let hit_t = hit.as_ref().map_or(f32::INFINITY, |hit| hit.t);
+        if let Some((_, light)) = scene.intersect_lights(&ray, 0.001, hit_t) {
+            return light.color * light.intensity;
+        }
+
+        if let Some(hit) = hit {
+            let mut color = hit.material.emission;
             let view_dir = -ray.direction;
+            let principled = (hit.material.mat_type == MaterialType::Principled)
+                .then(|| PrincipledBsdf::new(&hit.material, hit.normal, view_dir.normalize()));
 
             // Ambient
             color += hit.material.color * 0.1;
 
             // Diffuse and Specular
             for light in &scene.lights {
-                let (light_dir, distance) = match light.light_type {
-                    crate::primitives::LightType::Directional => (-light.direction.normalize(), f32::INFINITY),
-                    crate::primitives::LightType::Point => {
-                        let dir = light.position - hit.point;
-                        (dir.normalize(), dir.length())
+                let samples = if light.light_type.is_area() { AREA_LIGHT_SAMPLES } else { 1 };
+                for _ in 0..samples {
+                    let Some(sample) = light.sample(hit.point, scene.light_units, rng) else {
+                        continue;
+                    };
+                    let light_dir = sample.direction;
+                    let intensity = sample.intensity / samples as f32;
+
+                    // Shadow ray
+                    let shadow_ray = Ray::new(hit.point, light_dir);
+                    if scene.intersect(&shadow_ray, 0.001, sample.distance).is_none() {
+                        if let Some(bsdf) = &principled {
+                            // Sharp lobes show up through the reflection ray below
+                            color += bsdf.eval(view_dir.normalize(), light_dir) * intensity * PI;
+                            continue;
+                        }
+
+                        // Diffuse
+                        let diff = hit.normal.dot(light_dir).max(0.0);
+                        color += hit.material.color * intensity * diff;
+
+                        // Specular
+                        let reflect_dir = (-light_dir).reflect(hit.normal);
+                        let spec = view_dir.dot(reflect_dir).max(0.0).powf(hit.material.shininess);
+                        color += intensity * hit.material.specular * spec;
                     }
-                };
-                
-                // Shadow ray
-                let shadow_ray = Ray::new(hit.point, light_dir);
-                if scene.intersect(&shadow_ray, 0.001, distance).is_none() {
-                    // Diffuse
-                    let diff = hit.normal.dot(light_dir).max(0.0);
-                    color += hit.material.color * light.color * diff * light.intensity;
-
-                    // Specular
-                    let reflect_dir = (-light_dir).reflect(hit.normal);
-                    let spec = view_dir.dot(reflect_dir).max(0.0).powf(hit.material.shininess);
-                    color += light.color * hit.material.specular * spec * light.intensity;
                 }
             }
 
+            // A principled material traces one mirror and one refracted ray for all its layers
+            if let Some(bsdf) = &principled {
+                let reflectance = bsdf.mirror_reflectance(view_dir.normalize());
+                if reflectance.max_element() > 0.0 {
+                    let reflected_ray = Ray::new(hit.point, ray.direction.reflect(hit.normal));
+                    color += self.trace_ray(reflected_ray, scene, depth - 1, rng) * reflectance;
+                }
+                let transmittance = bsdf.transmittance(view_dir.normalize());
+                if transmittance.max_element() > 0.0 {
+                    let refracted_ray = Ray::new(hit.point, scatter_dielectric(ray.direction, hit.normal, hit.material.ior, rng));
+                    color += self.trace_ray(refracted_ray, scene, depth - 1, rng) * transmittance;
+                }
+                return color;
+            }
+
             // Reflection (Whitted style)
             if hit.material.reflectivity > 0.0 {
                 let reflected_ray = Ray::new(hit.point, ray.direction.reflect(hit.normal));
-                color += self.trace_ray(reflected_ray, scene, depth - 1) * hit.material.reflectivity;
+                color += self.trace_ray(reflected_ray, scene, depth - 1, rng) * hit.material.reflectivity;
             }
             
             // Refraction (Whitted style) - Basic implementation for Dielectric
             if hit.material.mat_type == MaterialType::Dielectric {
-                let unit_direction = ray.direction.normalize();
-                let dot = unit_direction.dot(hit.normal);
-                let (normal, refraction_ratio) = if dot < 0.0 {
-                    (hit.normal, 1.0 / hit.material.ior)
-                } else {
-                    (-hit.normal, hit.material.ior)
-                };
-
-                let cos_theta = (-unit_direction).dot(normal).min(1.0);
-                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-                let cannot_refract = refraction_ratio * sin_theta > 1.0;
-                let direction;
-
-                if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen() {
-                    direction = unit_direction.reflect(normal);
-                } else {
-                    direction = refract(unit_direction, normal, refraction_ratio);
-                }
-                
+                let direction = scatter_dielectric(ray.direction, hit.normal, hit.material.ior, rng);
                 let refracted_ray = Ray::new(hit.point, direction);
-                return self.trace_ray(refracted_ray, scene, depth - 1);
+                return self.trace_ray(refracted_ray, scene, depth - 1, rng);
             }
 
             color
         } else {
-            // Background color (sky gradient)
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t
+            scene.background.radiance(ray.direction)
         }
     }
 
-    pub fn trace_pathtrace(&self, ray: Ray, scene: &Scene, depth: u32) -> Vec3 {
-        if depth == 0 {
-            return Vec3::ZERO;
-        }
+    /// Follows one path for up to `depth` bounces, adding up the light it gathers weighted by
+    /// its throughput. Past `roulette_depth` bounces, paths end at random with a chance that
+    /// grows as their throughput falls, and the survivors count for the ones that ended.
+    ///
+    /// Lights and an environment map are sampled directly at every surface but mirrors and
+    /// glass, and the rays scattered off those surfaces can find them too. `bsdf_pdf` is the
+    /// density with which the last surface picked `ray`, to weigh the two ways of finding the
+    /// same light against each other with the power heuristic. Camera rays and sharp bounces
+    /// have none, and count what they find in full.
+    pub fn trace_pathtrace(&self, ray: Ray, scene: &Scene, depth: u32, rng: &mut impl Rng) -> Vec3 {
+        let mut ray = ray;
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut bsdf_pdf: Option<f32> = None;
+
+        for bounce in 0..depth {
+            let hit = scene.intersect(&ray, 0.001, f32::INFINITY);
+            let hit_t = hit.as_ref().map_or(f32::INFINITY, |hit| hit.t);
+            if let Some((t, light)) = scene.intersect_lights(&ray, 0.001, hit_t) {
+                let radiance = light.color * light.intensity;
+                color += throughput * match bsdf_pdf {
+                    Some(pdf) => radiance * power_heuristic(pdf, light.pdf(&ray, t)),
+                    None => radiance,
+                };
+                break;
+            }
 
-        if let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) {
-            let mut direct_light = Vec3::ZERO;
+            let Some(hit) = hit else {
+                let radiance = scene.background.radiance(ray.direction);
+                color += throughput * match bsdf_pdf {
+                    Some(pdf) if scene.background.is_sampled() => {
+                        radiance * power_heuristic(pdf, scene.background.pdf(ray.direction))
+                    }
+                    _ => radiance,
+                };
+                break;
+            };
+
+            let mut direct_light = hit.material.emission;
             
             // 1. Direct Lighting (Next Event Estimation)
             // We explicitly sample lights for non-specular materials.
             // For perfect specular (Glass, Mirror), the probability of hitting a point light is 0,
-            // so we rely entirely on the recursive ray.
+            // so we rely entirely on the scattered ray.
             
             let is_specular = match hit.material.mat_type {
                 MaterialType::Dielectric => true,
-                MaterialType::Metal => hit.material.roughness < 0.05, // Treat very smooth metal as specular
-                MaterialType::Lambertian => false,
+                MaterialType::Metal => hit.material.roughness < SHARP_ROUGHNESS, // Treat very smooth metal as specular
+                // Light sampling skips only its sharp lobes
+                MaterialType::Lambertian | MaterialType::Principled => false,
             };
 
+            let view_dir = -ray.direction.normalize();
+            let facing_normal = if hit.normal.dot(view_dir) < 0.0 { -hit.normal } else { hit.normal };
+            let principled = (hit.material.mat_type == MaterialType::Principled)
+                .then(|| PrincipledBsdf::new(&hit.material, hit.normal, view_dir));
+
+            // BSDF times cosine towards `light_dir`, and the density with which the bounce
+            // below picks that direction. Both are zero for perfect specular surfaces.
+            let eval_bsdf = |light_dir: Vec3| match (hit.material.mat_type, &principled) {
+                (MaterialType::Lambertian, _) => {
+                    let cos_theta = hit.normal.dot(light_dir).max(0.0);
+                    (hit.material.color / PI * cos_theta, cos_theta / PI)
+                }
+                (MaterialType::Metal, _) if !is_specular => {
+                    let ggx = Ggx::new(hit.material.color, hit.material.roughness);
+                    (ggx.eval(facing_normal, view_dir, light_dir), ggx.pdf(facing_normal, view_dir, light_dir))
+                }
+                (MaterialType::Principled, Some(bsdf)) => (bsdf.eval(view_dir, light_dir), bsdf.pdf(view_dir, light_dir)),
+                _ => (Vec3::ZERO, 0.0),
+            };
+
+            // Without a bounce to follow, light sampling has to find the lights by itself
+            let last_bounce = bounce + 1 == depth;
+            let light_weight = |light_pdf: f32, pdf: f32| if last_bounce { 1.0 } else { power_heuristic(light_pdf, pdf) };
+
             if !is_specular {
                 for light in &scene.lights {
-                    let (light_dir, distance) = match light.light_type {
-                        crate::primitives::LightType::Directional => (-light.direction.normalize(), f32::INFINITY),
-                        crate::primitives::LightType::Point => {
-                            let dir = light.position - hit.point;
-                            (dir.normalize(), dir.length())
-                        }
+                    let Some(sample) = light.sample(hit.point, scene.light_units, rng) else {
+                        continue;
                     };
 
                     // Shadow ray
-                    let shadow_ray = Ray::new(hit.point, light_dir);
-                    if scene.intersect(&shadow_ray, 0.001, distance).is_none() {
-                        let cos_theta = hit.normal.dot(light_dir).max(0.0);
-                        
-                        if hit.material.mat_type == MaterialType::Lambertian {
-                            // Diffuse: color * light * cos_theta
-                            // We assume light intensity handles falloff/energy
-                            direct_light += hit.material.color * light.color * light.intensity * cos_theta;
-                        } else if hit.material.mat_type == MaterialType::Metal {
-                            // Rough Metal: Specular highlight
-                            // Simple Blinn-Phong-like approximation for direct light on rough metal
-                            let view_dir = -ray.direction.normalize();
-                            let halfway = (light_dir + view_dir).normalize();
-                            let spec = hit.normal.dot(halfway).max(0.0).powf(2.0 / hit.material.roughness.max(0.01));
-                            direct_light += light.color * hit.material.color * light.intensity * spec;
-                        }
+                    let shadow_ray = Ray::new(hit.point, sample.direction);
+                    if scene.intersect(&shadow_ray, 0.001, sample.distance).is_none() {
+                        // Intensities are relative to a diffuse surface, hence the PI.
+                        // The sample's intensity already includes falloff/energy
+                        let (reflected, pdf) = eval_bsdf(sample.direction);
+                        direct_light += reflected * sample.intensity * PI * light_weight(sample.pdf, pdf);
+                    }
+                }
+
+                if let Some(sample) = scene.background.sample(rng) {
+                    let (reflected, pdf) = eval_bsdf(sample.direction);
+                    let shadow_ray = Ray::new(hit.point, sample.direction);
+                    let unblocked = scene.intersect(&shadow_ray, 0.001, f32::INFINITY).is_none()
+                        && scene.intersect_lights(&shadow_ray, 0.001, f32::INFINITY).is_none();
+                    if reflected != Vec3::ZERO && unblocked {
+                        direct_light += reflected * sample.radiance / sample.pdf * light_weight(sample.pdf, pdf);
                     }
                 }
             }
+            color += throughput * direct_light;
+            if last_bounce {
+                break;
+            }
 
-            // 2. Indirect Lighting (Recursive Ray)
+            // 2. Indirect Lighting (Scattered Ray)
             let scatter_direction;
             let attenuation;
+            // Whether the bounce went where light sampling above doesn't look
+            let mut sharp = is_specular;
 
             match hit.material.mat_type {
                 MaterialType::Lambertian => {
                     // Cosine weighted sampling
-                    scatter_direction = (hit.normal + random_unit_vector()).normalize();
+                    scatter_direction = (hit.normal + random_unit_vector(rng)).normalize();
                     attenuation = hit.material.color;
                 }
+                MaterialType::Metal if is_specular => {
+                    scatter_direction = (-view_dir).reflect(facing_normal);
+                    attenuation = fresnel_schlick(hit.material.color, facing_normal.dot(view_dir));
+                }
                 MaterialType::Metal => {
-                    let reflected = ray.direction.normalize().reflect(hit.normal);
-                    scatter_direction = reflected + random_unit_vector() * hit.material.roughness;
-                    attenuation = hit.material.color;
-                    
-                    if scatter_direction.dot(hit.normal) <= 0.0 {
-                        return direct_light; // Absorbed
-                    }
+                    let ggx = Ggx::new(hit.material.color, hit.material.roughness);
+                    let Some((light_dir, weight)) = ggx.sample(facing_normal, view_dir, rng) else {
+                        break; // Absorbed
+                    };
+                    scatter_direction = light_dir;
+                    attenuation = weight;
+                }
+                MaterialType::Principled => {
+                    let Some(sample) = principled.as_ref().and_then(|bsdf| bsdf.sample(view_dir, rng)) else {
+                        break; // Absorbed
+                    };
+                    scatter_direction = sample.direction;
+                    attenuation = sample.weight;
+                    sharp = !sample.lobe.is_light_sampled();
                 }
                 MaterialType::Dielectric => {
                     attenuation = Vec3::ONE;
-                    let unit_direction = ray.direction.normalize();
-                    let dot = unit_direction.dot(hit.normal);
-                    let (normal, refraction_ratio) = if dot < 0.0 {
-                        (hit.normal, 1.0 / hit.material.ior)
-                    } else {
-                        (-hit.normal, hit.material.ior)
-                    };
-
-                    let cos_theta = (-unit_direction).dot(normal).min(1.0);
-                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-                    let cannot_refract = refraction_ratio * sin_theta > 1.0;
-
-                    if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen() {
-                        scatter_direction = unit_direction.reflect(normal);
-                    } else {
-                        scatter_direction = refract(unit_direction, normal, refraction_ratio);
-                    }
+                    scatter_direction = scatter_dielectric(ray.direction, hit.normal, hit.material.ior, rng);
                 }
             }
 
-            let scattered_ray = Ray::new(hit.point, scatter_direction);
-            
-            // For Lambertian, we effectively average the indirect light.
-            // Since we added direct light, we shouldn't double count it.
-            // But our lights are invisible (analytical), so they won't be hit by scattered_ray.
-            // However, the sky IS visible.
-            // If we hit the sky with scattered_ray, that's "ambient" light.
-            // So: Result = Direct + Attenuation * Indirect
-            
-            return direct_light + attenuation * self.trace_pathtrace(scattered_ray, scene, depth - 1);
-
-        } else {
-            // Background color (sky gradient)
-            let unit_direction = ray.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            return Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t;
+            // Whatever light sampling could also have found is weighted by how likely the
+            // bounce was to find it instead. Point and directional lights are invisible
+            // (analytical), so they won't be hit by the scattered ray.
+            bsdf_pdf = (!sharp).then(|| eval_bsdf(scatter_direction).1);
+            throughput *= attenuation;
+            ray = Ray::new(hit.point, scatter_direction);
+
+            // Russian roulette: dim paths end early, the surviving ones make up for them
+            if bounce + 1 >= self.roulette_depth {
+                let survival = throughput.max_element().min(1.0);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
         }
+
+        color
     }
 
+    /// Paths for the ray visualization, seeded from `seed` like the image so they stay put between frames.
     pub fn trace_paths(&self, scene: &Scene, camera: &Camera, count: usize) -> Vec<RayPath> {
         let mut paths = Vec::new();
-        let mut rng = rand::thread_rng();
+        // A stream no pixel sample uses
+        let mut rng = Pcg32::new(0xcafe_f00d_d15e_a5e5 ^ self.seed, u64::MAX);
 
         for _ in 0..count {
             let u = rng.gen::<f32>();
@@ -294,11 +563,12 @@ impl Raytracer {
                 points: vec![ray.origin],
                 segment_types: Vec::new(),
                 hit: false,
+                light_rays: Vec::new(),
             };
 
             match self.mode {
-                RenderMode::Raytracing => self.trace_path_recursive_raytracing(ray, scene, self.max_bounces, &mut path, true),
-                RenderMode::Pathtracing => self.trace_path_recursive_pathtracing(ray, scene, self.max_bounces, &mut path, true),
+                RenderMode::Raytracing => self.trace_path_raytracing(ray, scene, &mut path, &mut rng),
+                RenderMode::Pathtracing => self.trace_path_pathtracing(ray, scene, &mut path, &mut rng),
             }
             
             paths.push(path);
@@ -307,16 +577,28 @@ impl Raytracer {
         paths
     }
 
-    fn trace_path_recursive_raytracing(&self, ray: Ray, scene: &Scene, depth: u32, path: &mut RayPath, is_primary: bool) {
-        if depth == 0 {
-            path.points.push(ray.at(2.0));
-            if !path.points.is_empty() {
-                path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
+    /// Shadow rays from `point` to every light that reaches it, cut off at the length of a miss.
+    fn add_light_rays(&self, scene: &Scene, point: Vec3, path: &mut RayPath, rng: &mut impl Rng) {
+        for light in &scene.lights {
+            let Some(sample) = light.sample(point, scene.light_units, rng) else {
+                continue;
+            };
+            if scene.intersect(&Ray::new(point, sample.direction), 0.001, sample.distance).is_none() {
+                path.light_rays.push((point, point + sample.direction * sample.distance.min(5.0)));
             }
-            return;
         }
+    }
 
-        if let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) {
+    /// Follows the ray the way `trace_ray` does, one bounce at a time like `trace_pathtrace`.
+    fn trace_path_raytracing(&self, ray: Ray, scene: &Scene, path: &mut RayPath, rng: &mut impl Rng) {
+        let mut ray = ray;
+        for bounce in 0..self.max_bounces {
+            let is_primary = bounce == 0;
+            let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) else {
+                path.points.push(ray.at(5.0));
+                path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
+                return;
+            };
             path.points.push(hit.point);
             path.hit = true;
 
@@ -337,7 +619,7 @@ impl Raytracer {
                 let direction;
                 let segment_type;
                 let reflectance_value = reflectance(cos_theta, refraction_ratio);
-                let random_value = rand::thread_rng().gen::<f32>();
+                let random_value = rng.gen::<f32>();
                 if cannot_refract || reflectance_value > random_value {
                     direction = unit_direction.reflect(normal);
                     segment_type = RaySegmentType::Reflection;
@@ -346,78 +628,281 @@ impl Raytracer {
                     segment_type = RaySegmentType::Refraction;
                 }
                 path.segment_types.push(if is_primary { RaySegmentType::Primary } else { segment_type });
-                let refracted_ray = Ray::new(hit.point, direction);
-                self.trace_path_recursive_raytracing(refracted_ray, scene, depth - 1, path, false);
+                ray = Ray::new(hit.point, direction);
             } else if hit.material.reflectivity > 0.0 {
+                self.add_light_rays(scene, hit.point, path, rng);
                 path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Reflection });
-                let reflected_ray = Ray::new(hit.point, ray.direction.reflect(hit.normal));
-                self.trace_path_recursive_raytracing(reflected_ray, scene, depth - 1, path, false);
+                ray = Ray::new(hit.point, ray.direction.reflect(hit.normal));
             } else {
+                self.add_light_rays(scene, hit.point, path, rng);
                 path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
+                return;
             }
-        } else {
-            path.points.push(ray.at(5.0));
-            path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
         }
+        self.end_path(ray, path);
     }
 
-    fn trace_path_recursive_pathtracing(&self, ray: Ray, scene: &Scene, depth: u32, path: &mut RayPath, is_primary: bool) {
-        if depth == 0 {
-            path.points.push(ray.at(2.0));
-            if !path.points.is_empty() {
+    /// Follows the ray the way `trace_pathtrace` scatters it, one bounce at a time.
+    fn trace_path_pathtracing(&self, ray: Ray, scene: &Scene, path: &mut RayPath, rng: &mut impl Rng) {
+        let mut ray = ray;
+        for bounce in 0..self.max_bounces {
+            let is_primary = bounce == 0;
+            let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) else {
+                path.points.push(ray.at(5.0));
                 path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
-            }
-            return;
-        }
-
-        if let Some(hit) = scene.intersect(&ray, 0.001, f32::INFINITY) {
+                return;
+            };
             path.points.push(hit.point);
             path.hit = true;
 
+            // Same test as `trace_pathtrace` for where lights are sampled
+            let is_specular = match hit.material.mat_type {
+                MaterialType::Dielectric => true,
+                MaterialType::Metal => hit.material.roughness < SHARP_ROUGHNESS,
+                MaterialType::Lambertian | MaterialType::Principled => false,
+            };
+            if !is_specular {
+                self.add_light_rays(scene, hit.point, path, rng);
+            }
+
             let scatter_direction;
             let segment_type;
             match hit.material.mat_type {
                 MaterialType::Lambertian => {
-                    scatter_direction = hit.normal + random_unit_vector();
+                    scatter_direction = hit.normal + random_unit_vector(rng);
                     segment_type = RaySegmentType::Diffuse;
                 }
                 MaterialType::Metal => {
-                    let reflected = ray.direction.normalize().reflect(hit.normal);
-                    scatter_direction = reflected + random_unit_vector() * hit.material.roughness;
+                    let view_dir = -ray.direction.normalize();
+                    let normal = if hit.normal.dot(view_dir) < 0.0 { -hit.normal } else { hit.normal };
+                    let reflected = (-view_dir).reflect(normal);
+                    scatter_direction = if is_specular {
+                        reflected
+                    } else {
+                        let ggx = Ggx::new(hit.material.color, hit.material.roughness);
+                        ggx.sample(normal, view_dir, rng).map_or(reflected, |(light_dir, _)| light_dir)
+                    };
                     segment_type = RaySegmentType::Reflection;
                 }
                 MaterialType::Dielectric => {
                     let unit_direction = ray.direction.normalize();
-                    let dot = unit_direction.dot(hit.normal);
-                    let (normal, refraction_ratio) = if dot < 0.0 {
-                        (hit.normal, 1.0 / hit.material.ior)
+                    scatter_direction = scatter_dielectric(unit_direction, hit.normal, hit.material.ior, rng);
+                    // Refracted rays keep going the same way through the surface
+                    segment_type = if scatter_direction.dot(hit.normal).signum() == unit_direction.dot(hit.normal).signum() {
+                        RaySegmentType::Refraction
                     } else {
-                        (-hit.normal, hit.material.ior)
+                        RaySegmentType::Reflection
                     };
-
-                    let cos_theta = (-unit_direction).dot(normal).min(1.0);
-                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-                    let cannot_refract = refraction_ratio * sin_theta > 1.0;
-                    
-                    let reflectance_value = reflectance(cos_theta, refraction_ratio);
-                    let random_value = rand::thread_rng().gen::<f32>();
-                    if cannot_refract || reflectance_value > random_value {
-                        scatter_direction = unit_direction.reflect(normal);
-                        segment_type = RaySegmentType::Reflection;
-                    } else {
-                        scatter_direction = refract(unit_direction, normal, refraction_ratio);
-                        segment_type = RaySegmentType::Refraction;
+                }
+                MaterialType::Principled => {
+                    let view_dir = -ray.direction.normalize();
+                    let bsdf = PrincipledBsdf::new(&hit.material, hit.normal, view_dir);
+                    match bsdf.sample(view_dir, rng) {
+                        Some(sample) => {
+                            scatter_direction = sample.direction;
+                            segment_type = match sample.lobe {
+                                Lobe::Diffuse => RaySegmentType::Diffuse,
+                                Lobe::Glossy | Lobe::Mirror => RaySegmentType::Reflection,
+                                Lobe::Transmission => RaySegmentType::Refraction,
+                            };
+                        }
+                        None => {
+                            scatter_direction = (-view_dir).reflect(hit.normal);
+                            segment_type = RaySegmentType::Reflection;
+                        }
                     }
                 }
             }
             
             path.segment_types.push(if is_primary { RaySegmentType::Primary } else { segment_type });
-            let scattered_ray = Ray::new(hit.point, scatter_direction);
-            self.trace_path_recursive_pathtracing(scattered_ray, scene, depth - 1, path, false);
+            ray = Ray::new(hit.point, scatter_direction);
+        }
+        self.end_path(ray, path);
+    }
+
+    /// A short last segment along `ray` for a path that ran out of bounces.
+    fn end_path(&self, ray: Ray, path: &mut RayPath) {
+        path.points.push(ray.at(2.0));
+        path.segment_types.push(if self.max_bounces == 0 { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::Background;
+    use crate::primitives::{Cube, Light, LightType, Material, Sphere};
+    use glam::Vec2;
+
+    /// Metal plates from sharp to rough side by side, lit by a sphere light alone, after
+    /// Veach's test of combined sampling. As an emissive object instead of a light, only
+    /// bounces find it. The plates lie in one plane, so no light reaches them indirectly.
+    fn plates_scene(emissive: bool) -> Scene {
+        let mut scene = Scene::default();
+        let plate = |z: f32, roughness| Cube {
+            min: Vec3::new(-1.5, -0.02, z - 0.3),
+            max: Vec3::new(1.5, 0.0, z + 0.3),
+            material: Material { mat_type: MaterialType::Metal, color: Vec3::splat(0.9), roughness, ..Material::default() },
+            transform: Default::default(),
+        };
+        scene.cubes = vec![plate(-1.4, SHARP_ROUGHNESS + 0.01), plate(-0.7, 0.06), plate(0.0, 0.15), plate(0.7, 0.4)];
+        scene.spheres.clear();
+        scene.planes.clear();
+        scene.background = Background::Color(Vec3::ZERO);
+
+        let (position, radius, radiance) = (Vec3::new(0.0, 1.2, -3.0), 0.3, Vec3::splat(20.0));
+        scene.lights = Vec::new();
+        if emissive {
+            let material = Material { color: Vec3::ZERO, emission: radiance, ..Material::default() };
+            scene.spheres.push(Sphere { center: position, radius, material, transform: Default::default() });
         } else {
-            path.points.push(ray.at(5.0));
-            path.segment_types.push(if is_primary { RaySegmentType::Primary } else { RaySegmentType::Diffuse });
+            scene.lights.push(Light {
+                light_type: LightType::Sphere,
+                position,
+                direction: Vec3::ZERO,
+                color: Vec3::ONE,
+                intensity: radiance.x,
+                size: Vec2::new(radius, 0.0),
+                inner_angle: 0.0,
+                outer_angle: 0.0,
+            });
+        }
+        scene.rebuild_bvh();
+        scene
+    }
+
+    /// Per-pixel averages of `samples` paths each, clamped to display range. Every path
+    /// goes through the middle of its pixel, so only the lighting is left to converge.
+    fn render_with(raytracer: &Raytracer, scene: &Scene, samples: u32, seed: u64) -> Vec<Vec3> {
+        let camera = Camera::new(Vec3::new(0.0, 1.5, 2.5), Vec3::new(0.0, 0.0, -0.5), 50.0, 4.0 / 3.0);
+        let (width, height) = (32, 24);
+        let mut rng = Pcg32::new(seed, 0);
+        let mut image = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let ray = camera.get_ray((x as f32 + 0.5) / width as f32, 1.0 - (y as f32 + 0.5) / height as f32);
+                let sum: Vec3 = (0..samples).map(|_| raytracer.trace_pathtrace(ray, scene, raytracer.max_bounces, &mut rng)).sum();
+                image.push((sum / samples as f32).clamp(Vec3::ZERO, Vec3::ONE));
+            }
+        }
+        image
+    }
+
+    fn render(scene: &Scene, samples: u32, seed: u64) -> Vec<Vec3> {
+        render_with(&Raytracer { mode: RenderMode::Pathtracing, ..Default::default() }, scene, samples, seed)
+    }
+
+    /// Mean absolute difference of two images. A few pixels at the edge of a sharp
+    /// reflection of the light converge slowly whatever the sampling, and would dominate a
+    /// squared error.
+    fn mean_error(a: &[Vec3], b: &[Vec3]) -> f32 {
+        a.iter().zip(b).map(|(&a, &b)| (a - b).abs().element_sum() / 3.0).sum::<f32>() / a.len() as f32
+    }
+
+    fn mean(image: &[Vec3]) -> f32 {
+        image.iter().map(|pixel| pixel.element_sum() / 3.0).sum::<f32>() / image.len() as f32
+    }
+
+    /// Light sampling combined with BSDF sampling converges to the same image as BSDF
+    /// sampling alone, faster than it and without fireflies holding it back.
+    #[test]
+    fn mis_converges_to_reference() {
+        let scene = plates_scene(false);
+        let reference = render(&scene, 1024, 1);
+
+        let bsdf_only = plates_scene(true);
+        let (expected, found) = (mean(&reference), mean(&render(&bsdf_only, 1024, 2)));
+        assert!((found - expected).abs() < 0.05 * expected, "{} vs {}", found, expected);
+
+        // Error falls as one over the square root of the samples, by 4 here
+        let coarse = mean_error(&render(&scene, 16, 3), &reference);
+        let fine = mean_error(&render(&scene, 256, 4), &reference);
+        assert!(fine < 0.5 * coarse, "error {} at 256 samples, {} at 16", fine, coarse);
+
+        let combined = mean_error(&render(&scene, 64, 5), &reference);
+        let bsdf_sampled = mean_error(&render(&bsdf_only, 64, 5), &reference);
+        assert!(combined < 0.4 * bsdf_sampled, "error {} with light sampling, {} without", combined, bsdf_sampled);
+
+        // Plates on either side of the threshold to perfect mirrors look the same
+        let with_roughness = |roughness| {
+            let mut scene = scene.clone();
+            for cube in &mut scene.cubes {
+                cube.material.roughness = roughness;
+            }
+            render(&scene, 64, 6)
+        };
+        let step = mean_error(&with_roughness(SHARP_ROUGHNESS - 0.005), &with_roughness(SHARP_ROUGHNESS + 0.005));
+        assert!(step < 0.1 * expected, "error {} across the mirror threshold", step);
+    }
+
+    /// Paths ended by Russian roulette are made up for by the ones that go on, so a scene
+    /// with glass and mirrors that takes many bounces comes out the same.
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let mut scene = Scene::default();
+        scene.rebuild_bvh();
+        let raytracer = |roulette_depth| Raytracer { mode: RenderMode::Pathtracing, max_bounces: 12, roulette_depth, ..Default::default() };
+
+        let reference = render_with(&raytracer(12), &scene, 256, 1);
+        let roulette = render_with(&raytracer(1), &scene, 256, 2);
+        let (expected, found) = (mean(&reference), mean(&roulette));
+        assert!((found - expected).abs() < 0.02 * expected, "{} vs {}", found, expected);
+        assert!(mean_error(&roulette, &reference) < 0.05 * expected, "{}", mean_error(&roulette, &reference));
+    }
+
+    #[test]
+    fn same_seed_gives_same_image() {
+        let mut scene = Scene::default();
+        scene.rebuild_bvh();
+        let camera = Camera::new(Vec3::new(0.0, 1.5, 2.5), Vec3::new(0.0, 0.0, -0.5), 50.0, 4.0 / 3.0);
+        let raytracer = |seed| Raytracer { width: 40, height: 30, samples_per_pixel: 2, mode: RenderMode::Pathtracing, seed, ..Default::default() };
+
+        let image = raytracer(5).render_samples(&scene, &camera, 0).pixels;
+        assert_eq!(image, raytracer(5).render_samples(&scene, &camera, 0).pixels);
+        assert_ne!(image, raytracer(6).render_samples(&scene, &camera, 0).pixels);
+        assert_ne!(image, raytracer(5).render_samples(&scene, &camera, 2).pixels);
+
+        let points = |raytracer: Raytracer| raytracer.trace_paths(&scene, &camera, 20).into_iter().flat_map(|path| path.points).collect::<Vec<_>>();
+        assert_eq!(points(raytracer(5)), points(raytracer(5)));
+    }
+
+    /// Inside a mirror ball every path uses up all its bounces, as many as the UI allows.
+    #[test]
+    fn ray_paths_follow_every_bounce() {
+        let mut scene = Scene::empty();
+        let mirror = Material { mat_type: MaterialType::Metal, roughness: 0.0, reflectivity: 1.0, ..Material::default() };
+        scene.spheres.push(Sphere { center: Vec3::ZERO, radius: 5.0, material: mirror, transform: Default::default() });
+        scene.rebuild_bvh();
+        let camera = Camera::new(Vec3::ZERO, Vec3::new(0.3, 0.2, -1.0), 50.0, 1.0);
+
+        for mode in [RenderMode::Raytracing, RenderMode::Pathtracing] {
+            let raytracer = Raytracer { mode, max_bounces: 100, ..Default::default() };
+            for path in raytracer.trace_paths(&scene, &camera, 4) {
+                assert_eq!(path.points.len(), 102, "{:?}", mode);
+                assert_eq!(path.segment_types.len(), 101, "{:?}", mode);
+            }
+        }
+    }
+
+    /// Both halves of a comparison are what their filter alone gives, splatted across tiles.
+    #[test]
+    fn compare_filter_splits_image() {
+        let mut scene = Scene::default();
+        scene.rebuild_bvh();
+        let camera = Camera::new(Vec3::new(0.0, 1.5, 2.5), Vec3::new(0.0, 0.0, -0.5), 50.0, 4.0 / 3.0);
+        let (width, height) = (40, 30);
+        let render = |filter, compare_filter| {
+            let raytracer = Raytracer { width, height, samples_per_pixel: 2, filter, compare_filter, ..Default::default() };
+            raytracer.render_samples(&scene, &camera, 0).pixels
+        };
+
+        let split = render(FilterType::Box, Some(FilterType::Lanczos));
+        let boxed = render(FilterType::Box, None);
+        let lanczos = render(FilterType::Lanczos, None);
+        assert_ne!(boxed, lanczos);
+        for (index, &pixel) in split.iter().enumerate() {
+            let expected = if index as u32 % width < width / 2 { boxed[index] } else { lanczos[index] };
+            assert_eq!(pixel, expected, "pixel {}", index);
         }
     }
 }
@@ -0,0 +1,465 @@
+//! Off-main-thread rendering for the wasm build.
+//!
+//! The browser runs the `render_worker` binary in a dedicated Web Worker. Once the worker
+//! listens it replies [`WorkerReply::Ready`]; until then the app holds its requests back, as
+//! messages posted earlier are lost. The app then posts [`WorkerRequest`]s as JSON and the
+//! worker answers each job with one tile at a time, so the image fills in while the UI stays
+//! responsive, or with a failure, so the app is never left waiting.
+//!
+//! The scene only travels with the first pass of an image. The worker keeps it, and later
+//! passes just say which samples to add, as sending and parsing a scene with meshes or an
+//! environment map costs about as much as loading it.
+
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::raytracer::{Raytracer, Tile};
+use crate::scene::Scene;
+use serde::{Deserialize, Serialize};
+
+/// What the worker renders until the next setup.
+#[derive(Serialize, Deserialize)]
+pub struct RenderSetup {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub raytracer: Raytracer,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TileResult {
+    pub job_id: u64,
+    pub film: Film, // As returned by `Raytracer::render_tile`
+}
+
+/// Messages from the app to the worker.
+#[derive(Serialize, Deserialize)]
+pub enum WorkerRequest {
+    Setup(Box<RenderSetup>),       // Abandons the job the worker is on, if any
+    Render { id: u64, pass: u32 }, // A pass of the last setup, replaces the job the worker is on
+    Cancel { job_id: u64 },
+}
+
+/// Messages from the worker to the app.
+#[derive(Serialize, Deserialize)]
+pub enum WorkerReply {
+    Ready,
+    Tile(TileResult),
+    Failed { job_id: Option<u64>, message: String }, // None if the request could not be read
+}
+
+struct RunningJob {
+    id: u64,
+    pass: u32, // Index of the first sample, see `Raytracer::render_tile`
+    tiles: Vec<Tile>,
+    next: usize,
+}
+
+/// The worker's side of the protocol, without the messaging. Each `step` renders one tile, so
+/// a cancellation or a newer job that arrives in between takes effect before the next tile.
+#[derive(Default)]
+pub struct WorkerState {
+    setup: Option<RenderSetup>,
+    running: Option<RunningJob>,
+}
+
+impl WorkerState {
+    /// Takes a request from the app. Returns the reply if it has to be answered right away.
+    pub fn handle(&mut self, request: &str) -> Option<WorkerReply> {
+        match serde_json::from_str::<WorkerRequest>(request) {
+            Ok(WorkerRequest::Setup(mut setup)) => {
+                // The BVH is not serialized
+                setup.scene.rebuild_bvh();
+                self.setup = Some(*setup);
+                self.running = None;
+                None
+            }
+            Ok(WorkerRequest::Render { id, pass }) => {
+                let Some(setup) = &self.setup else {
+                    self.running = None;
+                    return Some(WorkerReply::Failed { job_id: Some(id), message: "No scene to render".to_string() });
+                };
+                let tiles = setup.raytracer.tiles();
+                self.running = Some(RunningJob { id, pass, tiles, next: 0 });
+                None
+            }
+            Ok(WorkerRequest::Cancel { job_id }) => {
+                if self.running.as_ref().is_some_and(|running| running.id == job_id) {
+                    self.running = None;
+                }
+                None
+            }
+            Err(err) => Some(WorkerReply::Failed { job_id: None, message: format!("Invalid request: {}", err) }),
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Renders the next tile of the current job. None if there is no job.
+    pub fn step(&mut self) -> Option<WorkerReply> {
+        let running = self.running.as_mut()?;
+        let (Some(setup), Some(&tile)) = (&self.setup, running.tiles.get(running.next)) else {
+            self.running = None;
+            return None;
+        };
+
+        let result = TileResult {
+            job_id: running.id,
+            film: setup.raytracer.render_tile(&setup.scene, &setup.camera, &tile, running.pass),
+        };
+        running.next += 1;
+        if running.next == running.tiles.len() {
+            self.running = None;
+        }
+        Some(WorkerReply::Tile(result))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use worker::run_worker;
+
+#[cfg(target_arch = "wasm32")]
+mod worker {
+    use super::{WorkerReply, WorkerState};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen::JsCast;
+
+    type MessageHandler = Closure<dyn FnMut(web_sys::MessageEvent)>;
+
+    fn post(scope: &web_sys::DedicatedWorkerGlobalScope, reply: &WorkerReply) {
+        // The app waits for every tile, so one that can't be sent still has to be answered
+        let json = serde_json::to_string(reply).unwrap_or_else(|err| {
+            let job_id = match reply {
+                WorkerReply::Tile(result) => Some(result.job_id),
+                _ => None,
+            };
+            let failed = WorkerReply::Failed { job_id, message: format!("Failed to serialize reply: {}", err) };
+            serde_json::to_string(&failed).unwrap_or_default()
+        });
+        if let Err(err) = scope.post_message(&JsValue::from_str(&json)) {
+            log::error!("Failed to post reply: {:?}", err);
+        }
+    }
+
+    /// Queues the next step as a task of its own through a message channel to the worker
+    /// itself, which unlike setTimeout adds no delay.
+    struct StepQueue {
+        port: web_sys::MessagePort,
+        queued: Cell<bool>,
+    }
+
+    impl StepQueue {
+        fn request(&self) {
+            if !self.queued.replace(true) {
+                if let Err(err) = self.port.post_message(&JsValue::NULL) {
+                    log::error!("Failed to queue the next tile: {:?}", err);
+                }
+            }
+        }
+    }
+
+    /// Entry point of the worker: answers every posted [`super::WorkerRequest`].
+    pub fn run_worker() {
+        let scope: web_sys::DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+        let state = Rc::new(RefCell::new(WorkerState::default()));
+
+        let channel = match web_sys::MessageChannel::new() {
+            Ok(channel) => channel,
+            Err(err) => {
+                let message = format!("Failed to create message channel: {:?}", err);
+                post(&scope, &WorkerReply::Failed { job_id: None, message });
+                return;
+            }
+        };
+        let steps = Rc::new(StepQueue { port: channel.port2(), queued: Cell::new(false) });
+
+        let on_step = {
+            let scope = scope.clone();
+            let state = state.clone();
+            let steps = steps.clone();
+            MessageHandler::new(move |_event: web_sys::MessageEvent| {
+                steps.queued.set(false);
+                let reply = state.borrow_mut().step();
+                if let Some(reply) = reply {
+                    post(&scope, &reply);
+                }
+                if state.borrow().is_busy() {
+                    steps.request();
+                }
+            })
+        };
+        channel.port1().set_onmessage(Some(on_step.as_ref().unchecked_ref()));
+        on_step.forget();
+
+        let reply_scope = scope.clone();
+        let on_message = MessageHandler::new(move |event: web_sys::MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            let reply = state.borrow_mut().handle(&text);
+            if let Some(reply) = reply {
+                post(&reply_scope, &reply);
+            }
+            if state.borrow().is_busy() {
+                steps.request();
+            }
+        });
+        scope.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        on_message.forget();
+
+        post(&scope, &WorkerReply::Ready);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use client::WorkerRenderer;
+
+#[cfg(target_arch = "wasm32")]
+mod client {
+    use super::{RenderSetup, WorkerReply, WorkerRequest};
+    use crate::camera::Camera;
+    use crate::film;
+    use crate::raytracer::{Accumulator, Raytracer};
+    use crate::scene::Scene;
+    use crate::tone_mapping::ToneMapping;
+    use glam::Vec4;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use wasm_bindgen::prelude::*;
+    use wasm_bindgen::JsCast;
+
+    /// Loader script Trunk generates for the `render_worker` binary (see index.html).
+    const WORKER_SCRIPT: &str = "./render_worker_loader.js";
+
+    type Inbox = Rc<RefCell<Vec<WorkerReply>>>;
+    type MessageHandler = Closure<dyn FnMut(web_sys::MessageEvent)>;
+    type ErrorHandler = Closure<dyn FnMut(web_sys::Event)>;
+
+    struct Handlers {
+        _on_message: MessageHandler,
+        _on_error: ErrorHandler,
+    }
+
+    /// Main-thread handle to the render worker.
+    pub struct WorkerRenderer {
+        worker: web_sys::Worker,
+        inbox: Inbox,
+        failed: Rc<Cell<bool>>, // Set if the worker script could not be loaded, crashed or failed a job
+        _handlers: Handlers,
+        ready: bool,         // The worker listens for requests
+        queued: Vec<String>, // Requests to post once the worker is ready
+        has_setup: bool,     // The worker has the scene of the current image
+        job_id: u64,
+        pending_tiles: usize,   // Tiles of the current job that have not arrived yet
+        pass_buffer: Vec<Vec4>, // Filter-weighted sample sums of the current pass
+        pass_samples: u32,
+        first_pass: bool, // Draw tiles as they arrive instead of once the pass is complete
+    }
+
+    fn spawn(inbox: &Inbox, failed: &Rc<Cell<bool>>) -> Result<(web_sys::Worker, Handlers), JsValue> {
+        let worker = web_sys::Worker::new(WORKER_SCRIPT)?;
+
+        let failed = failed.clone();
+        let on_error = ErrorHandler::new(move |_event: web_sys::Event| failed.set(true));
+        worker.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        let inbox = inbox.clone();
+        let on_message = MessageHandler::new(move |event: web_sys::MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            // A reply that can't be read may have been the tile or failure the app waits for
+            let reply = serde_json::from_str::<WorkerReply>(&text).unwrap_or_else(|err| WorkerReply::Failed {
+                job_id: None,
+                message: format!("Invalid reply from render worker: {}", err),
+            });
+            inbox.borrow_mut().push(reply);
+        });
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        Ok((worker, Handlers { _on_message: on_message, _on_error: on_error }))
+    }
+
+    impl WorkerRenderer {
+        pub fn new() -> Result<Self, JsValue> {
+            let inbox = Inbox::default();
+            let failed = Rc::new(Cell::new(false));
+            let (worker, handlers) = spawn(&inbox, &failed)?;
+            Ok(Self {
+                worker,
+                inbox,
+                failed,
+                _handlers: handlers,
+                ready: false,
+                queued: Vec::new(),
+                has_setup: false,
+                job_id: 0,
+                pending_tiles: 0,
+                pass_buffer: Vec::new(),
+                pass_samples: 0,
+                first_pass: false,
+            })
+        }
+
+        pub fn is_busy(&self) -> bool {
+            self.pending_tiles > 0
+        }
+
+        pub fn has_failed(&self) -> bool {
+            self.failed.get()
+        }
+
+        fn post(&self, json: &str) {
+            if let Err(err) = self.worker.post_message(&JsValue::from_str(json)) {
+                // The job is lost, so waiting for its tiles would stall the image
+                log::error!("Failed to post to render worker: {:?}", err);
+                self.failed.set(true);
+            }
+        }
+
+        fn send(&mut self, request: &WorkerRequest) {
+            match serde_json::to_string(request) {
+                Ok(json) if self.ready => self.post(&json),
+                Ok(json) => self.queued.push(json),
+                Err(err) => {
+                    log::error!("Failed to serialize render worker request: {}", err);
+                    self.failed.set(true);
+                }
+            }
+        }
+
+        /// Abandons the running job, if any, for another backend to take over. The worker
+        /// drops it before its next tile, and gets the scene again with the next pass.
+        pub fn cancel(&mut self) {
+            if self.is_busy() {
+                if self.ready {
+                    self.send(&WorkerRequest::Cancel { job_id: self.job_id });
+                } else {
+                    self.queued.clear();
+                }
+                self.pending_tiles = 0;
+            }
+            self.has_setup = false;
+        }
+
+        /// Sends one pass to the worker, which abandons a job that is still running for it.
+        /// `pass` 0 starts a new image; later passes add to `accumulator`. The scene, camera
+        /// and settings are only sent if they `changed` since the last pass.
+        pub fn start(&mut self, scene: &Scene, camera: &Camera, raytracer: &Raytracer, pass: u32, changed: bool) {
+            if changed || !self.has_setup {
+                self.send(&WorkerRequest::Setup(Box::new(RenderSetup {
+                    scene: scene.clone(),
+                    camera: *camera,
+                    raytracer: raytracer.clone(),
+                })));
+                self.has_setup = true;
+            }
+            self.job_id += 1;
+            self.send(&WorkerRequest::Render { id: self.job_id, pass });
+
+            self.pending_tiles = raytracer.tiles().len();
+            self.pass_buffer = vec![Vec4::ZERO; (raytracer.width * raytracer.height) as usize];
+            self.pass_samples = raytracer.samples_per_pixel;
+            self.first_pass = pass == 0;
+        }
+
+        /// Applies the replies that arrived since the last call. New tiles of a first pass are
+        /// drawn straight into `display`; a finished pass is added to `accumulator`.
+        /// Returns true if `display` changed.
+        pub fn poll(&mut self, accumulator: &mut Accumulator, tone_mapping: ToneMapping, display: &mut [u8]) -> bool {
+            let replies = std::mem::take(&mut *self.inbox.borrow_mut());
+            let mut changed = false;
+
+            for reply in replies {
+                let result = match reply {
+                    WorkerReply::Ready => {
+                        self.ready = true;
+                        for json in std::mem::take(&mut self.queued) {
+                            self.post(&json);
+                        }
+                        continue;
+                    }
+                    WorkerReply::Failed { job_id, message } => {
+                        // Failures of abandoned jobs don't matter any more
+                        if job_id.is_none_or(|id| id == self.job_id) {
+                            log::error!("Render worker failed: {}", message);
+                            self.failed.set(true);
+                            self.pending_tiles = 0;
+                        }
+                        continue;
+                    }
+                    WorkerReply::Tile(result) if result.job_id == self.job_id && self.is_busy() => result,
+                    WorkerReply::Tile(_) => continue,
+                };
+
+                // Films overlap where the filter reaches into neighboring tiles
+                for (index, sum) in result.film.pixel_indices(accumulator.width).zip(result.film.pixels) {
+                    self.pass_buffer[index] += sum;
+                    if self.first_pass {
+                        let rgba = tone_mapping.to_rgba(film::resolve(self.pass_buffer[index]));
+                        display[index * 4..index * 4 + 4].copy_from_slice(&rgba);
+                        changed = true;
+                    }
+                }
+
+                self.pending_tiles -= 1;
+                if self.pending_tiles == 0 {
+                    for (sum, sample) in accumulator.buffer.iter_mut().zip(&self.pass_buffer) {
+                        *sum += *sample;
+                    }
+                    accumulator.sample_count += self.pass_samples;
+                    display.copy_from_slice(&accumulator.to_rgba(tone_mapping));
+                    changed = true;
+                }
+            }
+
+            changed
+        }
+    }
+
+    impl Drop for WorkerRenderer {
+        fn drop(&mut self) {
+            self.worker.terminate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Jobs are rendered a tile per step, a cancellation or a newer job takes effect before the
+    /// next tile, and a request that can't be read or has no scene is answered instead of dropped.
+    #[test]
+    fn worker_answers_every_job() {
+        let render = |id| serde_json::to_string(&WorkerRequest::Render { id, pass: id as u32 }).unwrap();
+        let tile_of = |reply: Option<WorkerReply>| match reply {
+            Some(WorkerReply::Tile(result)) => Some(result.job_id),
+            _ => None,
+        };
+
+        let mut state = WorkerState::default();
+        assert!(matches!(state.handle(&render(1)), Some(WorkerReply::Failed { job_id: Some(1), .. })));
+        assert!(!state.is_busy());
+
+        let raytracer = Raytracer { width: 32, height: 16, ..Default::default() };
+        let setup = RenderSetup { scene: Scene::default(), camera: Camera::default(), raytracer };
+        assert!(state.handle(&serde_json::to_string(&WorkerRequest::Setup(Box::new(setup))).unwrap()).is_none());
+        assert!(state.handle(&render(1)).is_none());
+        assert_eq!(tile_of(state.step()), Some(1));
+        state.handle(&render(2));
+        assert_eq!(tile_of(state.step()), Some(2));
+        assert_eq!(tile_of(state.step()), Some(2));
+        assert!(!state.is_busy() && state.step().is_none());
+
+        state.handle(&render(3));
+        state.handle(&serde_json::to_string(&WorkerRequest::Cancel { job_id: 2 }).unwrap());
+        assert!(state.is_busy());
+        state.handle(&serde_json::to_string(&WorkerRequest::Cancel { job_id: 3 }).unwrap());
+        assert!(!state.is_busy());
+
+        assert!(matches!(state.handle("{"), Some(WorkerReply::Failed { job_id: None, .. })));
+    }
+}
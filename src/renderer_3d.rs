@@ -1,10 +1,14 @@
 use crate::camera::Camera;
-use crate::scene::Scene;
+use crate::primitives::{Light, LightType, Mesh, Plane};
+use crate::scene::{ObjectId, Scene};
 use crate::raytracer::RayPath;
 use glam::{Mat4, Vec3};
 use wgpu::util::DeviceExt;
 use bytemuck::{Pod, Zeroable};
 
+/// Wireframe color of the selected object.
+const SELECTED_COLOR: [f32; 3] = [1.0, 0.6, 0.0];
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 struct Vertex {
@@ -174,6 +178,7 @@ impl Renderer3D {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render(
         &self,
         device: &wgpu::Device,
@@ -183,6 +188,7 @@ impl Renderer3D {
         camera: &Camera,
         ray_paths: &[RayPath],
         view_camera: &Camera, // The camera we are looking THROUGH to see the 3D scene
+        selected: Option<ObjectId>,
     ) {
         // Update uniforms
         let view_proj = view_camera.projection_matrix() * view_camera.view_matrix();
@@ -203,19 +209,28 @@ impl Renderer3D {
             vertices.push(Vertex { position: [10.0, 0.0, x], color: grid_color });
         }
 
-        // Scene Objects
-        for sphere in &scene.spheres {
-            self.add_sphere_wireframe(&mut vertices, sphere.center, sphere.radius, sphere.material.color.into());
+        // Scene Objects, the selected one drawn in the highlight color
+        let color = |id: ObjectId, color: Vec3| if selected == Some(id) { SELECTED_COLOR } else { color.into() };
+        for (i, sphere) in scene.spheres.iter().enumerate() {
+            let matrix = sphere.transform.to_mat4() * Mat4::from_translation(sphere.center);
+            self.add_sphere_wireframe(&mut vertices, matrix, sphere.radius, color(ObjectId::Sphere(i), sphere.material.color));
+        }
+        for (i, cube) in scene.cubes.iter().enumerate() {
+            let cube_color = color(ObjectId::Cube(i), cube.material.color);
+            self.add_cube_wireframe(&mut vertices, cube.transform.to_mat4(), cube.min, cube.max, cube_color);
         }
-        for cube in &scene.cubes {
-            self.add_cube_wireframe(&mut vertices, cube.min, cube.max, cube.material.color.into());
+        for (i, mesh) in scene.meshes.iter().enumerate() {
+            self.add_mesh_wireframe(&mut vertices, mesh, color(ObjectId::Mesh(i), mesh.material.color));
         }
-        for _plane in &scene.planes {
-            // Visualize plane as a large quad
-            // TODO: Better plane visualization
+        // Planes are infinite and would hide the grid, so only the selected one is outlined
+        if let Some(plane) = selected.and_then(|id| match id {
+            ObjectId::Plane(i) => scene.planes.get(i),
+            _ => None,
+        }) {
+            self.add_plane_outline(&mut vertices, plane, SELECTED_COLOR);
         }
         for light in &scene.lights {
-            self.add_sphere_wireframe(&mut vertices, light.position, 0.2, light.color.into());
+            self.add_light(&mut vertices, light);
         }
 
         // Camera Frustum
@@ -238,6 +253,11 @@ impl Renderer3D {
                 vertices.push(Vertex { position: path.points[i].into(), color });
                 vertices.push(Vertex { position: path.points[i+1].into(), color });
             }
+            for &(start, end) in &path.light_rays {
+                let color = [1.0, 1.0, 1.0]; // White - shadow ray that reached a light
+                vertices.push(Vertex { position: start.into(), color });
+                vertices.push(Vertex { position: end.into(), color });
+            }
         }
 
         if vertices.is_empty() {
@@ -291,27 +311,29 @@ impl Renderer3D {
         queue.submit(std::iter::once(encoder.finish()));
     }
 
-    fn add_sphere_wireframe(&self, vertices: &mut Vec<Vertex>, center: Vec3, radius: f32, color: [f32; 3]) {
+    /// Three circles of `radius` around the origin of `matrix`, so a scaled sphere shows as an ellipsoid.
+    fn add_sphere_wireframe(&self, vertices: &mut Vec<Vertex>, matrix: Mat4, radius: f32, color: [f32; 3]) {
         let segments = 16;
+        let mut push = |local: Vec3| vertices.push(Vertex { position: matrix.transform_point3(local * radius).into(), color });
         for i in 0..segments {
             let angle1 = (i as f32 / segments as f32) * std::f32::consts::TAU;
             let angle2 = ((i + 1) as f32 / segments as f32) * std::f32::consts::TAU;
             
             // XY circle
-            vertices.push(Vertex { position: (center + Vec3::new(angle1.cos() * radius, angle1.sin() * radius, 0.0)).into(), color });
-            vertices.push(Vertex { position: (center + Vec3::new(angle2.cos() * radius, angle2.sin() * radius, 0.0)).into(), color });
+            push(Vec3::new(angle1.cos(), angle1.sin(), 0.0));
+            push(Vec3::new(angle2.cos(), angle2.sin(), 0.0));
             
             // XZ circle
-            vertices.push(Vertex { position: (center + Vec3::new(angle1.cos() * radius, 0.0, angle1.sin() * radius)).into(), color });
-            vertices.push(Vertex { position: (center + Vec3::new(angle2.cos() * radius, 0.0, angle2.sin() * radius)).into(), color });
+            push(Vec3::new(angle1.cos(), 0.0, angle1.sin()));
+            push(Vec3::new(angle2.cos(), 0.0, angle2.sin()));
             
             // YZ circle
-            vertices.push(Vertex { position: (center + Vec3::new(0.0, angle1.cos() * radius, angle1.sin() * radius)).into(), color });
-            vertices.push(Vertex { position: (center + Vec3::new(0.0, angle2.cos() * radius, angle2.sin() * radius)).into(), color });
+            push(Vec3::new(0.0, angle1.cos(), angle1.sin()));
+            push(Vec3::new(0.0, angle2.cos(), angle2.sin()));
         }
     }
 
-    fn add_cube_wireframe(&self, vertices: &mut Vec<Vertex>, min: Vec3, max: Vec3, color: [f32; 3]) {
+    fn add_cube_wireframe(&self, vertices: &mut Vec<Vertex>, matrix: Mat4, min: Vec3, max: Vec3, color: [f32; 3]) {
         let corners = [
             Vec3::new(min.x, min.y, min.z),
             Vec3::new(max.x, min.y, min.z),
@@ -321,7 +343,7 @@ impl Renderer3D {
             Vec3::new(max.x, min.y, max.z),
             Vec3::new(max.x, max.y, max.z),
             Vec3::new(min.x, max.y, max.z),
-        ];
+        ].map(|corner| matrix.transform_point3(corner));
 
         let edges = [
             (0, 1), (1, 2), (2, 3), (3, 0), // Front face
@@ -335,12 +357,116 @@ impl Renderer3D {
         }
     }
 
+    fn add_mesh_wireframe(&self, vertices: &mut Vec<Vertex>, mesh: &Mesh, color: [f32; 3]) {
+        let matrix = mesh.transform.to_mat4();
+        for &[a, b, c] in &mesh.triangles {
+            let [a, b, c] = [a, b, c].map(|i| matrix.transform_point3(mesh.vertices[i as usize]));
+            for (start, end) in [(a, b), (b, c), (c, a)] {
+                vertices.push(Vertex { position: start.into(), color });
+                vertices.push(Vertex { position: end.into(), color });
+            }
+        }
+    }
+
+    /// A square patch of the plane around its point, with a line along the normal.
+    fn add_plane_outline(&self, vertices: &mut Vec<Vertex>, plane: &Plane, color: [f32; 3]) {
+        let matrix = plane.transform.to_mat4();
+        let half_size = 5.0;
+        let normal = plane.normal.normalize();
+        let tangent = normal.any_orthonormal_vector() * half_size;
+        let bitangent = normal.cross(tangent);
+        let corners = [
+            plane.point - tangent - bitangent,
+            plane.point + tangent - bitangent,
+            plane.point + tangent + bitangent,
+            plane.point - tangent + bitangent,
+        ]
+        .map(|corner| matrix.transform_point3(corner));
+
+        for i in 0..4 {
+            vertices.push(Vertex { position: corners[i].into(), color });
+            vertices.push(Vertex { position: corners[(i + 1) % 4].into(), color });
+        }
+        vertices.push(Vertex { position: matrix.transform_point3(plane.point).into(), color });
+        vertices.push(Vertex { position: matrix.transform_point3(plane.point + normal).into(), color });
+    }
+
+    /// Area lights are drawn at their size with a line along the way they shine, spot lights
+    /// as their cones, point and directional lights as a small sphere.
+    fn add_light(&self, vertices: &mut Vec<Vertex>, light: &Light) {
+        let color = light.color.into();
+        let (normal, tangent, bitangent) = match light.light_type {
+            LightType::Point | LightType::Directional => {
+                self.add_sphere_wireframe(vertices, Mat4::from_translation(light.position), 0.2, color);
+                return;
+            }
+            LightType::Spot => {
+                self.add_sphere_wireframe(vertices, Mat4::from_translation(light.position), 0.1, color);
+                self.add_spot_cone(vertices, light, color);
+                return;
+            }
+            LightType::Sphere => {
+                self.add_sphere_wireframe(vertices, Mat4::from_translation(light.position), light.size.x, color);
+                return;
+            }
+            LightType::Rectangle | LightType::Disk => light.area_frame(),
+        };
+
+        let outline: Vec<Vec3> = if light.light_type == LightType::Rectangle {
+            let half_width = tangent * 0.5 * light.size.x;
+            let half_height = bitangent * 0.5 * light.size.y;
+            vec![
+                -half_width - half_height,
+                half_width - half_height,
+                half_width + half_height,
+                -half_width + half_height,
+            ]
+        } else {
+            let segments = 16;
+            (0..segments)
+                .map(|i| {
+                    let angle = (i as f32 / segments as f32) * std::f32::consts::TAU;
+                    (tangent * angle.cos() + bitangent * angle.sin()) * light.size.x
+                })
+                .collect()
+        };
+        for i in 0..outline.len() {
+            vertices.push(Vertex { position: (light.position + outline[i]).into(), color });
+            vertices.push(Vertex { position: (light.position + outline[(i + 1) % outline.len()]).into(), color });
+        }
+        vertices.push(Vertex { position: light.position.into(), color });
+        vertices.push(Vertex { position: (light.position + normal * 0.5).into(), color });
+    }
+
+    /// Outer cone with four lines from the apex, and the inner cone's rim, one unit long.
+    fn add_spot_cone(&self, vertices: &mut Vec<Vertex>, light: &Light, color: [f32; 3]) {
+        let (normal, tangent, bitangent) = light.area_frame();
+        let length = 1.0;
+        // Wider cones are drawn at the widest angle that still has a rim in front of the light
+        let rim = |angle: f32, i: u32| {
+            let radius = length * angle.min(80.0).to_radians().tan();
+            let around = (i as f32 / 16.0) * std::f32::consts::TAU;
+            light.position + normal * length + (tangent * around.cos() + bitangent * around.sin()) * radius
+        };
+
+        for angle in [light.inner_angle, light.outer_angle] {
+            for i in 0..16 {
+                vertices.push(Vertex { position: rim(angle, i).into(), color });
+                vertices.push(Vertex { position: rim(angle, i + 1).into(), color });
+            }
+        }
+        for i in [0, 4, 8, 12] {
+            vertices.push(Vertex { position: light.position.into(), color });
+            vertices.push(Vertex { position: rim(light.outer_angle, i).into(), color });
+        }
+    }
+
     fn add_camera_frustum(&self, vertices: &mut Vec<Vertex>, camera: &Camera) {
         let color = [0.0, 1.0, 0.0]; // Green camera
         let pos = camera.transform.position;
         
         // Draw camera position
-        self.add_sphere_wireframe(vertices, pos, 0.1, color);
+        self.add_sphere_wireframe(vertices, Mat4::from_translation(pos), 0.1, color);
 
         // Draw frustum cone
         let forward = camera.transform.forward();
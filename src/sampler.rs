@@ -0,0 +1,424 @@
+//! Where the random numbers of each pixel sample come from.
+//!
+//! A sampler hands out the numbers of one sample after another, each a point in as many
+//! dimensions as the path needs: the first two place it in the pixel, the rest are drawn by
+//! the tracing code in the order it needs them. Better spread points than independent random
+//! ones converge faster at the few samples per pixel this app renders with.
+//!
+//! The tracing code takes any [`rand::Rng`], so samplers reach it through [`SamplerRng`].
+
+use glam::UVec2;
+use rand::RngCore;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum SamplerType {
+    /// Independent random numbers, clumping and leaving gaps.
+    #[default]
+    Independent,
+    /// Every pair of dimensions jittered in a grid of `samples_per_pixel` cells.
+    Stratified,
+    /// Owen-scrambled Sobol' points, which stay well spread as samples accumulate.
+    Sobol,
+    /// Sobol' points shifted by a blue noise mask, so the error of neighboring pixels
+    /// differs as much as possible and reads as fine grain rather than blotches.
+    BlueNoise,
+}
+
+impl SamplerType {
+    pub const ALL: [SamplerType; 4] = [Self::Independent, Self::Stratified, Self::Sobol, Self::BlueNoise];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Independent => "Independent",
+            Self::Stratified => "Stratified",
+            Self::Sobol => "Sobol",
+            Self::BlueNoise => "Blue Noise",
+        }
+    }
+
+    /// `seed` seeds the independent sampler and scrambles the others. Stratification
+    /// splits the pixel into `samples_per_pixel` cells.
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> SamplerRng {
+        let independent = IndependentSampler { seed, rng: Pcg32::new(seed, 0) };
+        let seed = (seed ^ (seed >> 32)) as u32;
+        let sampler: Box<dyn Sampler> = match self {
+            Self::Independent => Box::new(independent),
+            Self::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            Self::Sobol => Box::new(SobolSampler { seed, pixel_seed: seed, index: 0, dimension: 0 }),
+            Self::BlueNoise => Box::new(BlueNoiseSampler { seed, pixel: UVec2::ZERO, index: 0, dimension: 0 }),
+        };
+        SamplerRng(sampler)
+    }
+}
+
+pub trait Sampler {
+    /// Moves on to sample `index` of pixel (x, y) and back to its first dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    /// The next dimension of the current sample, in [0, 1) as a fraction of 2^32.
+    fn next_dimension(&mut self) -> u32;
+}
+
+/// A [`Sampler`] as a random number generator: every `u32` drawn is the next dimension.
+pub struct SamplerRng(Box<dyn Sampler>);
+
+impl SamplerRng {
+    pub fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.0.start_pixel_sample(x, y, index);
+    }
+}
+
+impl RngCore for SamplerRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_dimension()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        ((self.next_u32() as u64) << 32) | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// A generator of its own for every pixel sample rather than one running through the image,
+/// so the image doesn't depend on the order pixels are rendered in. Should another platform
+/// round a random decision differently, that also only changes the one sample.
+struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        let pixel = mix64(self.seed ^ ((y as u64) << 32 | x as u64));
+        self.rng = Pcg32::new(mix64(pixel ^ index as u64), 0);
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+}
+
+/// Jitters pairs of dimensions in a grid of cells, visiting the cells in a different order
+/// for each pair and pixel so the pairs don't correlate. Samples beyond `samples_per_pixel`
+/// (from progressive rendering) start another round through the grid.
+struct StratifiedSampler {
+    seed: u32,
+    samples: u32,
+    grid: UVec2,
+    pixel_seed: u32,
+    index: u32,
+    dimension: u32,
+    pending: Option<u32>, // Second dimension of the current pair
+}
+
+impl StratifiedSampler {
+    fn new(seed: u32, samples: u32) -> Self {
+        let samples = samples.max(1);
+        let columns = (samples as f32).sqrt() as u32;
+        let grid = UVec2::new(columns, samples.div_ceil(columns));
+        Self { seed, samples, grid, pixel_seed: seed, index: 0, dimension: 0, pending: None }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash_combine(hash_combine(self.seed, x), y);
+        self.index = index;
+        self.dimension = 0;
+        self.pending = None;
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        if let Some(value) = self.pending.take() {
+            return value;
+        }
+
+        let pair_seed = hash_combine(self.pixel_seed, self.dimension);
+        self.dimension += 1;
+        let round = self.index / self.samples;
+        let cell = permute(self.index % self.samples, self.grid.x * self.grid.y, hash_combine(pair_seed, round));
+        let jitter = hash_combine(pair_seed, self.index);
+        // (cell + jitter) / cells, in fixed point
+        let stratum = |cell: u32, cells: u32, jitter: u32| ((((cell as u64) << 32) | jitter as u64) / cells as u64) as u32;
+        self.pending = Some(stratum(cell / self.grid.x, self.grid.y, hash(jitter)));
+        stratum(cell % self.grid.x, self.grid.x, jitter)
+    }
+}
+
+/// Owen-scrambled Sobol' points after Burley, "Practical Hash-based Owen Scrambling" (2020).
+/// Each pair of dimensions is its own shuffled 2D sequence, scrambled differently per pixel.
+struct SobolSampler {
+    seed: u32,
+    pixel_seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash_combine(hash_combine(self.seed, x), y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        self.dimension += 1;
+        owen_sobol(self.index, self.dimension - 1, self.pixel_seed)
+    }
+}
+
+/// The same Sobol' points in every pixel, each pixel shifted (modulo 1) by a blue noise mask,
+/// after Georgiev and Fajardo, "Blue-noise Dithered Sampling" (2016). Every dimension reads
+/// the mask at a different offset.
+struct BlueNoiseSampler {
+    seed: u32,
+    pixel: UVec2,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel = UVec2::new(x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let offset_seed = hash_combine(self.seed ^ 0xb1ae, dimension);
+        let mask_x = self.pixel.x.wrapping_add(offset_seed) as usize % MASK_SIZE;
+        let mask_y = self.pixel.y.wrapping_add(offset_seed >> 16) as usize % MASK_SIZE;
+        let rank = blue_noise_mask()[mask_y * MASK_SIZE + mask_x] as u32;
+        // The rank picks one of MASK_SIZE^2 equal steps, a hash fills in the bits below
+        let shift = (rank << 20) | (hash(offset_seed ^ rank) >> 12);
+        owen_sobol(self.index, dimension, self.seed).wrapping_add(shift)
+    }
+}
+
+/// Edge length of the tiling blue noise mask.
+const MASK_SIZE: usize = 64;
+
+/// Ranks 0..MASK_SIZE^2 spread over the mask so that pixels of any rank range form blue
+/// noise. Built once, on first use.
+fn blue_noise_mask() -> &'static [u16] {
+    static MASK: OnceLock<Vec<u16>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+/// Ulichney's void-and-cluster method, "The void-and-cluster method for dither array
+/// generation" (1993). Points go where the fewest others are nearby (the largest void), and
+/// come out where the most are (the tightest cluster), with a Gaussian measuring nearness.
+/// Past half the ranks, filling voids stands in for the original's inverted third phase.
+fn void_and_cluster() -> Vec<u16> {
+    use rand::Rng;
+
+    const SIGMA: f32 = 1.5;
+    let size = MASK_SIZE;
+    let count = size * size;
+    // Gaussian of the distance on the torus, indexed by the offset between two pixels
+    let kernel: Vec<f32> = (0..count)
+        .map(|offset| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(offset % size), wrap(offset / size));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+
+    struct Pattern {
+        points: Vec<bool>,
+        energy: Vec<f32>,
+    }
+    let toggle = |pattern: &mut Pattern, index: usize| {
+        pattern.points[index] = !pattern.points[index];
+        let sign = if pattern.points[index] { 1.0 } else { -1.0 };
+        let (x, y) = (index % size, index / size);
+        for (other, energy) in pattern.energy.iter_mut().enumerate() {
+            let dx = (other % size + size - x) % size;
+            let dy = (other / size + size - y) % size;
+            *energy += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |pattern: &Pattern| {
+        (0..count).filter(|&i| pattern.points[i]).max_by(|&a, &b| pattern.energy[a].total_cmp(&pattern.energy[b])).unwrap()
+    };
+    let largest_void = |pattern: &Pattern| {
+        (0..count).filter(|&i| !pattern.points[i]).min_by(|&a, &b| pattern.energy[a].total_cmp(&pattern.energy[b])).unwrap()
+    };
+
+    // A random tenth of the pixels, evened out by moving points from clusters into voids
+    let mut pattern = Pattern { points: vec![false; count], energy: vec![0.0; count] };
+    let initial = count / 10;
+    let mut rng = Pcg32::new(0x5eed_b1ae, 0);
+    while pattern.points.iter().filter(|&&point| point).count() < initial {
+        let index = rng.gen_range(0..count);
+        if !pattern.points[index] {
+            toggle(&mut pattern, index);
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&pattern);
+        toggle(&mut pattern, cluster);
+        let void = largest_void(&pattern);
+        toggle(&mut pattern, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+    let mut removing = Pattern { points: pattern.points.clone(), energy: pattern.energy.clone() };
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&removing);
+        toggle(&mut removing, cluster);
+        ranks[cluster] = rank as u16;
+    }
+    for rank in initial..count {
+        let void = largest_void(&pattern);
+        toggle(&mut pattern, void);
+        ranks[void] = rank as u16;
+    }
+    ranks
+}
+
+/// Dimension `dimension` of the Sobol' point `index`. Dimension pairs are padded: each one is
+/// the first two Sobol' dimensions with its own index shuffle and scramble.
+fn owen_sobol(index: u32, dimension: u32, seed: u32) -> u32 {
+    let pair_seed = hash_combine(seed, dimension / 2);
+    let index = nested_uniform_scramble(index, pair_seed);
+    let value = if dimension & 1 == 0 { index.reverse_bits() } else { sobol_second_dimension(index) };
+    nested_uniform_scramble(value, hash_combine(pair_seed, dimension % 2 + 1))
+}
+
+/// Its generator matrix is Pascal's triangle modulo 2, one column per bit of `index`.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut column = 1 << 31;
+    let mut value = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= column;
+        }
+        index >>= 1;
+        column ^= column >> 1;
+    }
+    value
+}
+
+/// Owen scrambling: every bit gets flipped depending on the bits above it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// A hash in which every bit only depends on the bits below it, with Vegdahl's constants.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x
+}
+
+/// Kensler's hashed permutation of 0..len, "Correlated Multi-Jittered Sampling" (2013).
+fn permute(mut index: u32, len: u32, seed: u32) -> u32 {
+    let mask = u32::MAX >> (len - 1).leading_zeros().min(31);
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < len {
+            return index.wrapping_add(seed) % len;
+        }
+    }
+}
+
+/// Wellons' lowbias32 integer hash.
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+/// The splitmix64 finalizer: every input bit flips about half of the output bits.
+fn mix64(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    hash(seed ^ value.wrapping_add(0x9e3779b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    /// Root mean square error over many pixels of estimating the area of a quarter disk with
+    /// 16 samples. Uses the third and fourth dimension, past the pixel position.
+    fn quarter_disk_error(sampler_type: SamplerType) -> f32 {
+        const SAMPLES: u32 = 16;
+        let pixels = 256;
+        let mut squared_error = 0.0;
+        for pixel in 0..pixels {
+            let mut sampler = sampler_type.build(3, SAMPLES);
+            let mut inside = 0;
+            for index in 0..SAMPLES {
+                sampler.start_pixel_sample(pixel % 16, pixel / 16, index);
+                let _position: (f32, f32) = (sampler.gen(), sampler.gen());
+                let (u, v): (f32, f32) = (sampler.gen(), sampler.gen());
+                inside += (u * u + v * v < 1.0) as u32;
+            }
+            let error = inside as f32 / SAMPLES as f32 - std::f32::consts::FRAC_PI_4;
+            squared_error += error * error;
+        }
+        (squared_error / pixels as f32).sqrt()
+    }
+
+    #[test]
+    fn well_spread_samplers_converge_faster() {
+        let independent = quarter_disk_error(SamplerType::Independent);
+        for sampler_type in [SamplerType::Stratified, SamplerType::Sobol, SamplerType::BlueNoise] {
+            let error = quarter_disk_error(sampler_type);
+            assert!(error < 0.7 * independent, "{:?}: {} vs {}", sampler_type, error, independent);
+        }
+
+        let mut ranks = blue_noise_mask().to_vec();
+        ranks.sort_unstable();
+        assert!(ranks.iter().enumerate().all(|(i, &rank)| rank as usize == i));
+    }
+}
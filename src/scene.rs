@@ -1,22 +1,49 @@
-use crate::primitives::{Cube, Intersectable, Light, LightType, Plane, Sphere, HitRecord, Material, MaterialType};
-use crate::math::Ray;
-use glam::Vec3;
+use crate::background::Background;
+use crate::bvh::{Aabb, Bounded, Bvh};
+use crate::primitives::{Cube, Intersectable, Light, LightType, LightUnits, Mesh, Plane, Sphere, HitRecord, Material, MaterialType, PrincipledParams};
+use crate::math::{Ray, Transform};
+use crate::sky::Sky;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
 
+/// Identifies an object by its kind and its index in the matching list of [`Scene`].
+/// Only valid until objects are added or removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ObjectId {
+    Sphere(usize),
+    Cube(usize),
+    Plane(usize),
+    Mesh(usize),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Scene {
     pub spheres: Vec<Sphere>,
     pub cubes: Vec<Cube>,
     pub planes: Vec<Plane>,
+    #[serde(default)]
+    pub meshes: Vec<Mesh>,
     pub lights: Vec<Light>,
+    #[serde(default)]
+    pub light_units: LightUnits,
+    #[serde(default)]
+    pub background: Background,
+    // Spheres, cubes and meshes (in that order). Planes are unbounded and tested separately.
+    // Call `rebuild_bvh` after editing objects or deserializing.
+    #[serde(skip)]
+    bvh: Bvh,
+    // Objects were removed since the BVH was built. Added ones change the count instead.
+    #[serde(skip)]
+    objects_removed: bool,
 }
 
 impl Default for Scene {
     fn default() -> Self {
         let mut spheres = Vec::new();
         let mut cubes = Vec::new();
-        let mut planes = Vec::new();
         
         // Floor Plane
-        planes.push(Plane {
+        let planes = vec![Plane {
             point: Vec3::new(0.0, -0.5, 0.0),
             normal: Vec3::new(0.0, 1.0, 0.0),
             material: Material {
@@ -27,8 +54,11 @@ impl Default for Scene {
                 roughness: 1.0,
                 ior: 1.5,
                 mat_type: MaterialType::Lambertian,
+                emission: Vec3::ZERO,
+                principled: PrincipledParams::default(),
             },
-        });
+            transform: Transform::default(),
+        }];
 
         // 5 Cubes with Spheres in a circle
         let count = 5;
@@ -41,8 +71,8 @@ impl Default for Scene {
             
             // Cube (Light turqoise diffuse)
             cubes.push(Cube {
-                min: Vec3::new(x - 0.5, -0.5, z - 0.5),
-                max: Vec3::new(x + 0.5, 0.5, z + 0.5),
+                min: Vec3::splat(-0.5),
+                max: Vec3::splat(0.5),
                 material: Material {
                     color: Vec3::new(0.1, 0.8, 0.8),
                     specular: 0.0,
@@ -51,6 +81,12 @@ impl Default for Scene {
                     roughness: 1.0,
                     ior: 1.5,
                     mat_type: MaterialType::Lambertian,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
+                },
+                transform: Transform {
+                    position: Vec3::new(x, 0.0, z),
+                    ..Default::default()
                 },
             });
 
@@ -64,6 +100,8 @@ impl Default for Scene {
                     roughness: 0.1,
                     ior: 1.5,
                     mat_type: MaterialType::Lambertian,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
                 },
                 1 => Material { // Gray Metal
                     color: Vec3::new(0.6, 0.6, 0.6),
@@ -73,6 +111,8 @@ impl Default for Scene {
                     roughness: 0.1,
                     ior: 1.5,
                     mat_type: MaterialType::Metal,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
                 },
                 2 => Material { // Glass
                     color: Vec3::new(1.0, 1.0, 1.0),
@@ -82,6 +122,8 @@ impl Default for Scene {
                     roughness: 0.0,
                     ior: 1.52,
                     mat_type: MaterialType::Dielectric,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
                 },
                 3 => Material { // Blue Metal (Rough)
                     color: Vec3::new(0.1, 0.1, 0.8),
@@ -91,6 +133,8 @@ impl Default for Scene {
                     roughness: 0.4,
                     ior: 1.5,
                     mat_type: MaterialType::Metal,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
                 },
                 _ => Material { // Yellow Lambertian
                     color: Vec3::new(0.8, 0.8, 0.1),
@@ -100,17 +144,23 @@ impl Default for Scene {
                     roughness: 0.1,
                     ior: 1.5,
                     mat_type: MaterialType::Lambertian,
+                    emission: Vec3::ZERO,
+                    principled: PrincipledParams::default(),
                 },
             };
 
             spheres.push(Sphere {
-                center: Vec3::new(x, 1.0, z),
+                center: Vec3::ZERO,
                 radius: 0.5,
                 material,
+                transform: Transform {
+                    position: Vec3::new(x, 1.0, z),
+                    ..Default::default()
+                },
             });
         }
 
-        // 3-Point Lighting Setup
+        // 3-Point Lighting Setup, powers in watts (see `LightUnits::Radiometric`)
         
         // Key Light: Main light from front-right, slightly above
         // Warm white, brightest light
@@ -119,17 +169,23 @@ impl Default for Scene {
             position: Vec3::new(5.0, 6.0, 4.0),
             direction: Vec3::ZERO,
             color: Vec3::new(1.0, 0.98, 0.95), // Slightly warm
-            intensity: 0.8,
+            intensity: 2400.0,
+            size: Vec2::ZERO,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
         };
 
         // Fill Light: Softer light from front-left to reduce harsh shadows
-        // Cool white, about 50% of key light intensity
+        // Cool white, about 50% of key light brightness at the center since it is closer
         let fill_light = Light {
             light_type: LightType::Point,
             position: Vec3::new(-4.0, 3.0, 3.0),
             direction: Vec3::ZERO,
             color: Vec3::new(0.95, 0.98, 1.0), // Slightly cool
-            intensity: 0.4,
+            intensity: 540.0,
+            size: Vec2::ZERO,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
         };
 
         // Rim/Back Light: From behind to create separation and highlights
@@ -139,20 +195,236 @@ impl Default for Scene {
             position: Vec3::new(0.0, 7.0, -5.0),
             direction: Vec3::ZERO,
             color: Vec3::new(1.0, 1.0, 1.0),
-            intensity: 0.5,
+            intensity: 1500.0,
+            size: Vec2::ZERO,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
         };
 
-        Scene {
+        // Sun: color and intensity come from the sky, see `update_sun`
+        let sky = Sky::default();
+        let sun = Light {
+            light_type: LightType::Directional,
+            position: Vec3::new(0.0, 5.0, 0.0),
+            direction: -sky.sun_direction(),
+            color: Vec3::ONE,
+            intensity: 0.0,
+            size: Vec2::ZERO,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+        };
+
+        let mut scene = Scene {
             spheres,
             cubes,
             planes,
-            lights: vec![key_light, fill_light, rim_light],
-        }
+            lights: vec![key_light, fill_light, rim_light, sun],
+            background: Background::Sky(sky),
+            ..Scene::empty()
+        };
+        scene.update_sun();
+        scene.rebuild_bvh();
+        scene
     }
 }
 
 impl Scene {
+    /// A scene without any objects or lights.
+    pub fn empty() -> Self {
+        Self {
+            spheres: Vec::new(),
+            cubes: Vec::new(),
+            planes: Vec::new(),
+            meshes: Vec::new(),
+            lights: Vec::new(),
+            light_units: LightUnits::default(),
+            background: Background::default(),
+            bvh: Bvh::default(),
+            objects_removed: false,
+        }
+    }
+
+    /// Couples a physical sky to the first directional light: the sky's sun points against the
+    /// light, and the light gets the color and brightness of sunlight through that sky.
+    /// Call after turning the sun or changing the sky.
+    pub fn update_sun(&mut self) {
+        let Background::Sky(sky) = &mut self.background else {
+            return;
+        };
+        let Some(sun) = self.lights.iter_mut().find(|light| light.light_type == LightType::Directional) else {
+            return;
+        };
+        sky.set_sun_direction(-sun.direction.normalize());
+        let (color, irradiance) = sky.sunlight();
+        sun.color = color;
+        let scale = |units: LightUnits| units.scale(LightType::Directional, f32::INFINITY);
+        sun.intensity = irradiance * scale(self.light_units) / scale(LightUnits::Radiometric);
+    }
+
+    /// Switches to reading light intensities in `units`. Point and spot lights are converted to
+    /// light the origin as before, so only the falloff changes.
+    pub fn set_light_units(&mut self, units: LightUnits) {
+        for light in &mut self.lights {
+            let distance = light.position.length().max(1.0);
+            light.intensity *= units.scale(light.light_type, distance) / self.light_units.scale(light.light_type, distance);
+        }
+        self.light_units = units;
+    }
+
+    /// The material of every object.
+    pub fn materials(&self) -> impl Iterator<Item = &Material> {
+        self.spheres
+            .iter()
+            .map(|sphere| &sphere.material)
+            .chain(self.cubes.iter().map(|cube| &cube.material))
+            .chain(self.planes.iter().map(|plane| &plane.material))
+            .chain(self.meshes.iter().map(|mesh| &mesh.material))
+    }
+
+    fn object_bounds(&self) -> Vec<Aabb> {
+        self.spheres
+            .iter()
+            .map(Bounded::bounds)
+            .chain(self.cubes.iter().map(Bounded::bounds))
+            .chain(self.meshes.iter().map(Bounded::bounds))
+            .collect()
+    }
+
+    /// Brings the BVH up to date with the objects. Refits if only positions or sizes
+    /// changed, rebuilds if objects were added or removed: a tree refitted to a different
+    /// set of objects stays correct, but gets slower with every such edit.
+    pub fn rebuild_bvh(&mut self) {
+        // Meshes keep theirs through every edit but to their geometry, which is rare and
+        // expensive to redo for an imported model
+        for mesh in self.meshes.iter_mut().filter(|mesh| !mesh.has_bvh()) {
+            mesh.rebuild_bvh();
+        }
+
+        let bounds = self.object_bounds();
+        if bounds.len() == self.bvh.primitive_count() && !self.objects_removed {
+            self.bvh.refit(&bounds);
+        } else {
+            self.bvh = Bvh::build(&bounds);
+        }
+        self.objects_removed = false;
+    }
+
     pub fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.intersect_object(ray, t_min, t_max).map(|(_, hit)| hit)
+    }
+
+    /// The object under `ray`, e.g. one from `Camera::get_ray` through a clicked pixel.
+    pub fn pick(&self, ray: &Ray) -> Option<ObjectId> {
+        self.intersect_object(ray, 0.0, f32::INFINITY).map(|(id, _)| id)
+    }
+
+    /// The closest area light surface along `ray` and its light. Lights aren't objects:
+    /// `intersect` goes through them, so they don't block shadow rays.
+    pub fn intersect_lights(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, &Light)> {
+        let mut closest = None;
+        let mut closest_t = t_max;
+        for light in &self.lights {
+            if let Some(t) = light.intersect(ray, t_min, closest_t) {
+                closest_t = t;
+                closest = Some((t, light));
+            }
+        }
+        closest
+    }
+
+    /// Like `intersect`, but also says which object was hit.
+    pub fn intersect_object(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(ObjectId, HitRecord)> {
+        let mut closest_hit: Option<HitRecord> = None;
+        let mut closest_index = 0;
+
+        let sphere_count = self.spheres.len();
+        let cube_end = sphere_count + self.cubes.len();
+        self.bvh.intersect(ray, t_min, t_max, |index, closest_t| {
+            let hit = if index < sphere_count {
+                self.spheres[index].intersect(ray, t_min, closest_t)
+            } else if index < cube_end {
+                self.cubes[index - sphere_count].intersect(ray, t_min, closest_t)
+            } else {
+                self.meshes[index - cube_end].intersect(ray, t_min, closest_t)
+            }?;
+
+            // Primitives accept hits at exactly closest_t. To give the same result as testing
+            // the objects in list order, a tie goes to the object that comes later.
+            if let Some(closest) = &closest_hit {
+                if hit.t == closest.t && index < closest_index {
+                    return None;
+                }
+            }
+
+            closest_index = index;
+            let t = hit.t;
+            closest_hit = Some(hit);
+            Some(t)
+        });
+
+        let mut closest = closest_hit.map(|hit| {
+            let id = if closest_index < sphere_count {
+                ObjectId::Sphere(closest_index)
+            } else if closest_index < cube_end {
+                ObjectId::Cube(closest_index - sphere_count)
+            } else {
+                ObjectId::Mesh(closest_index - cube_end)
+            };
+            (id, hit)
+        });
+
+        let mut closest_t = closest.as_ref().map_or(t_max, |(_, hit)| hit.t);
+        for (i, plane) in self.planes.iter().enumerate() {
+            if let Some(hit) = plane.intersect(ray, t_min, closest_t) {
+                closest_t = hit.t;
+                closest = Some((ObjectId::Plane(i), hit));
+            }
+        }
+
+        closest
+    }
+
+    /// Whether `id` still refers to an object.
+    pub fn contains(&self, id: ObjectId) -> bool {
+        match id {
+            ObjectId::Sphere(i) => i < self.spheres.len(),
+            ObjectId::Cube(i) => i < self.cubes.len(),
+            ObjectId::Plane(i) => i < self.planes.len(),
+            ObjectId::Mesh(i) => i < self.meshes.len(),
+        }
+    }
+
+    pub fn transform_mut(&mut self, id: ObjectId) -> Option<&mut Transform> {
+        match id {
+            ObjectId::Sphere(i) => self.spheres.get_mut(i).map(|sphere| &mut sphere.transform),
+            ObjectId::Cube(i) => self.cubes.get_mut(i).map(|cube| &mut cube.transform),
+            ObjectId::Plane(i) => self.planes.get_mut(i).map(|plane| &mut plane.transform),
+            ObjectId::Mesh(i) => self.meshes.get_mut(i).map(|mesh| &mut mesh.transform),
+        }
+    }
+
+    /// Removes an object. Ids of later objects of the same kind shift down by one.
+    pub fn remove(&mut self, id: ObjectId) {
+        self.objects_removed = true;
+        match id {
+            ObjectId::Sphere(i) => {
+                self.spheres.remove(i);
+            }
+            ObjectId::Cube(i) => {
+                self.cubes.remove(i);
+            }
+            ObjectId::Plane(i) => {
+                self.planes.remove(i);
+            }
+            ObjectId::Mesh(i) => {
+                self.meshes.remove(i);
+            }
+        }
+    }
+
+    /// Reference implementation that tests every object, used to validate the BVH.
+    #[cfg(test)]
+    pub fn intersect_linear(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let mut closest_hit: Option<HitRecord> = None;
         let mut closest_t = t_max;
 
@@ -170,6 +442,13 @@ impl Scene {
             }
         }
 
+        for mesh in &self.meshes {
+            if let Some(hit) = mesh.intersect(ray, t_min, closest_t) {
+                closest_t = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
         for plane in &self.planes {
             if let Some(hit) = plane.intersect(ray, t_min, closest_t) {
                 closest_t = hit.t;
@@ -180,3 +459,63 @@ impl Scene {
         closest_hit
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn pick_names_the_object_in_front() {
+        let scene = Scene::default();
+        let first_sphere = scene.spheres[0].transform.position;
+
+        // Straight down onto the first sphere, with its cube and the floor behind it
+        let ray = Ray::new(first_sphere + Vec3::Y * 5.0, -Vec3::Y);
+        assert_eq!(scene.pick(&ray), Some(ObjectId::Sphere(0)));
+
+        // Down through empty space between the objects hits the floor
+        assert_eq!(scene.pick(&Ray::new(Vec3::new(0.0, 5.0, 0.0), -Vec3::Y)), Some(ObjectId::Plane(0)));
+        assert_eq!(scene.pick(&Ray::new(Vec3::ZERO, Vec3::Y)), None);
+
+        let mut scene = scene;
+        scene.remove(ObjectId::Sphere(0));
+        scene.rebuild_bvh();
+        assert_eq!(scene.pick(&ray), Some(ObjectId::Cube(0)));
+        assert!(!scene.contains(ObjectId::Sphere(4)));
+    }
+
+    /// Replacing an object keeps the count, but still gets a tree built for the new one.
+    #[test]
+    fn replacing_objects_rebuilds_the_bvh() {
+        let mut scene = Scene::default();
+        let far_away = Sphere { center: Vec3::new(50.0, 0.0, 50.0), radius: 1.0, material: Material::default(), transform: Transform::default() };
+        scene.remove(ObjectId::Sphere(0));
+        scene.spheres.push(far_away);
+        scene.rebuild_bvh();
+        assert_eq!(format!("{:?}", scene.bvh), format!("{:?}", Bvh::build(&scene.object_bounds())));
+    }
+
+    #[test]
+    fn switching_light_units_keeps_the_center_lit_the_same() {
+        let mut scene = Scene::default();
+        let mut rng = StdRng::seed_from_u64(3);
+        let key = scene.lights[0];
+        let near = key.position * 0.5;
+        let radiometric = |point| key.sample(point, LightUnits::Radiometric, &mut StdRng::seed_from_u64(4)).unwrap().intensity;
+
+        // Halfway to the light it is four times as bright
+        assert!((radiometric(near) / radiometric(Vec3::ZERO) - Vec3::splat(4.0)).length() < 1e-3);
+
+        scene.set_light_units(LightUnits::Legacy);
+        let legacy = scene.lights[0];
+        for point in [Vec3::ZERO, near] {
+            let intensity = legacy.sample(point, LightUnits::Legacy, &mut rng).unwrap().intensity;
+            assert!((intensity - radiometric(Vec3::ZERO)).length() < 1e-5);
+        }
+
+        scene.set_light_units(LightUnits::Radiometric);
+        assert!((scene.lights[0].intensity - key.intensity).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,553 @@
+//! Versioned scene files.
+//!
+//! A scene file holds everything needed to reproduce a render: the objects and lights,
+//! both cameras and the `Raytracer` settings. Files are JSON or RON, picked by extension.
+
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::math::Transform;
+use crate::primitives::{LightType, LightUnits, Material};
+use crate::raytracer::Raytracer;
+use crate::scene::Scene;
+use crate::sky::{MAX_TURBIDITY, MIN_TURBIDITY};
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Bumped whenever a change to the format would make older readers misinterpret a file.
+/// Fields added since version 1 have defaults, so older files still load.
+///
+/// 2: Triangle meshes
+/// 3: Object transforms
+/// 4: Area lights and emissive materials
+/// 5: Spot lights
+/// 6: Light units, radiometric unless the file says otherwise
+/// 7: Backgrounds, with environment maps embedded in the file
+/// 8: Physical sky background
+/// 9: Principled materials
+pub const SCENE_FILE_VERSION: u32 = 9;
+
+/// Largest image side a scene file may ask for.
+const MAX_RESOLUTION: u32 = 4096;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub version: u32,
+    pub scene: Scene,
+    pub camera: Camera,      // The camera used for raytracing
+    pub view_camera: Camera, // The camera used to view the 3D scene
+    pub raytracer: Raytracer,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SceneFormat {
+    Json,
+    Ron,
+}
+
+impl SceneFormat {
+    /// Format matching the extension of `file_name`.
+    pub fn from_file_name(file_name: &str) -> Result<Self, SceneFileError> {
+        let extension = file_name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(Self::Json),
+            Some("ron") => Ok(Self::Ron),
+            _ => Err(SceneFileError::UnknownFormat(file_name.to_string())),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Ron => "ron",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    UnknownFormat(String),    // File name without a .json or .ron extension
+    Parse(String),            // Not valid JSON/RON, or not shaped like a scene file
+    UnsupportedVersion(u32),  // Written by a newer (or broken) version of the app
+    Invalid(String),          // Well-formed, but describes a scene we can't render
+    Encode(String),           // Serializing the scene failed
+    Io(String),               // Reading or writing the file failed
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(name) => write!(f, "'{}' is not a .json or .ron scene file", name),
+            Self::Parse(message) => write!(f, "Could not parse scene file: {}", message),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Scene file version {} is not supported (expected 1 to {})",
+                version, SCENE_FILE_VERSION
+            ),
+            Self::Invalid(message) => write!(f, "Invalid scene file: {}", message),
+            Self::Encode(message) => write!(f, "Could not encode scene file: {}", message),
+            Self::Io(message) => write!(f, "Could not access scene file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+/// Just enough of a scene file to check its version before parsing the rest.
+#[derive(Deserialize)]
+struct VersionHeader {
+    version: u32,
+}
+
+impl SceneFile {
+    pub fn new(scene: &Scene, camera: &Camera, view_camera: &Camera, raytracer: &Raytracer) -> Self {
+        Self {
+            version: SCENE_FILE_VERSION,
+            scene: scene.clone(),
+            camera: *camera,
+            view_camera: *view_camera,
+            raytracer: raytracer.clone(),
+        }
+    }
+
+    pub fn encode(&self, format: SceneFormat) -> Result<String, SceneFileError> {
+        let result = match format {
+            SceneFormat::Json => serde_json::to_string_pretty(self).map_err(|err| err.to_string()),
+            SceneFormat::Ron => {
+                ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())
+            }
+        };
+        result.map_err(SceneFileError::Encode)
+    }
+
+    /// Parses and validates a scene file. The BVH of the returned scene still has to be built.
+    pub fn decode(text: &str, format: SceneFormat) -> Result<Self, SceneFileError> {
+        // Check the version first, so a newer file reports that instead of a confusing parse error
+        let header: VersionHeader = parse(text, format)?;
+        if !(1..=SCENE_FILE_VERSION).contains(&header.version) {
+            return Err(SceneFileError::UnsupportedVersion(header.version));
+        }
+
+        let mut file: SceneFile = parse(text, format)?;
+        file.validate()?;
+        if header.version < 3 {
+            file.move_positions_into_transforms();
+        }
+        if header.version < 6 {
+            // Light intensities had no units and no falloff yet
+            file.scene.light_units = LightUnits::Legacy;
+        }
+        Ok(file)
+    }
+
+    /// Older files place spheres and cubes through their geometry alone. Moving their centers
+    /// into the transform renders the same, but makes rotation and scale pivot on the object.
+    fn move_positions_into_transforms(&mut self) {
+        for sphere in &mut self.scene.spheres {
+            sphere.transform.position = sphere.transform.to_mat4().transform_point3(sphere.center);
+            sphere.center = Vec3::ZERO;
+        }
+        for cube in &mut self.scene.cubes {
+            let center = (cube.min + cube.max) / 2.0;
+            cube.transform.position = cube.transform.to_mat4().transform_point3(center);
+            cube.min -= center;
+            cube.max -= center;
+        }
+        self.version = SCENE_FILE_VERSION;
+    }
+
+    /// Checks that the file describes a scene that can be rendered. `decode` already does this;
+    /// call it again after changing settings.
+    pub fn validate(&self) -> Result<(), SceneFileError> {
+        let raytracer = &self.raytracer;
+        if !(1..=MAX_RESOLUTION).contains(&raytracer.width) || !(1..=MAX_RESOLUTION).contains(&raytracer.height) {
+            return invalid(format!(
+                "raytracer: resolution {}x{} must be between 1 and {} on each side",
+                raytracer.width, raytracer.height, MAX_RESOLUTION
+            ));
+        }
+        if raytracer.samples_per_pixel == 0 {
+            return invalid("raytracer: samples_per_pixel must be at least 1".to_string());
+        }
+        if !raytracer.tone_mapping.exposure.is_finite() {
+            return invalid(format!("raytracer: exposure must be finite, got {}", raytracer.tone_mapping.exposure));
+        }
+
+        validate_camera("camera", &self.camera)?;
+        validate_camera("view_camera", &self.view_camera)?;
+
+        for (i, sphere) in self.scene.spheres.iter().enumerate() {
+            let name = format!("spheres[{}]", i);
+            validate_vec(&name, "center", sphere.center)?;
+            if !(sphere.radius.is_finite() && sphere.radius > 0.0) {
+                return invalid(format!("{}: radius must be positive, got {}", name, sphere.radius));
+            }
+            validate_material(&name, &sphere.material)?;
+            validate_transform(&name, &sphere.transform)?;
+        }
+
+        for (i, cube) in self.scene.cubes.iter().enumerate() {
+            let name = format!("cubes[{}]", i);
+            validate_vec(&name, "min", cube.min)?;
+            validate_vec(&name, "max", cube.max)?;
+            if cube.min.cmpgt(cube.max).any() {
+                return invalid(format!("{}: min {} must not exceed max {}", name, cube.min, cube.max));
+            }
+            validate_material(&name, &cube.material)?;
+            validate_transform(&name, &cube.transform)?;
+        }
+
+        for (i, plane) in self.scene.planes.iter().enumerate() {
+            let name = format!("planes[{}]", i);
+            validate_vec(&name, "point", plane.point)?;
+            validate_direction(&name, "normal", plane.normal)?;
+            validate_material(&name, &plane.material)?;
+            validate_transform(&name, &plane.transform)?;
+        }
+
+        for (i, mesh) in self.scene.meshes.iter().enumerate() {
+            let name = format!("meshes[{}]", i);
+            if mesh.triangles.is_empty() {
+                return invalid(format!("{}: must have at least one triangle", name));
+            }
+            if !mesh.normals.is_empty() && mesh.normals.len() != mesh.vertices.len() {
+                return invalid(format!(
+                    "{}: has {} normals for {} vertices",
+                    name,
+                    mesh.normals.len(),
+                    mesh.vertices.len()
+                ));
+            }
+            if let Some(v) = mesh.vertices.iter().chain(&mesh.normals).find(|v| !v.is_finite()) {
+                return invalid(format!("{}: vertices and normals must be finite, got {}", name, v));
+            }
+            if let Some(index) = mesh.triangles.iter().flatten().find(|&&i| i as usize >= mesh.vertices.len()) {
+                return invalid(format!("{}: triangle index {} is out of range", name, index));
+            }
+            validate_material(&name, &mesh.material)?;
+            validate_transform(&name, &mesh.transform)?;
+        }
+
+        for (i, light) in self.scene.lights.iter().enumerate() {
+            let name = format!("lights[{}]", i);
+            validate_vec(&name, "position", light.position)?;
+            validate_color(&name, "color", light.color)?;
+            if matches!(light.light_type, LightType::Directional | LightType::Spot | LightType::Rectangle | LightType::Disk) {
+                validate_direction(&name, "direction", light.direction)?;
+            }
+            // Disks and spheres only use the radius in x
+            let size_positive = match light.light_type {
+                LightType::Point | LightType::Directional | LightType::Spot => true,
+                LightType::Rectangle => light.size.x > 0.0 && light.size.y > 0.0,
+                LightType::Disk | LightType::Sphere => light.size.x > 0.0,
+            };
+            if !(size_positive && light.size.is_finite()) {
+                return invalid(format!("{}: size must be positive, got {}", name, light.size));
+            }
+            if light.light_type == LightType::Spot
+                && !(0.0 <= light.inner_angle && light.inner_angle <= light.outer_angle && light.outer_angle <= 180.0)
+            {
+                return invalid(format!(
+                    "{}: spot angles must satisfy 0 <= inner <= outer <= 180, got {} and {}",
+                    name, light.inner_angle, light.outer_angle
+                ));
+            }
+            if !(light.intensity.is_finite() && light.intensity >= 0.0) {
+                return invalid(format!("{}: intensity must not be negative, got {}", name, light.intensity));
+            }
+        }
+
+        match &self.scene.background {
+            Background::Color(color) => validate_color("background", "color", *color)?,
+            Background::Gradient => {}
+            Background::Sky(sky) => {
+                validate_direction("background", "sun_direction", sky.sun_direction())?;
+                if !(MIN_TURBIDITY..=MAX_TURBIDITY).contains(&sky.turbidity()) {
+                    return invalid(format!(
+                        "background: turbidity must be between {} and {}, got {}",
+                        MIN_TURBIDITY, MAX_TURBIDITY, sky.turbidity()
+                    ));
+                }
+            }
+            Background::EnvironmentMap(map) => {
+                if !map.rotation.is_finite() {
+                    return invalid(format!("background: rotation must be finite, got {}", map.rotation));
+                }
+                if !(map.intensity.is_finite() && map.intensity >= 0.0) {
+                    return invalid(format!("background: intensity must not be negative, got {}", map.intensity));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(text: &str, format: SceneFormat) -> Result<T, SceneFileError> {
+    let result = match format {
+        SceneFormat::Json => serde_json::from_str(text).map_err(|err| err.to_string()),
+        SceneFormat::Ron => ron::from_str(text).map_err(|err| err.to_string()),
+    };
+    result.map_err(SceneFileError::Parse)
+}
+
+fn invalid(message: String) -> Result<(), SceneFileError> {
+    Err(SceneFileError::Invalid(message))
+}
+
+fn validate_vec(name: &str, field: &str, v: Vec3) -> Result<(), SceneFileError> {
+    if !v.is_finite() {
+        return invalid(format!("{}: {} must be finite, got {}", name, field, v));
+    }
+    Ok(())
+}
+
+fn validate_direction(name: &str, field: &str, v: Vec3) -> Result<(), SceneFileError> {
+    validate_vec(name, field, v)?;
+    if v.length_squared() == 0.0 {
+        return invalid(format!("{}: {} must not be zero", name, field));
+    }
+    Ok(())
+}
+
+fn validate_color(name: &str, field: &str, color: Vec3) -> Result<(), SceneFileError> {
+    validate_vec(name, field, color)?;
+    if color.min_element() < 0.0 {
+        return invalid(format!("{}: {} must not be negative, got {}", name, field, color));
+    }
+    Ok(())
+}
+
+fn validate_material(name: &str, material: &Material) -> Result<(), SceneFileError> {
+    validate_color(name, "material color", material.color)?;
+    validate_color(name, "material emission", material.emission)?;
+    let ranges = [
+        ("specular", material.specular, 0.0, f32::MAX),
+        ("shininess", material.shininess, 0.0, f32::MAX),
+        ("reflectivity", material.reflectivity, 0.0, 1.0),
+        ("roughness", material.roughness, 0.0, 1.0),
+        ("ior", material.ior, f32::MIN_POSITIVE, f32::MAX),
+        ("metallic", material.principled.metallic, 0.0, 1.0),
+        ("clearcoat", material.principled.clearcoat, 0.0, 1.0),
+        ("clearcoat roughness", material.principled.clearcoat_roughness, 0.0, 1.0),
+        ("sheen", material.principled.sheen, 0.0, 1.0),
+        ("transmission", material.principled.transmission, 0.0, 1.0),
+        ("anisotropy", material.principled.anisotropy, 0.0, 1.0),
+    ];
+    for (field, value, min, max) in ranges {
+        if !(min..=max).contains(&value) {
+            return invalid(format!("{}: material {} is out of range, got {}", name, field, value));
+        }
+    }
+    Ok(())
+}
+
+fn validate_rotation(name: &str, rotation: Quat) -> Result<(), SceneFileError> {
+    if !rotation.is_finite() || (rotation.length() - 1.0).abs() > 1e-3 {
+        return invalid(format!("{}: rotation must be a unit quaternion, got {}", name, rotation));
+    }
+    Ok(())
+}
+
+fn validate_transform(name: &str, transform: &Transform) -> Result<(), SceneFileError> {
+    validate_vec(name, "position", transform.position)?;
+    validate_rotation(name, transform.rotation)?;
+    validate_vec(name, "scale", transform.scale)?;
+    if transform.scale.cmpeq(Vec3::ZERO).any() {
+        return invalid(format!("{}: scale must not be zero on any axis, got {}", name, transform.scale));
+    }
+    Ok(())
+}
+
+fn validate_camera(name: &str, camera: &Camera) -> Result<(), SceneFileError> {
+    validate_vec(name, "position", camera.transform.position)?;
+    validate_rotation(name, camera.transform.rotation)?;
+    if !(camera.fov > 0.0 && camera.fov < 180.0) {
+        return invalid(format!("{}: fov must be between 0 and 180 degrees, got {}", name, camera.fov));
+    }
+    if !(camera.aspect_ratio.is_finite() && camera.aspect_ratio > 0.0) {
+        return invalid(format!("{}: aspect_ratio must be positive, got {}", name, camera.aspect_ratio));
+    }
+    Ok(())
+}
+
+/// Result of a load started with [`SceneFileLoader::open`]: the file name and its contents.
+pub type LoadResult = Result<(String, SceneFile), SceneFileError>;
+
+#[cfg(all(feature = "gui", target_arch = "wasm32"))]
+fn decode_named(file_name: String, text: &str) -> LoadResult {
+    let format = SceneFormat::from_file_name(&file_name)?;
+    SceneFile::decode(text, format).map(|file| (file_name, file))
+}
+
+/// Reads and decodes the scene file at `path`, picking the format by extension.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(path: &std::path::Path) -> Result<SceneFile, SceneFileError> {
+    let format = SceneFormat::from_file_name(&path.to_string_lossy())?;
+    let text = std::fs::read_to_string(path).map_err(|err| SceneFileError::Io(format!("{}: {}", path.display(), err)))?;
+    SceneFile::decode(&text, format)
+}
+
+/// Picks a scene file to load: a file dialog on native, a browser upload on wasm.
+/// Browser file access is asynchronous, so the result is collected with [`Self::poll`].
+#[cfg(feature = "gui")]
+#[derive(Default)]
+pub struct SceneFileLoader {
+    inbox: std::rc::Rc<std::cell::RefCell<Option<LoadResult>>>,
+}
+
+#[cfg(feature = "gui")]
+impl SceneFileLoader {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(&self) {
+        let Some(path) = rfd::FileDialog::new().add_filter("Scene", &["json", "ron"]).pick_file() else {
+            return; // Cancelled
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let result = load(&path).map(|file| (file_name, file));
+        *self.inbox.borrow_mut() = Some(result);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn open(&self) {
+        let inbox = self.inbox.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let Some(handle) = rfd::AsyncFileDialog::new().add_filter("Scene", &["json", "ron"]).pick_file().await else {
+                return; // Cancelled
+            };
+            let bytes = handle.read().await;
+            let result = String::from_utf8(bytes)
+                .map_err(|err| SceneFileError::Io(format!("{}: {}", handle.file_name(), err)))
+                .and_then(|text| decode_named(handle.file_name(), &text));
+            *inbox.borrow_mut() = Some(result);
+        });
+    }
+
+    /// The loaded file, once the user has picked one.
+    pub fn poll(&self) -> Option<LoadResult> {
+        self.inbox.borrow_mut().take()
+    }
+}
+
+/// Saves `file` through a save dialog. Returns the chosen file name, or None if cancelled.
+#[cfg(all(feature = "gui", not(target_arch = "wasm32")))]
+pub fn save(file: &SceneFile, format: SceneFormat) -> Result<Option<String>, SceneFileError> {
+    let text = file.encode(format)?;
+    let Some(path) = rfd::FileDialog::new()
+        .add_filter("Scene", &[format.extension()])
+        .set_file_name(format!("scene.{}", format.extension()))
+        .save_file()
+    else {
+        return Ok(None);
+    };
+    std::fs::write(&path, text).map_err(|err| SceneFileError::Io(format!("{}: {}", path.display(), err)))?;
+    Ok(Some(path.file_name().unwrap_or_default().to_string_lossy().into_owned()))
+}
+
+/// Saves `file` as a browser download. Returns the file name.
+#[cfg(all(feature = "gui", target_arch = "wasm32"))]
+pub fn save(file: &SceneFile, format: SceneFormat) -> Result<Option<String>, SceneFileError> {
+    use wasm_bindgen::JsCast;
+
+    let text = file.encode(format)?;
+    let file_name = format!("scene.{}", format.extension());
+    let js_error = |err: wasm_bindgen::JsValue| SceneFileError::Io(format!("{:?}", err));
+
+    let parts = js_sys::Array::of1(&wasm_bindgen::JsValue::from_str(&text));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("text/plain");
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+
+    // Clicking a temporary link with a download attribute saves the blob
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| SceneFileError::Io("No document".to_string()))?;
+    let link: web_sys::HtmlAnchorElement = document.create_element("a").map_err(js_error)?.unchecked_into();
+    link.set_href(&url);
+    link.set_download(&file_name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(js_error)?;
+
+    Ok(Some(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bounded;
+
+    fn example() -> SceneFile {
+        SceneFile::new(&Scene::default(), &Camera::default(), &Camera::default(), &Raytracer::default())
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        for format in [SceneFormat::Json, SceneFormat::Ron] {
+            let file = example();
+            let text = file.encode(format).unwrap();
+            let decoded = SceneFile::decode(&text, format).unwrap();
+
+            assert_eq!(decoded.camera, file.camera);
+            assert_eq!(decoded.raytracer.width, file.raytracer.width);
+            assert_eq!(decoded.scene.spheres.len(), file.scene.spheres.len());
+            assert_eq!(decoded.scene.spheres[0].material, file.scene.spheres[0].material);
+            assert_eq!(decoded.scene.lights.len(), file.scene.lights.len());
+        }
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut file = example();
+        file.version = SCENE_FILE_VERSION + 1;
+        let text = file.encode(SceneFormat::Json).unwrap();
+
+        let err = SceneFile::decode(&text, SceneFormat::Json).unwrap_err();
+        assert!(matches!(err, SceneFileError::UnsupportedVersion(v) if v == SCENE_FILE_VERSION + 1));
+
+        // Older files are still read
+        file.version = 1;
+        let text = file.encode(SceneFormat::Json).unwrap();
+        assert!(SceneFile::decode(&text, SceneFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn moves_old_object_positions_into_transforms() {
+        let mut file = example();
+        file.version = 2;
+        file.scene.spheres[0].center = Vec3::new(1.0, 2.0, 3.0);
+        file.scene.spheres[0].transform = Transform::default();
+        let before = file.scene.spheres[0].bounds();
+        let text = file.encode(SceneFormat::Json).unwrap();
+
+        let decoded = SceneFile::decode(&text, SceneFormat::Json).unwrap();
+        let sphere = &decoded.scene.spheres[0];
+        assert_eq!(sphere.center, Vec3::ZERO);
+        assert_eq!(sphere.transform.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(sphere.bounds(), before);
+
+        let cube = &decoded.scene.cubes[0];
+        assert_eq!(cube.min, -cube.max);
+        assert_eq!(cube.bounds(), file.scene.cubes[0].bounds());
+
+        // Written before intensities had units
+        assert_eq!(decoded.scene.light_units, LightUnits::Legacy);
+    }
+
+    #[test]
+    fn names_the_offending_object() {
+        let mut file = example();
+        file.scene.spheres[1].radius = -1.0;
+        let text = file.encode(SceneFormat::Ron).unwrap();
+
+        let err = SceneFile::decode(&text, SceneFormat::Ron).unwrap_err();
+        assert!(err.to_string().contains("spheres[1]: radius"), "{}", err);
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let err = SceneFile::decode("{\"version\": 1, \"scene\": 3}", SceneFormat::Json).unwrap_err();
+        assert!(matches!(err, SceneFileError::Parse(_)), "{}", err);
+        assert!(SceneFormat::from_file_name("scene.txt").is_err());
+    }
+}
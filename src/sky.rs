@@ -0,0 +1,206 @@
+//! Analytic daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for
+//! Daylight" (1999), and the sunlight that makes it through the same atmosphere.
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Kilocandela per m² of the model to the renderer's radiance. Picked so the sun lights a
+/// scene about as brightly as the default point lights do.
+const SKY_SCALE: f32 = 0.03;
+
+/// Illuminance of the sun above the atmosphere, in kilolux.
+const SUN_ILLUMINANCE: f32 = 128.0;
+
+/// Turbidities the model was fitted for.
+pub const MIN_TURBIDITY: f32 = 2.0;
+pub const MAX_TURBIDITY: f32 = 10.0;
+
+/// Wavelengths in micrometers that stand in for the red, green and blue channels.
+const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+
+/// The sky and its [`SkyModel`], which is looked up for every ray that misses the scene. The
+/// fields are only changed through setters so the model always matches them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SkyFile", into = "SkyFile")]
+pub struct Sky {
+    sun_direction: Vec3, // Towards the sun
+    turbidity: f32,      // Haziness, from 2 on a clear day to 10 in haze
+    model: SkyModel,
+}
+
+/// How a [`Sky`] is saved, without the model.
+#[derive(Serialize, Deserialize)]
+struct SkyFile {
+    sun_direction: Vec3,
+    turbidity: f32,
+}
+
+impl From<SkyFile> for Sky {
+    fn from(file: SkyFile) -> Self {
+        Self::new(file.sun_direction, file.turbidity)
+    }
+}
+
+impl From<Sky> for SkyFile {
+    fn from(sky: Sky) -> Self {
+        Self { sun_direction: sky.sun_direction, turbidity: sky.turbidity }
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::new(Vec3::new(-0.5, 0.6, -0.6).normalize(), 3.0)
+    }
+}
+
+/// A [`Sky`] with everything that only depends on the sun worked out, to look up many
+/// directions. Channels are luminance Y and chromaticity x and y, in that order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkyModel {
+    pub sun_direction: Vec3,
+    pub perez: [Vec3; 5], // Coefficients A to E of the Perez distribution for each channel
+    pub zenith: Vec3,     // Zenith value divided by the distribution towards the zenith
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        Self { sun_direction, turbidity, model: SkyModel::new(sun_direction, turbidity) }
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f32 {
+        self.turbidity
+    }
+
+    pub fn set_sun_direction(&mut self, sun_direction: Vec3) {
+        *self = Self::new(sun_direction, self.turbidity);
+    }
+
+    pub fn set_turbidity(&mut self, turbidity: f32) {
+        *self = Self::new(self.sun_direction, turbidity);
+    }
+
+    pub fn model(&self) -> &SkyModel {
+        &self.model
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        self.model.radiance(direction)
+    }
+
+    /// Color and irradiance of the sunlight reaching the ground, the irradiance in the units
+    /// of a radiometric directional light. Zero once the sun has set.
+    pub fn sunlight(&self) -> (Vec3, f32) {
+        let cos_theta = self.sun_direction.normalize().y;
+        if cos_theta <= 0.0 {
+            return (Vec3::ONE, 0.0);
+        }
+        // Relative air mass after Kasten and Young, which stays finite at the horizon
+        let zenith_angle = cos_theta.acos().to_degrees();
+        let air_mass = 1.0 / (cos_theta + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+        // Optical depth of Rayleigh scattering and of haze, from the Angstrom formula
+        let haze = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = Vec3::from_array(
+            WAVELENGTHS.map(|lambda| (-air_mass * (0.008735 * lambda.powf(-4.08) + haze * lambda.powf(-1.3))).exp()),
+        );
+        let brightest = transmittance.max_element();
+        (transmittance / brightest, SUN_ILLUMINANCE * SKY_SCALE * brightest)
+    }
+}
+
+impl SkyModel {
+    fn new(sun_direction: Vec3, turbidity: f32) -> Self {
+        let t = turbidity;
+        let sun_direction = sun_direction.normalize();
+        // The model only covers a sun above the horizon. Below it the sky fades out instead.
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+        let fade = ((sun_direction.y + 0.1) / 0.1).clamp(0.0, 1.0);
+
+        let perez = [
+            Vec3::new(0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608),
+            Vec3::new(-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092),
+            Vec3::new(-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102),
+            Vec3::new(0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537),
+            Vec3::new(-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |a: [f32; 4]| a[0] * theta_s.powi(3) + a[1] * theta_s.powi(2) + a[2] * theta_s + a[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let zenith = Vec3::new(zenith_luminance * SKY_SCALE * fade, zenith_x, zenith_y)
+            / perez_distribution(&perez, 1.0, theta_s.cos());
+        Self { sun_direction, perez, zenith }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalize();
+        // The model has no ground, so the sky below the horizon mirrors the horizon
+        let cos_theta = direction.y.max(0.01);
+        let cos_gamma = direction.dot(self.sun_direction);
+        let yxy = self.zenith * perez_distribution(&self.perez, cos_theta, cos_gamma);
+        yxy_to_rgb(yxy)
+    }
+}
+
+/// Relative brightness of each channel at an angle theta from the zenith and gamma from the sun.
+fn perez_distribution(perez: &[Vec3; 5], cos_theta: f32, cos_gamma: f32) -> Vec3 {
+    let [a, b, c, d, e] = *perez;
+    let gamma = cos_gamma.clamp(-1.0, 1.0).acos();
+    (Vec3::ONE + a * (b / cos_theta).exp()) * (Vec3::ONE + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// Luminance and chromaticity to linear sRGB.
+fn yxy_to_rgb(yxy: Vec3) -> Vec3 {
+    let (luminance, x, y) = (yxy.x, yxy.y, yxy.z);
+    if y <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+    .max(Vec3::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_sun_is_warmer_and_dimmer() {
+        let noon = Sky::new(Vec3::Y, 3.0);
+        let evening = Sky::new(Vec3::new(0.0, 0.1, -1.0), 3.0);
+
+        let (noon_color, noon_irradiance) = noon.sunlight();
+        let (evening_color, evening_irradiance) = evening.sunlight();
+        assert!(evening_irradiance < noon_irradiance);
+        assert!(evening_color.z / evening_color.x < noon_color.z / noon_color.x);
+
+        // Blue overhead, brighter towards the sun
+        let zenith = noon.radiance(Vec3::Y);
+        assert!(zenith.z > zenith.x, "{}", zenith);
+        assert!(evening.radiance(Vec3::new(0.0, 0.2, -1.0)).y > evening.radiance(Vec3::new(0.0, 0.2, 1.0)).y);
+
+        let night = Sky::new(Vec3::new(0.0, -0.5, -1.0), 3.0);
+        assert_eq!(night.sunlight().1, 0.0);
+        assert_eq!(night.radiance(Vec3::Y), Vec3::ZERO);
+
+        // Saved without the model, which loading works out again
+        let json = serde_json::to_string(&evening).unwrap();
+        assert_eq!(json, r#"{"sun_direction":[0.0,0.1,-1.0],"turbidity":3.0}"#);
+        assert_eq!(serde_json::from_str::<Sky>(&json).unwrap(), evening);
+    }
+}
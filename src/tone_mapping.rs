@@ -0,0 +1,174 @@
+//! Turns the linear HDR colors the renderer produces into colors for the screen: exposure,
+//! a tone curve that rolls bright values off towards white instead of clipping them, and the
+//! sRGB encoding displays and image files expect.
+
+use glam::{Mat3, Vec3};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum ToneMapper {
+    /// No curve, everything brighter than white clips.
+    Clamp,
+    /// Divides by one plus the luminance. Soft, but flattens highlights and never reaches white.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering transform. Contrasty, and very bright
+    /// colors bleach towards white.
+    #[default]
+    Aces,
+    /// Troy Sobotka's AgX in Benjamin Wrensch's polynomial fit. Desaturates bright colors
+    /// evenly, so lights don't shift hue as they get brighter.
+    AgX,
+    /// John Hable's filmic curve from Uncharted 2, with a toe that deepens the shadows.
+    Filmic,
+}
+
+impl ToneMapper {
+    pub const ALL: [ToneMapper; 5] = [Self::Clamp, Self::Reinhard, Self::Aces, Self::AgX, Self::Filmic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Clamp => "Clamp",
+            Self::Reinhard => "Reinhard",
+            Self::Aces => "ACES",
+            Self::AgX => "AgX",
+            Self::Filmic => "Filmic",
+        }
+    }
+
+    /// Maps a linear color of any brightness to a linear color from 0 to 1.
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        // Negative lobes of the reconstruction filters can leave pixels slightly below zero
+        let color = color.max(Vec3::ZERO);
+        let mapped = match self {
+            Self::Clamp => color,
+            Self::Reinhard => color / (1.0 + luminance(color)),
+            Self::Aces => aces(color),
+            Self::AgX => agx(color),
+            Self::Filmic => {
+                const WHITE: f32 = 11.2;
+                hable(color * 2.0) / hable(Vec3::splat(WHITE))
+            }
+        };
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+/// How the HDR image is shown. Only affects display, never the rendered samples.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub exposure: f32, // In stops, each one doubles the brightness
+    pub tone_mapper: ToneMapper,
+}
+
+impl ToneMapping {
+    /// The sRGB encoded display color, from 0 to 1, of a linear HDR color.
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let mapped = self.tone_mapper.apply(color * self.exposure.exp2());
+        Vec3::new(linear_to_srgb(mapped.x), linear_to_srgb(mapped.y), linear_to_srgb(mapped.z))
+    }
+
+    pub fn to_rgba(self, color: Vec3) -> [u8; 4] {
+        let display = (self.apply(color) * 255.0).round();
+        [display.x as u8, display.y as u8, display.z as u8, 255]
+    }
+
+    /// RGBA8 buffer of an image of linear HDR colors.
+    pub fn image_to_rgba(self, pixels: &[Vec3]) -> Vec<u8> {
+        pixels.iter().flat_map(|&color| self.to_rgba(color)).collect()
+    }
+}
+
+/// The sRGB transfer function: a short linear segment near black, then a 2.4 power curve.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn aces(color: Vec3) -> Vec3 {
+    // sRGB to the ACES working space and back, folded together with the RRT's saturation tweak
+    const INPUT: Mat3 = Mat3::from_cols(
+        Vec3::new(0.59719, 0.07600, 0.02840),
+        Vec3::new(0.35458, 0.90834, 0.13383),
+        Vec3::new(0.04823, 0.01566, 0.83777),
+    );
+    const OUTPUT: Mat3 = Mat3::from_cols(
+        Vec3::new(1.60475, -0.10208, -0.00327),
+        Vec3::new(-0.53108, 1.10813, -0.07276),
+        Vec3::new(-0.07367, -0.00605, 1.07602),
+    );
+    let v = INPUT * color;
+    let curve = (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081);
+    OUTPUT * curve
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    const INSET: Mat3 = Mat3::from_cols(
+        Vec3::new(0.842_479_06, 0.042_328_24, 0.042_375_654),
+        Vec3::new(0.078_433_6, 0.878_468_6, 0.078_433_6),
+        Vec3::new(0.079_223_745, 0.079_166_13, 0.879_143),
+    );
+    const OUTSET: Mat3 = Mat3::from_cols(
+        Vec3::new(1.196_879, -0.052_896_85, -0.052_971_635),
+        Vec3::new(-0.098_020_88, 1.151_903_1, -0.098_043_45),
+        Vec3::new(-0.099_029_74, -0.098_961_18, 1.151_073_7),
+    );
+    // The log encoding spans 16.5 stops around middle gray
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    let encoded = (INSET * color).max(Vec3::splat(1e-10)).map(f32::log2).clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV));
+    let x = (encoded - MIN_EV) / (MAX_EV - MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let contrast = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.002_32;
+
+    // The curve's output is display encoded with a 2.2 gamma, which the sRGB encoding redoes
+    (OUTSET * contrast).max(Vec3::ZERO).powf(2.2)
+}
+
+fn hable(x: Vec3) -> Vec3 {
+    const A: f32 = 0.15; // Shoulder strength
+    const B: f32 = 0.50; // Linear strength
+    const C: f32 = 0.10; // Linear angle
+    const D: f32 = 0.20; // Toe strength
+    const E: f32 = 0.02; // Toe numerator
+    const F: f32 = 0.30; // Toe denominator
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every curve keeps black black, never darkens a brighter color and gets close to white
+    /// for very bright ones.
+    #[test]
+    fn tone_mappers_roll_off_to_white() {
+        assert!((linear_to_srgb(0.5) - 0.735_4).abs() < 1e-4);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+
+        for tone_mapper in ToneMapper::ALL {
+            assert!(tone_mapper.apply(Vec3::ZERO).max_element() < 1e-3, "{:?}", tone_mapper);
+            assert!(tone_mapper.apply(Vec3::splat(1000.0)).min_element() > 0.95, "{:?}", tone_mapper);
+
+            let mut previous = 0.0;
+            for i in 0..=200 {
+                let gray = tone_mapper.apply(Vec3::splat(0.001 * 1.05f32.powi(i))).y;
+                assert!(gray >= previous, "{:?}", tone_mapper);
+                previous = gray;
+            }
+        }
+
+        // One stop more exposure doubles the light
+        let brighter = ToneMapping { exposure: 1.0, tone_mapper: ToneMapper::Clamp };
+        assert_eq!(brighter.to_rgba(Vec3::splat(0.25)), ToneMapping { exposure: 0.0, ..brighter }.to_rgba(Vec3::splat(0.5)));
+    }
+}
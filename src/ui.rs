@@ -1,7 +1,31 @@
+use crate::background::Background;
 use crate::camera::Camera;
-use crate::raytracer::Raytracer;
+use crate::film::FilterType;
+use crate::sky::{Sky, MAX_TURBIDITY, MIN_TURBIDITY};
+use crate::gpu_raytracer::GpuRaytracer;
+use crate::raytracer::{Backend, Raytracer};
+use crate::scene_file::SceneFormat;
+use crate::gizmo::{Gizmo, GizmoMode};
+use crate::math::Transform;
 use egui::Ui;
-use glam::Quat;
+use glam::{EulerRot, Quat};
+
+/// What the properties panel and the gizmo act on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Selection {
+    Object(ObjectId),
+    Light(usize),
+    Camera, // The render camera
+}
+
+/// Scene file action picked in the controls, carried out by the app.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SceneFileRequest {
+    Load,
+    Save(SceneFormat),
+    ImportObj,
+    LoadEnvironmentMap,
+}
 
 #[derive(PartialEq)]
 pub enum ExplanationTab {
@@ -12,29 +36,56 @@ pub enum ExplanationTab {
 
 pub struct UiState {
     pub auto_update: bool,
+    pub progressive: bool,
     pub show_rays: bool,
     pub ray_count: usize,
     pub camera_pitch: f32,
     pub camera_yaw: f32,
     pub explanation_tab: ExplanationTab,
+    pub gpu_available: bool, // Whether the GPU backend can run on this adapter
+    pub scene_file_request: Option<SceneFileRequest>,
+    pub scene_file_status: Option<Result<String, String>>, // Outcome of the last load or save
+    pub selected: Option<Selection>,
+    pub gizmo: Gizmo,
 }
 
 impl Default for UiState {
     fn default() -> Self {
         Self {
             auto_update: true,
+            progressive: true,
             show_rays: true,
             ray_count: 25,
             camera_pitch: 0.0,
             camera_yaw: 0.0,
             explanation_tab: ExplanationTab::HowToUse,
+            gpu_available: false,
+            scene_file_request: None,
+            scene_file_status: None,
+            selected: None,
+            gizmo: Gizmo::default(),
+        }
+    }
+}
+
+impl UiState {
+    /// The backend that renders `scene` with these settings, and why it isn't the selected
+    /// one when the GPU can't take it.
+    pub fn active_backend(&self, raytracer: &Raytracer, scene: &Scene) -> (Backend, Option<String>) {
+        match raytracer.backend {
+            Backend::Cpu => (Backend::Cpu, None),
+            Backend::Gpu if !self.gpu_available => (Backend::Cpu, Some("no compute shader support".to_string())),
+            Backend::Gpu => match GpuRaytracer::unsupported(scene) {
+                Some(feature) => (Backend::Cpu, Some(format!("the GPU doesn't render {}", feature))),
+                None => (Backend::Gpu, None),
+            },
         }
     }
 }
 
-use crate::scene::Scene;
-use crate::primitives::{Light, LightType};
-use glam::Vec3;
+use crate::scene::{ObjectId, Scene};
+use crate::primitives::{Cube, Light, LightType, LightUnits, Material, MaterialType, Plane, Sphere};
+use glam::{Vec2, Vec3};
 
 pub fn render_controls(
     ui: &mut Ui,
@@ -103,19 +154,46 @@ pub fn render_controls(
 
     ui.horizontal(|ui| {
         ui.label("Mode:");
-        egui::ComboBox::from_id_source("render_mode")
-            .selected_text(format!("{:?}", raytracer.mode))
+        egui::ComboBox::from_id_salt("render_mode")
+            .selected_text(format!("{:?} ({:?})", raytracer.mode, raytracer.backend))
             .show_ui(ui, |ui| {
-                if ui.selectable_value(&mut raytracer.mode, crate::raytracer::RenderMode::Raytracing, "Raytracing").changed() {
-                    *trigger_render = true;
-                }
-                if ui.selectable_value(&mut raytracer.mode, crate::raytracer::RenderMode::Pathtracing, "Pathtracing").changed() {
-                    *trigger_render = true;
+                use crate::raytracer::RenderMode;
+                for (backend, mode, label) in [
+                    (Backend::Cpu, RenderMode::Raytracing, "Raytracing (CPU)"),
+                    (Backend::Cpu, RenderMode::Pathtracing, "Pathtracing (CPU)"),
+                    (Backend::Gpu, RenderMode::Raytracing, "Raytracing (GPU)"),
+                    (Backend::Gpu, RenderMode::Pathtracing, "Pathtracing (GPU)"),
+                ] {
+                    let enabled = backend == Backend::Cpu || ui_state.gpu_available;
+                    let selected = raytracer.backend == backend && raytracer.mode == mode;
+                    let response = ui
+                        .add_enabled(enabled, egui::SelectableLabel::new(selected, label))
+                        .on_disabled_hover_text("This browser or adapter has no compute shader support");
+                    if response.clicked() && !selected {
+                        raytracer.backend = backend;
+                        raytracer.mode = mode;
+                        *trigger_render = true;
+                    }
                 }
             });
     });
 
-    if ui.add(egui::Slider::new(&mut raytracer.max_bounces, 0..=10).text("Max Bounces")).changed() {
+    // Raytracing branches at every glass surface, paths end early by Russian roulette
+    let is_pathtracing = raytracer.mode == crate::raytracer::RenderMode::Pathtracing;
+    let max_bounces = if is_pathtracing { 100 } else { 10 };
+    if ui
+        .add(egui::Slider::new(&mut raytracer.max_bounces, 0..=max_bounces).logarithmic(is_pathtracing).text("Max Bounces"))
+        .changed()
+    {
+        *trigger_render = true;
+    }
+
+    if is_pathtracing
+        && ui
+            .add(egui::Slider::new(&mut raytracer.roulette_depth, 0..=10).text("Roulette Depth"))
+            .on_hover_text("Bounces before dim paths may end at random. Lower is faster but noisier")
+            .changed()
+    {
         *trigger_render = true;
     }
 
@@ -123,6 +201,80 @@ pub fn render_controls(
         *trigger_render = true;
     }
 
+    // Follows the backend that runs, the CPU renders every scene the GPU can't
+    let (backend, _) = ui_state.active_backend(raytracer, scene);
+    ui.add_enabled_ui(backend == Backend::Cpu, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Sampler:");
+            egui::ComboBox::from_id_salt("sampler")
+                .selected_text(raytracer.sampler.name())
+                .show_ui(ui, |ui| {
+                    for sampler in crate::sampler::SamplerType::ALL {
+                        if ui.selectable_value(&mut raytracer.sampler, sampler, sampler.name()).changed() {
+                            *trigger_render = true;
+                        }
+                    }
+                });
+        })
+        .response
+        .on_hover_text("How samples spread over each pixel. Well spread samples converge faster")
+        .on_disabled_hover_text("The GPU always draws independent random numbers");
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            if filter_combo(ui, "filter", &mut raytracer.filter) {
+                *trigger_render = true;
+            }
+        })
+        .response
+        .on_hover_text("How the samples around each pixel are weighed into it")
+        .on_disabled_hover_text("The GPU always averages the samples inside each pixel");
+
+        ui.horizontal(|ui| {
+            let mut compare = raytracer.compare_filter.is_some();
+            if ui
+                .checkbox(&mut compare, "Compare with")
+                .on_hover_text("Filter the right half of the image differently, from the same samples")
+                .changed()
+            {
+                let other = if raytracer.filter == FilterType::Box { FilterType::Mitchell } else { FilterType::Box };
+                raytracer.compare_filter = compare.then_some(other);
+                *trigger_render = true;
+            }
+            if let Some(filter) = &mut raytracer.compare_filter {
+                if filter_combo(ui, "compare_filter", filter) {
+                    *trigger_render = true;
+                }
+            }
+        });
+    });
+
+    // Display only, the app redraws the image from the samples it has
+    ui.add(egui::Slider::new(&mut raytracer.tone_mapping.exposure, -5.0..=5.0).step_by(0.1).suffix(" EV").text("Exposure"))
+        .on_hover_text("Brightens or darkens the image by this many stops before tone mapping");
+
+    ui.horizontal(|ui| {
+        ui.label("Tone Mapping:");
+        let tone_mapper = &mut raytracer.tone_mapping.tone_mapper;
+        egui::ComboBox::from_id_salt("tone_mapper")
+            .selected_text(tone_mapper.name())
+            .show_ui(ui, |ui| {
+                for option in crate::tone_mapping::ToneMapper::ALL {
+                    ui.selectable_value(tone_mapper, option, option.name());
+                }
+            });
+    })
+    .response
+    .on_hover_text("How colors brighter than the display can show are brought into range");
+
+    // Progressive accumulation only makes sense for the stochastic pathtracer
+    if ui.add_enabled(is_pathtracing, egui::Checkbox::new(&mut ui_state.progressive, "Progressive"))
+        .on_hover_text("Keep adding samples every frame until something changes")
+        .changed()
+    {
+        *trigger_render = true;
+    }
+
     ui.checkbox(&mut ui_state.auto_update, "Auto Update");
 
     if ui.button("Render Now").clicked() {
@@ -139,20 +291,133 @@ pub fn render_controls(
         }
     }
 
-    if ui_state.show_rays {
-        if ui.add(egui::Slider::new(&mut ui_state.ray_count, 1..=200).text("Ray Count")).changed() {
-            *trigger_render = true;
-        }
+    if ui_state.show_rays && ui.add(egui::Slider::new(&mut ui_state.ray_count, 1..=200).text("Ray Count")).changed() {
+        *trigger_render = true;
     }
 
     if changed && ui_state.auto_update {
         *trigger_render = true;
     }
 
+    ui.separator();
+    ui.heading("Objects");
+    ui.label("Click an object in either view to select it");
+
+    ui.horizontal(|ui| {
+        ui.label("Gizmo:");
+        let gizmo = &mut ui_state.gizmo;
+        ui.selectable_value(&mut gizmo.mode, GizmoMode::Translate, "Move");
+        ui.selectable_value(&mut gizmo.mode, GizmoMode::Rotate, "Rotate");
+        ui.selectable_value(&mut gizmo.mode, GizmoMode::Scale, "Scale");
+    });
+    ui.horizontal(|ui| {
+        let snapping = &mut ui_state.gizmo.snapping;
+        ui.checkbox(&mut snapping.enabled, "Snap");
+        ui.add_enabled_ui(snapping.enabled, |ui| {
+            ui.add(egui::DragValue::new(&mut snapping.translate).speed(0.01).range(0.01..=10.0).prefix("Move: "));
+            ui.add(egui::DragValue::new(&mut snapping.rotate).speed(0.5).range(1.0..=90.0).prefix("Rotate: ").suffix("°"));
+            ui.add(egui::DragValue::new(&mut snapping.scale).speed(0.01).range(0.01..=10.0).prefix("Scale: "));
+        });
+    });
+
+    ui.horizontal(|ui| {
+        let above_floor = Transform { position: Vec3::new(0.0, 0.5, 0.0), ..Default::default() };
+        if ui.button("Add Sphere").clicked() {
+            scene.spheres.push(Sphere {
+                center: Vec3::ZERO,
+                radius: 0.5,
+                material: Material::default(),
+                transform: above_floor,
+            });
+            ui_state.selected = Some(Selection::Object(ObjectId::Sphere(scene.spheres.len() - 1)));
+            *trigger_render = true;
+        }
+        if ui.button("Add Cube").clicked() {
+            scene.cubes.push(Cube {
+                min: Vec3::splat(-0.5),
+                max: Vec3::splat(0.5),
+                material: Material::default(),
+                transform: above_floor,
+            });
+            ui_state.selected = Some(Selection::Object(ObjectId::Cube(scene.cubes.len() - 1)));
+            *trigger_render = true;
+        }
+        if ui.button("Add Plane").clicked() {
+            scene.planes.push(Plane {
+                point: Vec3::ZERO,
+                normal: Vec3::Y,
+                material: Material::default(),
+                transform: Transform::default(),
+            });
+            ui_state.selected = Some(Selection::Object(ObjectId::Plane(scene.planes.len() - 1)));
+            *trigger_render = true;
+        }
+    });
+
+    egui::CollapsingHeader::new("All Objects").show(ui, |ui| {
+        let ids = (0..scene.spheres.len()).map(ObjectId::Sphere)
+            .chain((0..scene.cubes.len()).map(ObjectId::Cube))
+            .chain((0..scene.planes.len()).map(ObjectId::Plane))
+            .chain((0..scene.meshes.len()).map(ObjectId::Mesh));
+        for id in ids {
+            let selection = Selection::Object(id);
+            if ui.selectable_label(ui_state.selected == Some(selection), object_name(scene, id)).clicked() {
+                ui_state.selected = Some(selection);
+            }
+        }
+    });
+
+    // Ids go stale when a scene is loaded or objects are removed
+    ui_state.selected = ui_state.selected.filter(|&selection| match selection {
+        Selection::Object(id) => scene.contains(id),
+        Selection::Light(i) => i < scene.lights.len(),
+        Selection::Camera => true,
+    });
+    match ui_state.selected {
+        Some(Selection::Object(id)) => {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(object_name(scene, id));
+                    if ui.button("Delete").clicked() {
+                        scene.remove(id);
+                        ui_state.selected = None;
+                        *trigger_render = true;
+                    }
+                });
+                if ui_state.selected.is_some() {
+                    *trigger_render |= object_properties(ui, scene, id);
+                }
+            });
+        }
+        Some(Selection::Light(i)) => {
+            ui.label(format!("Light {} selected, see Lighting below", i));
+        }
+        Some(Selection::Camera) => {
+            ui.label("Render camera selected, see Camera Controls");
+        }
+        None => {}
+    }
+
     ui.separator();
     ui.heading("Lighting");
 
+    let mut units = scene.light_units;
+    ui.horizontal(|ui| {
+        ui.label("Units:");
+        let legacy = ui.selectable_value(&mut units, LightUnits::Legacy, "Legacy")
+            .on_hover_text("Unitless intensities without distance falloff");
+        let radiometric = ui.selectable_value(&mut units, LightUnits::Radiometric, "Radiometric")
+            .on_hover_text("Watts for point and spot lights, W/m² for the sun, inverse-square falloff");
+        if legacy.changed() || radiometric.changed() {
+            // Converted so the middle of the scene stays as bright
+            scene.set_light_units(units);
+            *trigger_render = true;
+        }
+    });
+
     // Sun Control
+    let sky_drives_sun = matches!(scene.background, Background::Sky(_));
+    let mut sun_turned = false;
     if let Some(sun) = scene.lights.iter_mut().find(|l| l.light_type == LightType::Directional) {
         ui.label("Sun (Directional)");
         let mut sun_changed = false;
@@ -167,48 +432,55 @@ pub fn render_controls(
             sun_changed |= ui.add(egui::DragValue::new(&mut sun.direction.y).speed(0.1).prefix("Y: ")).changed();
             sun_changed |= ui.add(egui::DragValue::new(&mut sun.direction.z).speed(0.1).prefix("Z: ")).changed();
         });
-        if sun_changed {
+        if sun_changed && sun.direction.length_squared() > 0.0 {
             sun.direction = sun.direction.normalize();
+            sun_turned = true;
             *trigger_render = true;
         }
 
-        ui.horizontal(|ui| {
-            ui.label("Intensity:");
-            if ui.add(egui::Slider::new(&mut sun.intensity, 0.0..=5.0)).changed() {
-                *trigger_render = true;
-            }
-        });
-        
-        ui.horizontal(|ui| {
-            ui.label("Color:");
-            let mut rgb = [sun.color.x, sun.color.y, sun.color.z];
-            if ui.color_edit_button_rgb(&mut rgb).changed() {
-                sun.color = Vec3::from_array(rgb);
-                *trigger_render = true;
-            }
-        });
+        if sky_drives_sun {
+            ui.label("Intensity and color follow the sky");
+        } else {
+            ui.horizontal(|ui| {
+                ui.label("Intensity:");
+                if intensity_slider(ui, sun, units) {
+                    *trigger_render = true;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Color:");
+                let mut rgb = [sun.color.x, sun.color.y, sun.color.z];
+                if ui.color_edit_button_rgb(&mut rgb).changed() {
+                    sun.color = Vec3::from_array(rgb);
+                    *trigger_render = true;
+                }
+            });
+        }
+    }
+    if sun_turned {
+        scene.update_sun();
     }
 
     ui.separator();
-    ui.label("Point Lights");
+    ui.label("Point and Area Lights");
     
-    if ui.button("Add Point Light").clicked() {
-        scene.lights.push(Light {
-            light_type: LightType::Point,
-            position: Vec3::new(0.0, 2.0, 0.0),
-            direction: Vec3::ZERO,
-            color: Vec3::new(1.0, 1.0, 1.0),
-            intensity: 1.0,
-        });
-        *trigger_render = true;
-    }
+    ui.horizontal(|ui| {
+        ui.label("Add:");
+        for light_type in [LightType::Point, LightType::Spot, LightType::Rectangle, LightType::Disk, LightType::Sphere] {
+            if ui.button(format!("{:?}", light_type)).clicked() {
+                scene.lights.push(new_light(light_type, scene.light_units));
+                *trigger_render = true;
+            }
+        }
+    });
 
     let mut remove_index = None;
     for (i, light) in scene.lights.iter_mut().enumerate() {
-        if light.light_type == LightType::Point {
+        if light.light_type != LightType::Directional {
             ui.group(|ui| {
                 ui.horizontal(|ui| {
-                    ui.label(format!("Light {}", i));
+                    ui.label(format!("{:?} Light {}", light.light_type, i));
                     if ui.button("Remove").clicked() {
                         remove_index = Some(i);
                     }
@@ -222,9 +494,41 @@ pub fn render_controls(
                     light_changed |= ui.add(egui::DragValue::new(&mut light.position.z).speed(0.1).prefix("Z: ")).changed();
                 });
 
+                if matches!(light.light_type, LightType::Spot | LightType::Rectangle | LightType::Disk) {
+                    let mut dir_changed = false;
+                    ui.horizontal(|ui| {
+                        ui.label("Dir:");
+                        dir_changed |= ui.add(egui::DragValue::new(&mut light.direction.x).speed(0.1).prefix("X: ")).changed();
+                        dir_changed |= ui.add(egui::DragValue::new(&mut light.direction.y).speed(0.1).prefix("Y: ")).changed();
+                        dir_changed |= ui.add(egui::DragValue::new(&mut light.direction.z).speed(0.1).prefix("Z: ")).changed();
+                    });
+                    if dir_changed && light.direction.length_squared() > 0.0 {
+                        light.direction = light.direction.normalize();
+                        light_changed = true;
+                    }
+                }
+
+                ui.horizontal(|ui| match light.light_type {
+                    LightType::Rectangle => {
+                        ui.label("Size:");
+                        light_changed |= ui.add(egui::DragValue::new(&mut light.size.x).speed(0.05).range(0.01..=100.0).prefix("W: ")).changed();
+                        light_changed |= ui.add(egui::DragValue::new(&mut light.size.y).speed(0.05).range(0.01..=100.0).prefix("H: ")).changed();
+                    }
+                    LightType::Disk | LightType::Sphere => {
+                        ui.label("Radius:");
+                        light_changed |= ui.add(egui::DragValue::new(&mut light.size.x).speed(0.05).range(0.01..=100.0)).changed();
+                    }
+                    LightType::Spot => {
+                        ui.label("Cone:");
+                        light_changed |= ui.add(egui::DragValue::new(&mut light.inner_angle).speed(0.5).range(0.0..=light.outer_angle).prefix("Inner: ").suffix("°")).changed();
+                        light_changed |= ui.add(egui::DragValue::new(&mut light.outer_angle).speed(0.5).range(light.inner_angle..=90.0).prefix("Outer: ").suffix("°")).changed();
+                    }
+                    LightType::Point | LightType::Directional => {}
+                });
+
                 ui.horizontal(|ui| {
                     ui.label("Int:");
-                    light_changed |= ui.add(egui::Slider::new(&mut light.intensity, 0.0..=5.0)).changed();
+                    light_changed |= intensity_slider(ui, light, units);
                 });
 
                 ui.horizontal(|ui| {
@@ -245,9 +549,343 @@ pub fn render_controls(
 
     if let Some(index) = remove_index {
         scene.lights.remove(index);
+        // Later lights shift down, so a selected light index would now name another light
+        if matches!(ui_state.selected, Some(Selection::Light(_))) {
+            ui_state.selected = None;
+        }
         *trigger_render = true;
     }
+
+    ui.separator();
+    ui.heading("Background");
+
+    ui.horizontal(|ui| {
+        let is_color = matches!(scene.background, Background::Color(_));
+        if ui.selectable_label(is_color, "Color").clicked() && !is_color {
+            scene.background = Background::Color(Vec3::splat(0.5));
+            *trigger_render = true;
+        }
+        let is_gradient = matches!(scene.background, Background::Gradient);
+        if ui.selectable_label(is_gradient, "Gradient").clicked() && !is_gradient {
+            scene.background = Background::Gradient;
+            *trigger_render = true;
+        }
+        let is_sky = matches!(scene.background, Background::Sky(_));
+        if ui.selectable_label(is_sky, "Sky").on_hover_text("Physical sky lit by the sun").clicked() && !is_sky {
+            scene.background = Background::Sky(Sky::default());
+            if !scene.lights.iter().any(|light| light.light_type == LightType::Directional) {
+                let mut sun = new_light(LightType::Directional, scene.light_units);
+                sun.direction = -Sky::default().sun_direction();
+                scene.lights.push(sun);
+            }
+            scene.update_sun();
+            *trigger_render = true;
+        }
+        if ui.button("Load HDR...").on_hover_text("Equirectangular .hdr or .exr environment map").clicked() {
+            ui_state.scene_file_request = Some(SceneFileRequest::LoadEnvironmentMap);
+        }
+    });
+
+    let mut sky_changed = false;
+    match &mut scene.background {
+        Background::Color(color) => {
+            ui.horizontal(|ui| {
+                ui.label("Color:");
+                let mut rgb = color.to_array();
+                if ui.color_edit_button_rgb(&mut rgb).changed() {
+                    *color = Vec3::from_array(rgb);
+                    *trigger_render = true;
+                }
+            });
+        }
+        Background::Gradient => {}
+        Background::Sky(sky) => {
+            ui.horizontal(|ui| {
+                ui.label("Turbidity:").on_hover_text("Haziness, from a clear day to a hazy one");
+                let mut turbidity = sky.turbidity();
+                if ui.add(egui::Slider::new(&mut turbidity, MIN_TURBIDITY..=MAX_TURBIDITY)).changed() {
+                    sky.set_turbidity(turbidity);
+                    *trigger_render = true;
+                    sky_changed = true;
+                }
+            });
+        }
+        Background::EnvironmentMap(map) => {
+            let (width, height) = map.size();
+            ui.label(format!("{} ({}x{})", map.name, width, height));
+            ui.horizontal(|ui| {
+                ui.label("Rotation:");
+                *trigger_render |= ui.add(egui::Slider::new(&mut map.rotation, -180.0..=180.0).suffix("°")).changed();
+            });
+            ui.horizontal(|ui| {
+                ui.label("Intensity:");
+                *trigger_render |= ui.add(egui::Slider::new(&mut map.intensity, 0.0..=10.0).logarithmic(true)).changed();
+            });
+        }
+    }
+    if sky_changed {
+        // Haze dims and reddens the sunlight too
+        scene.update_sun();
+    }
+
+    ui.separator();
+    ui.heading("Scene File");
+
+    ui.horizontal(|ui| {
+        if ui.button("Load...").clicked() {
+            ui_state.scene_file_request = Some(SceneFileRequest::Load);
+        }
+        if ui.button("Save JSON...").clicked() {
+            ui_state.scene_file_request = Some(SceneFileRequest::Save(SceneFormat::Json));
+        }
+        if ui.button("Save RON...").clicked() {
+            ui_state.scene_file_request = Some(SceneFileRequest::Save(SceneFormat::Ron));
+        }
     });
+
+    if ui.button("Import OBJ...").clicked() {
+        ui_state.scene_file_request = Some(SceneFileRequest::ImportObj);
+    }
+
+    match &ui_state.scene_file_status {
+        Some(Ok(message)) => {
+            ui.label(message);
+        }
+        Some(Err(message)) => {
+            ui.colored_label(ui.visuals().error_fg_color, message);
+        }
+        None => {}
+    }
+    });
+}
+
+fn filter_combo(ui: &mut Ui, id: &str, filter: &mut FilterType) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(filter.name())
+        .show_ui(ui, |ui| {
+            for option in FilterType::ALL {
+                changed |= ui.selectable_value(filter, option, option.name()).changed();
+            }
+        });
+    changed
+}
+
+fn object_name(scene: &Scene, id: ObjectId) -> String {
+    match id {
+        ObjectId::Sphere(i) => format!("Sphere {}", i),
+        ObjectId::Cube(i) => format!("Cube {}", i),
+        ObjectId::Plane(i) => format!("Plane {}", i),
+        ObjectId::Mesh(i) => scene.meshes[i].name.clone(),
+    }
+}
+
+/// Geometry, transform and material of an object. Returns whether anything changed.
+fn object_properties(ui: &mut Ui, scene: &mut Scene, id: ObjectId) -> bool {
+    let mut changed = false;
+
+    let (transform, material) = match id {
+        ObjectId::Sphere(i) => {
+            let sphere = &mut scene.spheres[i];
+            ui.horizontal(|ui| {
+                ui.label("Radius:");
+                changed |= ui.add(egui::DragValue::new(&mut sphere.radius).speed(0.01).range(0.01..=100.0)).changed();
+            });
+            (&mut sphere.transform, &mut sphere.material)
+        }
+        ObjectId::Cube(i) => {
+            let cube = &mut scene.cubes[i];
+            // Edit the size around the current center
+            let center = (cube.min + cube.max) / 2.0;
+            let mut size = cube.max - cube.min;
+            let mut resized = false;
+            ui.horizontal(|ui| {
+                ui.label("Size:");
+                for (axis, prefix) in ["X: ", "Y: ", "Z: "].into_iter().enumerate() {
+                    resized |= ui.add(egui::DragValue::new(&mut size[axis]).speed(0.01).range(0.01..=100.0).prefix(prefix)).changed();
+                }
+            });
+            if resized {
+                cube.min = center - size / 2.0;
+                cube.max = center + size / 2.0;
+                changed = true;
+            }
+            (&mut cube.transform, &mut cube.material)
+        }
+        ObjectId::Plane(i) => {
+            let plane = &mut scene.planes[i];
+            let mut normal = plane.normal;
+            let mut turned = false;
+            ui.horizontal(|ui| {
+                ui.label("Normal:");
+                turned |= ui.add(egui::DragValue::new(&mut normal.x).speed(0.01).prefix("X: ")).changed();
+                turned |= ui.add(egui::DragValue::new(&mut normal.y).speed(0.01).prefix("Y: ")).changed();
+                turned |= ui.add(egui::DragValue::new(&mut normal.z).speed(0.01).prefix("Z: ")).changed();
+            });
+            // A zero normal has no direction, so that edit is dropped
+            if turned && normal.length_squared() > 0.0 {
+                plane.normal = normal.normalize();
+                changed = true;
+            }
+            (&mut plane.transform, &mut plane.material)
+        }
+        ObjectId::Mesh(i) => {
+            let mesh = &mut scene.meshes[i];
+            ui.label(format!("{} triangles", mesh.triangles.len()));
+            (&mut mesh.transform, &mut mesh.material)
+        }
+    };
+
+    changed |= transform_controls(ui, transform);
+    ui.separator();
+    changed |= material_controls(ui, material);
+    changed
+}
+
+fn material_controls(ui: &mut Ui, material: &mut Material) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Material:");
+        egui::ComboBox::from_id_salt("material_type")
+            .selected_text(format!("{:?}", material.mat_type))
+            .show_ui(ui, |ui| {
+                for mat_type in [MaterialType::Lambertian, MaterialType::Metal, MaterialType::Dielectric] {
+                    changed |= ui.selectable_value(&mut material.mat_type, mat_type, format!("{:?}", mat_type)).changed();
+                }
+                // Starts from parameters that look like the current type
+                let principled = material.mat_type == MaterialType::Principled;
+                if ui.selectable_label(principled, "Principled").clicked() && !principled {
+                    *material = material.to_principled();
+                    changed = true;
+                }
+            });
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Color:");
+        let mut rgb = material.color.to_array();
+        if ui.color_edit_button_rgb(&mut rgb).changed() {
+            material.color = Vec3::from_array(rgb);
+            changed = true;
+        }
+    });
+
+    changed |= ui.add(egui::Slider::new(&mut material.roughness, 0.0..=1.0).text("Roughness")).changed();
+    changed |= ui.add(egui::Slider::new(&mut material.ior, 1.0..=3.0).text("IOR")).changed();
+    changed |= ui.add(egui::Slider::new(&mut material.specular, 0.0..=1.0).text("Specular")).changed();
+    changed |= ui.add(egui::Slider::new(&mut material.shininess, 0.0..=256.0).logarithmic(true).text("Shininess")).changed();
+    changed |= ui
+        .add(egui::Slider::new(&mut material.reflectivity, 0.0..=1.0).text("Reflectivity"))
+        .on_hover_text("Mirror reflection in Raytracing mode")
+        .changed();
+
+    if material.mat_type == MaterialType::Principled {
+        let params = &mut material.principled;
+        changed |= ui.add(egui::Slider::new(&mut params.metallic, 0.0..=1.0).text("Metallic")).changed();
+        changed |= ui.add(egui::Slider::new(&mut params.transmission, 0.0..=1.0).text("Transmission")).changed();
+        changed |= ui.add(egui::Slider::new(&mut params.anisotropy, 0.0..=1.0).text("Anisotropy")).changed();
+        changed |= ui.add(egui::Slider::new(&mut params.sheen, 0.0..=1.0).text("Sheen")).changed();
+        changed |= ui.add(egui::Slider::new(&mut params.clearcoat, 0.0..=1.0).text("Clearcoat")).changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut params.clearcoat_roughness, 0.0..=1.0).text("Clearcoat roughness"))
+            .changed();
+    }
+
+    // Edited as a color and a strength, stored as their product
+    let mut strength = material.emission.max_element();
+    let mut rgb = if strength > 0.0 { material.emission / strength } else { Vec3::ONE }.to_array();
+    ui.horizontal(|ui| {
+        ui.label("Emission:");
+        let color_changed = ui.color_edit_button_rgb(&mut rgb).changed();
+        let strength_changed = ui.add(egui::DragValue::new(&mut strength).speed(0.1).range(0.0..=100.0)).changed();
+        if color_changed || strength_changed {
+            material.emission = Vec3::from_array(rgb) * strength;
+            changed = true;
+        }
+    })
+    .response
+    .on_hover_text("Makes the object glow");
+
+    changed
+}
+
+/// Intensity of `light`, with a range and unit to suit its type. Returns whether it changed.
+fn intensity_slider(ui: &mut Ui, light: &mut Light, units: LightUnits) -> bool {
+    let slider = match (units, light.light_type) {
+        // Area lights give the radiance of their surface in either units
+        (_, LightType::Rectangle | LightType::Disk | LightType::Sphere) => {
+            egui::Slider::new(&mut light.intensity, 0.0..=50.0).suffix(" W/(sr·m²)")
+        }
+        (LightUnits::Legacy, _) => egui::Slider::new(&mut light.intensity, 0.0..=5.0),
+        (LightUnits::Radiometric, LightType::Directional) => {
+            egui::Slider::new(&mut light.intensity, 0.0..=20.0).suffix(" W/m²")
+        }
+        (LightUnits::Radiometric, LightType::Point | LightType::Spot) => {
+            egui::Slider::new(&mut light.intensity, 0.0..=10000.0).logarithmic(true).suffix(" W")
+        }
+    };
+    ui.add(slider).changed()
+}
+
+/// A light of `light_type` above the middle of the scene, shining down.
+fn new_light(light_type: LightType, units: LightUnits) -> Light {
+    let (size, intensity) = match light_type {
+        LightType::Point | LightType::Directional => (Vec2::ZERO, 1.0),
+        LightType::Spot => (Vec2::ZERO, 2.0),
+        LightType::Rectangle => (Vec2::new(1.0, 1.0), 10.0),
+        LightType::Disk | LightType::Sphere => (Vec2::new(0.5, 0.0), 10.0),
+    };
+    let overhead = light_type == LightType::Spot || light_type.is_area();
+    let position = Vec3::new(0.0, if overhead { 4.0 } else { 2.0 }, 0.0);
+    Light {
+        light_type,
+        position,
+        direction: if overhead { Vec3::NEG_Y } else { Vec3::ZERO },
+        color: Vec3::new(1.0, 1.0, 1.0),
+        // As bright at the origin below in either units
+        intensity: intensity * units.scale(light_type, position.length()),
+        size,
+        inner_angle: 20.0,
+        outer_angle: 30.0,
+    }
+}
+
+/// Position, rotation (as XYZ Euler angles) and scale of an object. Returns whether anything changed.
+fn transform_controls(ui: &mut Ui, transform: &mut Transform) -> bool {
+    let mut changed = false;
+
+    ui.horizontal(|ui| {
+        ui.label("Pos:");
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.x).speed(0.1).prefix("X: ")).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.y).speed(0.1).prefix("Y: ")).changed();
+        changed |= ui.add(egui::DragValue::new(&mut transform.position.z).speed(0.1).prefix("Z: ")).changed();
+    });
+
+    let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+    let mut angles = [x, y, z].map(f32::to_degrees);
+    let mut rotated = false;
+    ui.horizontal(|ui| {
+        ui.label("Rot:");
+        for (angle, prefix) in angles.iter_mut().zip(["X: ", "Y: ", "Z: "]) {
+            rotated |= ui.add(egui::DragValue::new(angle).speed(1.0).prefix(prefix).suffix("°")).changed();
+        }
+    });
+    if rotated {
+        let [x, y, z] = angles.map(f32::to_radians);
+        transform.rotation = Quat::from_euler(EulerRot::XYZ, x, y, z);
+        changed = true;
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Scale:");
+        // Zero scale would flatten the object and break the inverse transform
+        for (scale, prefix) in [(&mut transform.scale.x, "X: "), (&mut transform.scale.y, "Y: "), (&mut transform.scale.z, "Z: ")] {
+            changed |= ui.add(egui::DragValue::new(scale).speed(0.01).range(0.01..=100.0).prefix(prefix)).changed();
+        }
+    });
+
+    changed
 }
 
 pub fn render_explanation(ui: &mut Ui, ui_state: &mut UiState) {
@@ -274,6 +912,13 @@ pub fn render_explanation(ui: &mut Ui, ui_state: &mut UiState) {
                 ui.label("• Shows the raytraced/pathtraced output");
                 ui.label("• Adjust camera position/rotation in the controls");
                 ui.label("• Click 'Reset View' to look at origin");
+                ui.label("• With 'Progressive' on, Pathtracing keeps refining the image");
+                ui.label("• The (GPU) modes render with a compute shader where supported");
+                ui.label("• 'Scene File' saves or loads the scene, cameras and settings");
+                ui.label("• 'Import OBJ' adds meshes (on the web, select the .mtl files too)");
+                ui.label("• Left-click an object in either view to edit it under 'Objects'");
+                ui.label("• Meshes, area lights, glowing or principled materials and");
+                ui.label("  environment maps render on the CPU, as shown under the image");
                 ui.add_space(5.0);
     
                 ui.label(egui::RichText::new("Ray Visualization").underline());
@@ -282,10 +927,11 @@ pub fn render_explanation(ui: &mut Ui, ui_state: &mut UiState) {
                 ui.label("• Cyan: Reflections (mirrors, metals)");
                 ui.label("• Magenta: Refractions (through glass)");
                 ui.label("• Light Blue: Diffuse scattering");
+                ui.label("• White: Shadow rays to the lights that reach a hit");
                 ui.add_space(5.0);
     
                 ui.label(egui::RichText::new("Lighting").underline());
-                ui.label("• Add/remove point lights dynamically");
+                ui.label("• Add/remove point and area lights dynamically");
                 ui.label("• Adjust light position, color, and intensity");
             }
             ExplanationTab::RaytracingVsPathtracing => {
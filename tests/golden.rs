@@ -0,0 +1,290 @@
+//! Golden-image regression tests: a few fixed scenes are rendered headlessly in both render
+//! modes at a fixed seed and compared against the reference images in `tests/golden`.
+//!
+//! Floating point results differ a little between platforms, so images only have to match
+//! within an RMSE tolerance. A mismatch writes the render and an amplified difference image to
+//! `target/tmp/golden`. After an intended change to the images, rerun with `UPDATE_GOLDEN=1`
+//! to write new references and check them in.
+
+use glam::{Quat, Vec2, Vec3};
+use interactive_wasm_raytracer::background::Background;
+use interactive_wasm_raytracer::camera::Camera;
+use interactive_wasm_raytracer::math::Transform;
+use interactive_wasm_raytracer::primitives::{Cube, Light, LightType, Material, MaterialType, Mesh, Plane, PrincipledParams, Sphere};
+use interactive_wasm_raytracer::raytracer::{RenderMode, Raytracer};
+use interactive_wasm_raytracer::scene::Scene;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+/// Largest root mean square difference of the color channels, from 0 to 1, that still passes.
+/// Every pixel sample has random numbers of its own, so should a platform round a random
+/// decision the other way, only that sample changes. Another seed changes these images by
+/// 0.007 to 0.03, so the tolerances sit well below noise and still catch shading that is off
+/// by a few percent, which `small_shading_changes_fail` checks.
+fn tolerance(mode: RenderMode) -> f32 {
+    match mode {
+        RenderMode::Raytracing => 0.004,
+        RenderMode::Pathtracing => 0.003,
+    }
+}
+
+const MODES: [(RenderMode, &str); 2] = [(RenderMode::Raytracing, "raytracing"), (RenderMode::Pathtracing, "pathtracing")];
+
+fn raytracer(mode: RenderMode) -> Raytracer {
+    Raytracer {
+        width: WIDTH,
+        height: HEIGHT,
+        mode,
+        max_bounces: 6,
+        samples_per_pixel: match mode {
+            RenderMode::Raytracing => 16,
+            RenderMode::Pathtracing => 128,
+        },
+        seed: 1,
+        ..Default::default()
+    }
+}
+
+fn camera(position: Vec3, target: Vec3) -> Camera {
+    Camera::new(position, target, 45.0, WIDTH as f32 / HEIGHT as f32)
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+fn load_png(path: &Path) -> Result<(u32, u32, Vec<u8>), String> {
+    let file = std::fs::File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| format!("{}: {}", path.display(), err))?;
+    let size = reader.output_buffer_size().ok_or_else(|| format!("{}: image too large", path.display()))?;
+    let mut buffer = vec![0; size];
+    let info = reader.next_frame(&mut buffer).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(format!("{}: expected 8-bit RGBA", path.display()));
+    }
+    buffer.truncate(info.buffer_size());
+    Ok((info.width, info.height, buffer))
+}
+
+/// Root mean square difference of the RGB channels, ignoring alpha.
+fn rmse(a: &[u8], b: &[u8]) -> f32 {
+    let sum: f32 = a
+        .chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .flat_map(|(a, b)| (0..3).map(move |i| (a[i] as f32 - b[i] as f32) / 255.0))
+        .map(|difference| difference * difference)
+        .sum();
+    (sum / (a.len() / 4 * 3) as f32).sqrt()
+}
+
+/// Per-pixel absolute difference, brightened 4x so small shifts show up.
+fn diff_image(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .flat_map(|(a, b)| {
+            let channel = |i: usize| (a[i].abs_diff(b[i]) as u32 * 4).min(255) as u8;
+            [channel(0), channel(1), channel(2), 255]
+        })
+        .collect()
+}
+
+/// Tone mapped RGBA8 render of `scene`.
+fn render(scene: &Scene, camera: &Camera, mode: RenderMode) -> Vec<u8> {
+    let raytracer = raytracer(mode);
+    raytracer.tone_mapping.image_to_rgba(&raytracer.render(scene, camera).pixels)
+}
+
+/// Renders `scene` in `mode` and compares it with the reference image `<name>.png`.
+fn check(name: &str, scene: &Scene, camera: &Camera, mode: RenderMode) -> Result<(), String> {
+    let pixels = render(scene, camera, mode);
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).map_err(|err| err.to_string())?;
+        return interactive_wasm_raytracer::image_file::save(&reference_path, WIDTH, HEIGHT, &pixels)
+            .map_err(|err| err.to_string());
+    }
+
+    let (width, height, reference) = load_png(&reference_path)?;
+    if (width, height) != (WIDTH, HEIGHT) {
+        return Err(format!("{}: reference is {}x{}, expected {}x{}", name, width, height, WIDTH, HEIGHT));
+    }
+    let error = rmse(&pixels, &reference);
+    if error <= tolerance(mode) {
+        return Ok(());
+    }
+
+    let save = |suffix: &str, rgba: &[u8]| {
+        let path = diff_dir().join(format!("{}{}.png", name, suffix));
+        interactive_wasm_raytracer::image_file::save(&path, WIDTH, HEIGHT, rgba).map(|()| path)
+    };
+    std::fs::create_dir_all(diff_dir()).map_err(|err| err.to_string())?;
+    save("", &pixels).map_err(|err| err.to_string())?;
+    let diff = save("-diff", &diff_image(&pixels, &reference)).map_err(|err| err.to_string())?;
+    Err(format!("{}: RMSE {:.4} above {}, see {}", name, error, tolerance(mode), diff.display()))
+}
+
+/// Checks `scene` in both render modes, reporting every mismatch before failing.
+fn check_both_modes(name: &str, mut scene: Scene, camera: Camera) {
+    scene.rebuild_bvh();
+    let errors: Vec<String> = MODES
+        .into_iter()
+        .filter_map(|(mode, suffix)| check(&format!("{}-{}", name, suffix), &scene, &camera, mode).err())
+        .collect();
+    assert!(errors.is_empty(), "{}", errors.join("\n"));
+}
+
+fn lambertian(color: Vec3) -> Material {
+    Material { color, roughness: 1.0, ..Material::default() }
+}
+
+fn floor() -> Plane {
+    Plane {
+        point: Vec3::ZERO,
+        normal: Vec3::Y,
+        material: lambertian(Vec3::splat(0.5)),
+        transform: Transform::default(),
+    }
+}
+
+fn light(light_type: LightType, position: Vec3, intensity: f32) -> Light {
+    Light {
+        light_type,
+        position,
+        direction: -Vec3::Y,
+        color: Vec3::ONE,
+        intensity,
+        size: Vec2::ZERO,
+        inner_angle: 0.0,
+        outer_angle: 0.0,
+    }
+}
+
+fn sphere(center: Vec3, radius: f32, material: Material) -> Sphere {
+    Sphere { center, radius, material, transform: Transform::default() }
+}
+
+#[test]
+fn default_scene() {
+    check_both_modes("default", Scene::default(), camera(Vec3::new(0.0, 2.5, 6.0), Vec3::ZERO));
+}
+
+/// One of each kind of object, transformed, under a point light.
+fn primitives_scene() -> (Scene, Camera) {
+    let mut scene = Scene::empty();
+    scene.background = Background::Gradient;
+    scene.planes.push(floor());
+    scene.spheres.push(sphere(Vec3::new(-1.3, 0.6, 0.0), 0.6, lambertian(Vec3::new(0.8, 0.2, 0.2))));
+    scene.cubes.push(Cube {
+        min: Vec3::splat(-0.5),
+        max: Vec3::splat(0.5),
+        material: lambertian(Vec3::new(0.2, 0.7, 0.3)),
+        transform: Transform {
+            position: Vec3::new(0.0, 0.5, -0.5),
+            rotation: Quat::from_rotation_y(0.6),
+            scale: Vec3::new(1.0, 1.0, 0.6),
+        },
+    });
+    let vertices = vec![Vec3::new(0.0, 1.2, 0.0), Vec3::new(-0.6, 0.0, 0.5), Vec3::new(0.6, 0.0, 0.5), Vec3::new(0.0, 0.0, -0.6)];
+    let triangles = vec![[0, 1, 2], [0, 2, 3], [0, 3, 1], [1, 3, 2]];
+    let mut pyramid = Mesh::new("pyramid".into(), vertices, Vec::new(), triangles, lambertian(Vec3::new(0.3, 0.4, 0.9)));
+    pyramid.transform.position = Vec3::new(1.4, 0.0, 0.2);
+    pyramid.transform.rotation = Quat::from_rotation_y(0.4);
+    scene.meshes.push(pyramid);
+    scene.lights.push(light(LightType::Point, Vec3::new(2.0, 4.0, 3.0), 600.0));
+    (scene, camera(Vec3::new(0.0, 2.0, 4.5), Vec3::new(0.0, 0.5, 0.0)))
+}
+
+#[test]
+fn primitives() {
+    let (scene, camera) = primitives_scene();
+    check_both_modes("primitives", scene, camera);
+}
+
+/// The tolerances are tight enough to catch surfaces reflecting 5% less light.
+#[test]
+fn small_shading_changes_fail() {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        return; // The references are being rewritten
+    }
+    let (mut scene, camera) = primitives_scene();
+    let materials = (scene.spheres.iter_mut().map(|sphere| &mut sphere.material))
+        .chain(scene.cubes.iter_mut().map(|cube| &mut cube.material))
+        .chain(scene.planes.iter_mut().map(|plane| &mut plane.material))
+        .chain(scene.meshes.iter_mut().map(|mesh| &mut mesh.material));
+    for material in materials {
+        material.color *= 0.95;
+    }
+    scene.rebuild_bvh();
+
+    for (mode, suffix) in MODES {
+        let (_, _, reference) = load_png(&golden_dir().join(format!("primitives-{}.png", suffix))).unwrap();
+        let error = rmse(&render(&scene, &camera, mode), &reference);
+        assert!(error > tolerance(mode), "{}: RMSE {:.4} within {}", suffix, error, tolerance(mode));
+    }
+}
+
+/// A row of reflective and transparent materials under a rectangle light.
+#[test]
+fn materials() {
+    let mut scene = Scene::empty();
+    scene.background = Background::Gradient;
+    scene.planes.push(floor());
+    let glass = Material { mat_type: MaterialType::Dielectric, roughness: 0.0, ior: 1.5, ..Material::default() };
+    let metal = |roughness| Material {
+        mat_type: MaterialType::Metal,
+        color: Vec3::new(0.9, 0.7, 0.4),
+        reflectivity: 0.8,
+        roughness,
+        ..Material::default()
+    };
+    let coated = Material {
+        mat_type: MaterialType::Principled,
+        color: Vec3::new(0.1, 0.3, 0.8),
+        roughness: 0.5,
+        principled: PrincipledParams { clearcoat: 1.0, clearcoat_roughness: 0.05, ..PrincipledParams::default() },
+        ..Material::default()
+    };
+    for (i, material) in [glass, metal(0.0), metal(0.3), coated].into_iter().enumerate() {
+        scene.spheres.push(sphere(Vec3::new(i as f32 * 1.1 - 1.65, 0.5, 0.0), 0.5, material));
+    }
+    let mut panel = light(LightType::Rectangle, Vec3::new(0.0, 3.0, 1.0), 8.0);
+    panel.size = Vec2::new(2.0, 1.0);
+    scene.lights.push(panel);
+    check_both_modes("materials", scene, camera(Vec3::new(0.0, 1.5, 4.5), Vec3::new(0.0, 0.4, 0.0)));
+}
+
+/// Each kind of light on its own patch of floor, in front of a dark background.
+#[test]
+fn lights() {
+    let mut scene = Scene::empty();
+    scene.background = Background::Color(Vec3::splat(0.02));
+    scene.planes.push(floor());
+    for x in [-1.5, 0.0, 1.5] {
+        scene.cubes.push(Cube {
+            min: Vec3::new(x - 0.25, 0.0, -0.25),
+            max: Vec3::new(x + 0.25, 0.6, 0.25),
+            material: lambertian(Vec3::splat(0.8)),
+            transform: Transform::default(),
+        });
+    }
+    let mut spot = light(LightType::Spot, Vec3::new(-1.5, 2.5, 0.5), 300.0);
+    spot.inner_angle = 20.0;
+    spot.outer_angle = 35.0;
+    let mut disk = light(LightType::Disk, Vec3::new(0.0, 2.0, 0.5), 20.0);
+    disk.size = Vec2::new(0.4, 0.0);
+    let mut bulb = light(LightType::Sphere, Vec3::new(1.5, 1.2, 0.8), 10.0);
+    bulb.size = Vec2::new(0.15, 0.0);
+    let mut sun = light(LightType::Directional, Vec3::ZERO, 1.0);
+    sun.direction = Vec3::new(-0.3, -1.0, -0.5);
+    scene.lights.extend([spot, disk, bulb, sun]);
+    check_both_modes("lights", scene, camera(Vec3::new(0.0, 3.0, 4.5), Vec3::new(0.0, 0.3, 0.0)));
+}
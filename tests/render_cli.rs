@@ -0,0 +1,48 @@
+//! Runs the headless `render` binary the way the documentation shows.
+
+use interactive_wasm_raytracer::camera::Camera;
+use interactive_wasm_raytracer::raytracer::Raytracer;
+use interactive_wasm_raytracer::scene::Scene;
+use interactive_wasm_raytracer::scene_file::{SceneFile, SceneFormat};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn temp_path(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("render_cli");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn render(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_render")).args(args).output().unwrap()
+}
+
+#[test]
+fn renders_a_scene_file() {
+    let raytracer = Raytracer { width: 16, height: 12, ..Default::default() };
+    let camera = Camera::new(glam::Vec3::new(0.0, 2.5, 6.0), glam::Vec3::ZERO, 45.0, 16.0 / 12.0);
+    let file = SceneFile::new(&Scene::default(), &camera, &camera, &raytracer);
+    let scene = temp_path("scene.json");
+    std::fs::write(&scene, file.encode(SceneFormat::Json).unwrap()).unwrap();
+    let image = temp_path("scene.png");
+    let _ = std::fs::remove_file(&image);
+
+    let output = render(&[scene.to_str().unwrap(), "-o", image.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::metadata(&image).unwrap().len() > 0);
+}
+
+/// Mistakes get a readable message and a failing exit status, 2 for bad arguments and 1 for
+/// anything that goes wrong after.
+#[test]
+fn reports_errors() {
+    let scene = temp_path("broken.json");
+    std::fs::write(&scene, "{ \"version\": ").unwrap();
+    let output = render(&[scene.to_str().unwrap(), "-o", temp_path("broken.png").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: "));
+
+    let output = render(&[scene.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Missing output image"));
+}